@@ -95,6 +95,7 @@
 					shellHook = ''
 						export VAULT_PORT="3000"
 						export VAULT_AUTH_KEY="dev_data/auth.key"
+						export VAULT_PEPPER="dev_data/pepper.key"
 						export VAULT_DB_FILE="dev_data/vault.db"
 						export VAULT_FILES_LOCATION="dev_data/files"
 						export CACHE_BUST_SKIP_HASHING="1"
@@ -1,10 +1,8 @@
-use std::time::Duration;
-
 use leptos::{server, use_context, ServerFnError};
 use serde::{Deserialize, Serialize};
 use thiserror::Error;
 
-use crate::vault::{Cipher, FolderName, PasswordHash, Salt};
+use crate::{files::FolderEntry, vault::{AuthKey, Cipher, KdfParameters, KeyRing, PasswordHash, Salt}};
 
 #[allow(unused)]
 use crate::db;
@@ -12,69 +10,893 @@ use crate::db;
 mod auth;
 pub use auth::*;
 
+mod registration;
+pub use registration::*;
+
+#[cfg(feature = "ssr")]
+mod rate_limit;
+#[cfg(feature = "ssr")]
+pub use rate_limit::*;
+
+#[cfg(feature = "ssr")]
+mod totp;
+#[cfg(feature = "ssr")]
+pub use totp::*;
+
+/// Returned when logging in, the auth token is set as a cookie instead.
 #[derive(Clone, Serialize, Deserialize, Debug)]
 pub struct LoginData {
-	pub auth: Auth,
-	pub folder_names: Vec<Cipher<FolderName>>,
+	pub key_ring: Cipher<KeyRing>,
+	pub folders: Vec<FolderEntry>,
+	/// Failed attempts to log in since the user last logged in, to warn them about someone guessing their password.
+	pub failed_logins: u32,
 }
 
+/// Unknown users can't be told apart from incorrect passwords, so usernames can't be enumerated.
 #[derive(Clone, Serialize, Deserialize, Error, Debug)]
 pub enum LoginError {
-	#[error("Unknown user")]
-	UnknownUser,
-	#[error("Incorrect password")]
+	#[error("Incorrect username or password")]
 	IncorrectPassword,
+	#[error("Too many failed attempts, try again in {0} seconds")]
+	TooManyAttempts(u64),
+	/// The password was correct, but the account has two-factor authentication enabled and no code was sent.
+	#[error("Enter a code from your authenticator app")]
+	TotpRequired,
+	#[error("Incorrect code")]
+	IncorrectTotpCode,
+	#[error("This account has been disabled")]
+	AccountDisabled,
+}
+
+/// What the client needs to derive its keys before logging in.
+#[derive(Clone, Serialize, Deserialize, Debug)]
+pub struct LoginParameters {
+	pub salt: Salt,
+	pub kdf: KdfParameters,
+	/// The parameters the server is configured with, the client upgrades to them after logging in if they differ from `kdf`.
+	pub new_kdf: KdfParameters,
+	/// The account still has a legacy password hash, which has to be sent along to upgrade it.
+	pub legacy: bool,
+	/// Accounts from before key rings need a new one sent along.
+	pub has_key_ring: bool,
+}
+
+/// What the client derives from a new password for the server to store.
+#[derive(Clone, Serialize, Deserialize, Debug)]
+pub struct NewPassword {
+	pub salt: Salt,
+	pub kdf: KdfParameters,
+	pub auth_key: AuthKey,
+	pub key_ring: Cipher<KeyRing>,
+}
+
+#[cfg(feature = "ssr")]
+impl NewPassword {
+	/// Rejects keys that weren't derived with the parameters the server is configured with,
+	/// so clients can't choose weaker ones.
+	fn validate(&self) -> Result<(), ServerFnError> {
+		let kdf: KdfParameters = use_context().unwrap();
+		
+		if self.kdf != kdf {
+			return Err(ServerFnError::Args("Keys must be derived with the configured parameters".to_owned()));
+		}
+		
+		Ok(())
+	}
+	
+	fn credentials<'a>(&'a self, auth_key_hash: &'a crate::vault::AuthKeyHash) -> db::Credentials<'a> {
+		db::Credentials {
+			salt: &self.salt,
+			kdf: self.kdf,
+			auth_key_hash,
+			key_ring: &self.key_ring,
+		}
+	}
+}
+
+/// Starts a new session for the user and sends a token referencing it with the response as a cookie.
+#[cfg(feature = "ssr")]
+fn start_session(username: String) -> Result<(), ServerFnError> {
+	use std::time::SystemTime;
+	
+	let db = db::use_db();
+	let authenticator: Authenticator = use_context().unwrap();
+	let response: leptos_axum::ResponseOptions = use_context().unwrap();
+	let request: http::request::Parts = use_context().unwrap();
+	
+	let user_agent = request.headers.get(http::header::USER_AGENT)
+		.and_then(|user_agent| user_agent.to_str().ok());
+	
+	db.delete_expired_sessions(SystemTime::now() - authenticator.session_lifetime())?;
+	
+	let session = SessionId::generate()?;
+	db.insert_session(session, &username, user_agent)?;
+	
+	let auth = authenticator.sign(username, session);
+	response.append_header(http::header::SET_COOKIE, auth.to_cookie());
+	
+	Ok(())
+}
+
+/// Starts a rate limited attempt to guess a secret of the user, or returns the seconds until one is allowed.
+#[cfg(feature = "ssr")]
+fn start_attempt(username: &str) -> Result<Attempt, u64> {
+	let rate_limiter: RateLimiter = use_context().unwrap();
+	
+	rate_limiter.attempt(username)
+		.map_err(|delay| delay.as_millis().div_ceil(1000) as u64)
+}
+
+/// Counts a failed attempt both for rate limiting and to warn the user the next time they log in.
+#[cfg(feature = "ssr")]
+fn fail_attempt(db: &db::Database, attempt: Attempt, username: &str) -> Result<(), ServerFnError> {
+	attempt.failed();
+	db.record_failed_login(username)?;
+	
+	Ok(())
+}
+
+/// Checks a TOTP code or one of the backup codes of a user with two-factor authentication enabled,
+/// either can only be used once.
+#[cfg(feature = "ssr")]
+fn verify_second_factor(db: &db::Database, username: &str, secret: &TotpSecret, code: &str) -> Result<bool, ServerFnError> {
+	use std::time::SystemTime;
+	use crate::vault::Pepper;
+	
+	if let Some(step) = secret.verify(code, SystemTime::now()) {
+		return Ok(db.use_totp_step(username, step)?);
+	}
+	
+	let Some(code) = normalize_backup_code(code) else {
+		return Ok(false);
+	};
+	
+	let pepper: Pepper = use_context().unwrap();
+	
+	Ok(db.use_backup_code(username, &pepper.backup_code_hash(&code))?)
 }
 
+/// Unknown users pose as an existing user picked by their username, so their parameters stay the same
+/// and look like those of a real account.
+#[cfg(feature = "ssr")]
+fn decoy_login_parameters(db: &db::Database, pepper: &crate::vault::Pepper, username: &str, kdf: KdfParameters) -> Result<LoginParameters, db::Error> {
+	db.get_decoy_login_parameters(pepper.fake_salt(username), pepper.decoy_index(username), kdf)
+}
+
+/// Returns made up parameters for unknown users that can't be told apart from those of an existing user.
 #[server]
-pub async fn get_user_salt(username: String) -> Result<Option<Salt>, ServerFnError> {
+pub async fn get_login_parameters(username: String) -> Result<Result<LoginParameters, LoginError>, ServerFnError> {
+	use crate::vault::Pepper;
+	
 	let db = db::use_db();
+	let kdf: KdfParameters = use_context().unwrap();
+	let pepper: Pepper = use_context().unwrap();
 	
-	Ok(db.get_salt(&username)?)
+	if let Err(retry_after) = start_attempt(&username) {
+		return Ok(Err(LoginError::TooManyAttempts(retry_after)));
+	}
+	
+	let parameters = match db.get_login_parameters(&username, kdf)? {
+		Some(parameters) => parameters,
+		None => decoy_login_parameters(&db, &pepper, &username, kdf)?,
+	};
+	
+	Ok(Ok(parameters))
 }
 
+/// The parameters keys of new passwords should be derived with.
 #[server]
-pub async fn login(username: String, hash: PasswordHash) -> Result<Result<LoginData, LoginError>, ServerFnError> {
+pub async fn get_kdf_parameters() -> Result<KdfParameters, ServerFnError> {
+	Ok(use_context().unwrap())
+}
+
+/// Accounts with two-factor authentication need `totp_code` as well, either a TOTP or a backup code.
+#[server]
+pub async fn login(username: String, auth_key: AuthKey, legacy_hash: Option<PasswordHash>, new_key_ring: Option<Cipher<KeyRing>>, totp_code: Option<String>) -> Result<Result<LoginData, LoginError>, ServerFnError> {
+	use crate::vault::{Pepper, Verification};
+	use tokio::task::spawn_blocking;
+	
 	let db = db::use_db();
+	let pepper: Pepper = use_context().unwrap();
 	
-	let Some(correct_hash) = db.get_password_hash(&username)? else {
-		return Ok(Err(LoginError::UnknownUser));
+	let attempt = match start_attempt(&username) {
+		Ok(attempt) => attempt,
+		Err(retry_after) => return Ok(Err(LoginError::TooManyAttempts(retry_after))),
 	};
 	
-	if hash != correct_hash {
+	let Some(verifier) = db.get_verifier(&username)? else {
+		let kdf: KdfParameters = use_context().unwrap();
+		
+		// takes as long as checking the password of the user posed as, so the response time doesn't tell
+		// that the user doesn't exist, legacy password hashes are compared without hashing
+		if !decoy_login_parameters(&db, &pepper, &username, kdf)?.legacy {
+			spawn_blocking(move || pepper.hash(&auth_key)).await??;
+		}
+		
+		attempt.failed();
 		return Ok(Err(LoginError::IncorrectPassword));
+	};
+	
+	let verification = {
+		let pepper = pepper.clone();
+		let auth_key = auth_key.clone();
+		spawn_blocking(move || pepper.verify(&verifier, &auth_key, legacy_hash.as_ref())).await?
+	};
+	
+	if verification == Verification::Incorrect {
+		fail_attempt(&db, attempt, &username)?;
+		return Ok(Err(LoginError::IncorrectPassword));
+	}
+	
+	// only told once the password is correct, so it doesn't tell anyone else that the user exists
+	if db.is_user_disabled(&username)? {
+		return Ok(Err(LoginError::AccountDisabled));
 	}
 	
-	let folder_names = db.get_folders(&username)?;
+	if let Some(secret) = db.get_totp_secret(&username)? {
+		// not a failed attempt, the password was correct
+		let Some(totp_code) = totp_code else {
+			return Ok(Err(LoginError::TotpRequired));
+		};
+		
+		if !verify_second_factor(&db, &username, &secret, &totp_code)? {
+			fail_attempt(&db, attempt, &username)?;
+			return Ok(Err(LoginError::IncorrectTotpCode));
+		}
+	}
 	
-	let authenticator: Authenticator = use_context().unwrap();
+	attempt.succeeded();
+	
+	if verification == Verification::Outdated {
+		let hash = spawn_blocking(move || pepper.hash(&auth_key)).await??;
+		db.set_auth_key_hash(&username, &hash)?;
+	}
+	
+	if let Some(key_ring) = new_key_ring {
+		db.init_key_ring(&username, &key_ring)?;
+	}
+	
+	let Some(key_ring) = db.get_key_ring(&username)? else {
+		eprintln!("User {username} logged in without a key ring");
+		return Err(ServerFnError::ServerError("Server Error".to_owned()));
+	};
+	
+	let folders = db.get_folders(&username)?;
+	let failed_logins = db.take_failed_logins(&username)?;
+	
+	start_session(username)?;
 	
 	Ok(Ok(LoginData {
-		auth: authenticator.sign(username, Duration::from_days(1)),
-		folder_names,
+		key_ring,
+		folders,
+		failed_logins,
 	}))
 }
 
+/// Second copy of the key ring wrapped with the keys derived from a recovery code.
+#[derive(Clone, Serialize, Deserialize, Debug)]
+pub struct Recovery {
+	pub auth_key: AuthKey,
+	pub key_ring: Cipher<KeyRing>,
+}
+
 #[derive(Clone, Serialize, Deserialize, Error, Debug)]
 pub enum CreateAccountError {
-	#[error("Unknown user")]
+	#[error("Username is already taken")]
 	UsernameTaken,
+	#[error("Registration is disabled")]
+	RegistrationDisabled,
+	#[error("An invite code is required")]
+	InviteRequired,
+	#[error("Invalid or expired invite code")]
+	InvalidInvite,
 }
 
+/// Who can create accounts, so the client knows whether to ask for an invite code.
 #[server]
-pub async fn create_account(username: String, salt: Salt, hash: PasswordHash) -> Result<Result<LoginData, CreateAccountError>, ServerFnError> {
+pub async fn get_registration_mode() -> Result<RegistrationMode, ServerFnError> {
+	Ok(use_context().unwrap())
+}
+
+/// `invite_code` is only needed while registration is [RegistrationMode::Invite], the invite is used up by creating the account.
+#[server]
+pub async fn create_account(username: String, password: NewPassword, recovery: Option<Recovery>, invite_code: Option<String>) -> Result<Result<LoginData, CreateAccountError>, ServerFnError> {
 	let mut db = db::use_db();
+	let registration_mode: RegistrationMode = use_context().unwrap();
+	
+	let invite = match registration_mode {
+		RegistrationMode::Open => None,
+		RegistrationMode::Disabled => return Ok(Err(CreateAccountError::RegistrationDisabled)),
+		RegistrationMode::Invite => {
+			let Some(invite_code) = invite_code.filter(|code| !code.trim().is_empty()) else {
+				return Ok(Err(CreateAccountError::InviteRequired));
+			};
+			
+			// Checked before anything else so that without a valid invite neither usernames can be probed
+			// nor password hashing triggered, using it up happens atomically with creating the user
+			let Some(code_hash) = invite_code_hash(&invite_code) else {
+				return Ok(Err(CreateAccountError::InvalidInvite));
+			};
+			
+			if !db.is_invite_valid(&code_hash)? {
+				return Ok(Err(CreateAccountError::InvalidInvite));
+			}
+			
+			Some(code_hash)
+		},
+	};
 	
-	if db.is_user(&username)? {
+	password.validate()?;
+	
+	// Admin rights only go by the username, so configured admin names can't be registered,
+	// otherwise anyone could become an admin after an admin account was deleted
+	let admins: Admins = use_context().unwrap();
+	
+	if admins.contains(&username) || db.is_user(&username)? {
 		return Ok(Err(CreateAccountError::UsernameTaken));
 	}
 	
-	db.insert_user(&username, salt, hash)?;
+	let pepper: crate::vault::Pepper = use_context().unwrap();
+	let auth_key = password.auth_key.clone();
+	let (hash, recovery) = tokio::task::spawn_blocking(move || -> Result<_, getrandom::Error> {
+		let hash = pepper.hash(&auth_key)?;
+		let recovery = match recovery {
+			Some(recovery) => Some((pepper.hash(&recovery.auth_key)?, recovery.key_ring)),
+			None => None,
+		};
+		
+		Ok((hash, recovery))
+	}).await??;
 	
-	let authenticator: Authenticator = use_context().unwrap();
+	if !db.insert_user(&username, &password.credentials(&hash), recovery.as_ref().map(|(hash, key_ring)| (hash, key_ring)), invite.as_ref())? {
+		return Ok(Err(CreateAccountError::InvalidInvite));
+	}
+	
+	start_session(username)?;
+	
+	Ok(Ok(LoginData {
+		key_ring: password.key_ring,
+		folders: Vec::new(),
+		failed_logins: 0,
+	}))
+}
+
+#[derive(Clone, Serialize, Deserialize, Error, Debug)]
+pub enum ChangePasswordError {
+	#[error("Not authenticated")]
+	NotAuthenticated,
+	#[error("Incorrect password")]
+	IncorrectPassword,
+	#[error("Too many failed attempts, try again in {0} seconds")]
+	TooManyAttempts(u64),
+}
+
+/// Checks the current password and replaces everything derived from it, returns the current session.
+/// `legacy_hash` is only needed for users whose verifier is still the legacy password hash, like for [login].
+#[cfg(feature = "ssr")]
+async fn replace_password(auth_key: AuthKey, legacy_hash: Option<PasswordHash>, new_password: &NewPassword) -> Result<Result<Session, ChangePasswordError>, ServerFnError> {
+	use crate::vault::{Pepper, Verification};
+	use tokio::task::spawn_blocking;
+	
+	new_password.validate()?;
+	
+	let Ok(session) = current_session().await else {
+		return Ok(Err(ChangePasswordError::NotAuthenticated));
+	};
+	
+	let db = db::use_db();
+	let pepper: Pepper = use_context().unwrap();
+	
+	let Some(verifier) = db.get_verifier(&session.username)? else {
+		return Ok(Err(ChangePasswordError::NotAuthenticated));
+	};
+	
+	let attempt = match start_attempt(&session.username) {
+		Ok(attempt) => attempt,
+		Err(retry_after) => return Ok(Err(ChangePasswordError::TooManyAttempts(retry_after))),
+	};
+	
+	let verification = {
+		let pepper = pepper.clone();
+		spawn_blocking(move || pepper.verify(&verifier, &auth_key, legacy_hash.as_ref())).await?
+	};
+	
+	if verification == Verification::Incorrect {
+		fail_attempt(&db, attempt, &session.username)?;
+		return Ok(Err(ChangePasswordError::IncorrectPassword));
+	}
+	
+	attempt.succeeded();
+	
+	let new_auth_key = new_password.auth_key.clone();
+	let hash = spawn_blocking(move || pepper.hash(&new_auth_key)).await??;
+	db.change_password(&session.username, &new_password.credentials(&hash))?;
+	
+	Ok(Ok(session))
+}
+
+/// Replaces the salt, login credential and wrapped key ring of a user after checking the current password.
+/// Signs out all other sessions.
+#[server]
+pub async fn change_password(auth_key: AuthKey, legacy_hash: Option<PasswordHash>, new_password: NewPassword) -> Result<Result<(), ChangePasswordError>, ServerFnError> {
+	let session = match replace_password(auth_key, legacy_hash, &new_password).await? {
+		Ok(session) => session,
+		Err(err) => return Ok(Err(err)),
+	};
+	
+	db::use_db().delete_sessions(&session.username, Some(session.id))?;
+	
+	Ok(Ok(()))
+}
+
+/// Derives the keys of the same password with new parameters, other sessions stay signed in.
+/// Only done right after logging in, which already replaced a legacy verifier.
+/// Only accepted while the configured parameters are stronger than the ones the keys of the user were derived with.
+#[server]
+pub async fn upgrade_kdf(auth_key: AuthKey, new_password: NewPassword) -> Result<Result<(), ChangePasswordError>, ServerFnError> {
+	let Ok(username) = authenticate().await else {
+		return Ok(Err(ChangePasswordError::NotAuthenticated));
+	};
+	
+	let kdf: KdfParameters = use_context().unwrap();
+	
+	let Some(parameters) = db::use_db().get_login_parameters(&username, kdf)? else {
+		return Ok(Err(ChangePasswordError::NotAuthenticated));
+	};
+	
+	if !kdf.is_stronger_than(&parameters.kdf) {
+		return Err(ServerFnError::Args("The key derivation parameters are already up to date".to_owned()));
+	}
+	
+	Ok(replace_password(auth_key, None, &new_password).await?.map(|_| ()))
+}
+
+/// Unknown users and users without a recovery code can't be told apart from incorrect codes, so usernames can't be enumerated.
+#[derive(Clone, Serialize, Deserialize, Error, Debug)]
+pub enum RecoverAccountError {
+	#[error("Incorrect username or recovery code")]
+	IncorrectRecoveryCode,
+	#[error("Too many failed attempts, try again in {0} seconds")]
+	TooManyAttempts(u64),
+	#[error("This account has been disabled")]
+	AccountDisabled,
+	/// The recovery code was correct, but the account has two-factor authentication enabled and no code was sent.
+	#[error("Enter a code from your authenticator app")]
+	TotpRequired,
+	#[error("Incorrect code")]
+	IncorrectTotpCode,
+}
+
+/// Checks the auth key derived from a recovery code and returns the key ring wrapped with it,
+/// with the attempt, which is up to the caller to count as succeeded.
+#[cfg(feature = "ssr")]
+async fn verify_recovery(db: &db::Database, username: &str, recovery_auth_key: AuthKey) -> Result<Result<(Cipher<KeyRing>, Attempt), RecoverAccountError>, ServerFnError> {
+	use crate::vault::{Pepper, Verification, Verifier};
+	use tokio::task::spawn_blocking;
+	
+	let pepper: Pepper = use_context().unwrap();
+	
+	let attempt = match start_attempt(username) {
+		Ok(attempt) => attempt,
+		Err(retry_after) => return Ok(Err(RecoverAccountError::TooManyAttempts(retry_after))),
+	};
+	
+	let Some((hash, key_ring)) = db.get_recovery(username)? else {
+		// takes as long as checking a code, so the response time doesn't tell whether the user exists
+		spawn_blocking(move || pepper.hash(&recovery_auth_key)).await??;
+		fail_attempt(db, attempt, username)?;
+		return Ok(Err(RecoverAccountError::IncorrectRecoveryCode));
+	};
+	
+	let verification = spawn_blocking(move || pepper.verify(&Verifier::AuthKey(hash), &recovery_auth_key, None)).await?;
+	
+	if verification == Verification::Incorrect {
+		fail_attempt(db, attempt, username)?;
+		return Ok(Err(RecoverAccountError::IncorrectRecoveryCode));
+	}
+	
+	if db.is_user_disabled(username)? {
+		return Ok(Err(RecoverAccountError::AccountDisabled));
+	}
+	
+	Ok(Ok((key_ring, attempt)))
+}
+
+/// Returns the key ring wrapped with the recovery code, so the client can wrap it again with a new password.
+#[server]
+pub async fn get_recovery_key_ring(username: String, recovery_auth_key: AuthKey) -> Result<Result<Cipher<KeyRing>, RecoverAccountError>, ServerFnError> {
+	let db = db::use_db();
+	
+	Ok(verify_recovery(&db, &username, recovery_auth_key).await?.map(|(key_ring, attempt)| {
+		attempt.succeeded();
+		key_ring
+	}))
+}
+
+/// Sets a new password using the recovery code instead of the current password, and signs in.
+/// The recovery code stays valid, since the master key it wraps doesn't change.
+/// It doesn't replace the second factor, accounts with two-factor authentication need `totp_code` as well, like for [login].
+#[server]
+pub async fn recover_account(username: String, recovery_auth_key: AuthKey, new_password: NewPassword, totp_code: Option<String>) -> Result<Result<LoginData, RecoverAccountError>, ServerFnError> {
+	let db = db::use_db();
+	
+	new_password.validate()?;
+	
+	let attempt = match verify_recovery(&db, &username, recovery_auth_key).await? {
+		Ok((_, attempt)) => attempt,
+		Err(err) => return Ok(Err(err)),
+	};
+	
+	if let Some(secret) = db.get_totp_secret(&username)? {
+		let Some(totp_code) = totp_code else {
+			return Ok(Err(RecoverAccountError::TotpRequired));
+		};
+		
+		if !verify_second_factor(&db, &username, &secret, &totp_code)? {
+			fail_attempt(&db, attempt, &username)?;
+			return Ok(Err(RecoverAccountError::IncorrectTotpCode));
+		}
+	}
+	
+	attempt.succeeded();
+	
+	let pepper: crate::vault::Pepper = use_context().unwrap();
+	let new_auth_key = new_password.auth_key.clone();
+	let hash = tokio::task::spawn_blocking(move || pepper.hash(&new_auth_key)).await??;
+	db.change_password(&username, &new_password.credentials(&hash))?;
+	db.delete_sessions(&username, None)?;
+	
+	let folders = db.get_folders(&username)?;
+	let failed_logins = db.take_failed_logins(&username)?;
+	
+	start_session(username)?;
 	
 	Ok(Ok(LoginData {
-		auth: authenticator.sign(username, Duration::from_days(1)),
-		folder_names: Vec::new(),
+		key_ring: new_password.key_ring,
+		folders,
+		failed_logins,
+	}))
+}
+
+#[derive(Clone, Serialize, Deserialize, Error, Debug)]
+pub enum DeleteAccountError {
+	#[error("Not authenticated")]
+	NotAuthenticated,
+	#[error("Incorrect password")]
+	IncorrectPassword,
+	#[error("Enter a code from your authenticator app")]
+	TotpRequired,
+	#[error("Incorrect code")]
+	IncorrectTotpCode,
+	#[error("Too many failed attempts, try again in {0} seconds")]
+	TooManyAttempts(u64),
+}
+
+/// Permanently deletes the account with all folders and files after checking the password, and the second factor if it is enabled.
+/// Deleting the user deletes their sessions, so all of their tokens stop working.
+#[server]
+pub async fn delete_account(auth_key: AuthKey, legacy_hash: Option<PasswordHash>, totp_code: Option<String>) -> Result<Result<(), DeleteAccountError>, ServerFnError> {
+	use std::path::PathBuf;
+	use crate::{files::remove_blob, vault::{Pepper, Verification}};
+	use tokio::task::spawn_blocking;
+	
+	let Ok(username) = authenticate().await else {
+		return Ok(Err(DeleteAccountError::NotAuthenticated));
+	};
+	
+	let db = db::use_db();
+	let pepper: Pepper = use_context().unwrap();
+	let files_location: PathBuf = use_context().unwrap();
+	let response: leptos_axum::ResponseOptions = use_context().unwrap();
+	
+	let Some(verifier) = db.get_verifier(&username)? else {
+		return Ok(Err(DeleteAccountError::NotAuthenticated));
+	};
+	
+	let attempt = match start_attempt(&username) {
+		Ok(attempt) => attempt,
+		Err(retry_after) => return Ok(Err(DeleteAccountError::TooManyAttempts(retry_after))),
+	};
+	
+	let verification = spawn_blocking(move || pepper.verify(&verifier, &auth_key, legacy_hash.as_ref())).await?;
+	
+	if verification == Verification::Incorrect {
+		fail_attempt(&db, attempt, &username)?;
+		return Ok(Err(DeleteAccountError::IncorrectPassword));
+	}
+	
+	if let Some(secret) = db.get_totp_secret(&username)? {
+		let Some(totp_code) = totp_code else {
+			return Ok(Err(DeleteAccountError::TotpRequired));
+		};
+		
+		if !verify_second_factor(&db, &username, &secret, &totp_code)? {
+			fail_attempt(&db, attempt, &username)?;
+			return Ok(Err(DeleteAccountError::IncorrectTotpCode));
+		}
+	}
+	
+	attempt.succeeded();
+	
+	db.delete_user(&username, |blob_id| remove_blob(&files_location, blob_id))?;
+	response.append_header(http::header::SET_COOKIE, Auth::clear_cookie());
+	
+	Ok(Ok(()))
+}
+
+/// A session the user is signed in with, times are unix timestamps in seconds.
+#[derive(Clone, Serialize, Deserialize, Debug)]
+pub struct SessionInfo {
+	pub created_at: i64,
+	pub last_seen_at: i64,
+	pub user_agent: Option<String>,
+	/// Whether this is the session the request was made in.
+	pub current: bool,
+}
+
+/// Ends the current session and removes the auth cookie, succeeds even if the session has already ended.
+#[server]
+pub async fn logout() -> Result<(), ServerFnError> {
+	let response: leptos_axum::ResponseOptions = use_context().unwrap();
+	
+	if let Ok(session) = current_session().await {
+		db::use_db().delete_session(session.id, &session.username)?;
+	}
+	
+	response.append_header(http::header::SET_COOKIE, Auth::clear_cookie());
+	
+	Ok(())
+}
+
+#[server]
+pub async fn get_sessions() -> Result<Result<Vec<SessionInfo>, AuthError>, ServerFnError> {
+	use std::time::SystemTime;
+	
+	let authenticator: Authenticator = use_context().unwrap();
+	
+	let Ok(session) = current_session().await else {
+		return Ok(Err(AuthError));
+	};
+	
+	Ok(Ok(db::use_db().get_sessions(&session.username, session.id, SystemTime::now() - authenticator.session_lifetime())?))
+}
+
+/// Signs out every session of the user except the current one.
+#[server]
+pub async fn sign_out_other_sessions() -> Result<Result<(), AuthError>, ServerFnError> {
+	let Ok(session) = current_session().await else {
+		return Ok(Err(AuthError));
+	};
+	
+	db::use_db().delete_sessions(&session.username, Some(session.id))?;
+	
+	Ok(Ok(()))
+}
+
+#[derive(Clone, Serialize, Deserialize, Debug)]
+pub struct TotpStatus {
+	pub enabled: bool,
+	pub backup_codes_left: u32,
+}
+
+/// A TOTP secret being set up, it is only enabled once a code generated with it is confirmed.
+#[derive(Clone, Serialize, Deserialize, Debug)]
+pub struct TotpEnrollment {
+	/// `otpauth://` URI for authenticator apps, to enter by hand if the QR code can't be scanned.
+	pub uri: String,
+	/// The URI as an SVG image of a QR code.
+	pub qr_code: String,
+}
+
+#[derive(Clone, Serialize, Deserialize, Error, Debug)]
+pub enum TotpError {
+	#[error("Not authenticated")]
+	NotAuthenticated,
+	#[error("Incorrect password")]
+	IncorrectPassword,
+	#[error("Incorrect code")]
+	IncorrectCode,
+	#[error("Two-factor authentication is already enabled")]
+	AlreadyEnabled,
+	#[error("Two-factor authentication is not being set up, start again")]
+	NotEnrolling,
+	#[error("Too many failed attempts, try again in {0} seconds")]
+	TooManyAttempts(u64),
+}
+
+#[server]
+pub async fn get_totp_status() -> Result<Result<TotpStatus, AuthError>, ServerFnError> {
+	let Ok(username) = authenticate().await else {
+		return Ok(Err(AuthError));
+	};
+	
+	let db = db::use_db();
+	
+	Ok(Ok(TotpStatus {
+		enabled: db.get_totp_secret(&username)?.is_some(),
+		backup_codes_left: db.count_backup_codes(&username)?,
+	}))
+}
+
+/// Generates a new TOTP secret to set up after checking the password, replacing one that was being set up before.
+/// Otherwise a stolen session would be enough to set up an authenticator of someone else and lock the user out.
+#[server]
+pub async fn start_totp_enrollment(auth_key: AuthKey, legacy_hash: Option<PasswordHash>) -> Result<Result<TotpEnrollment, TotpError>, ServerFnError> {
+	use crate::vault::{Pepper, Verification};
+	use tokio::task::spawn_blocking;
+	
+	let Ok(username) = authenticate().await else {
+		return Ok(Err(TotpError::NotAuthenticated));
+	};
+	
+	let db = db::use_db();
+	let pepper: Pepper = use_context().unwrap();
+	
+	// replacing the secret takes disabling it first, which needs a code
+	if db.get_totp_secret(&username)?.is_some() {
+		return Ok(Err(TotpError::AlreadyEnabled));
+	}
+	
+	let Some(verifier) = db.get_verifier(&username)? else {
+		return Ok(Err(TotpError::NotAuthenticated));
+	};
+	
+	let attempt = match start_attempt(&username) {
+		Ok(attempt) => attempt,
+		Err(retry_after) => return Ok(Err(TotpError::TooManyAttempts(retry_after))),
+	};
+	
+	let verification = spawn_blocking(move || pepper.verify(&verifier, &auth_key, legacy_hash.as_ref())).await?;
+	
+	if verification == Verification::Incorrect {
+		fail_attempt(&db, attempt, &username)?;
+		return Ok(Err(TotpError::IncorrectPassword));
+	}
+	
+	attempt.succeeded();
+	
+	let secret = TotpSecret::generate()?;
+	db.set_pending_totp_secret(&username, &secret)?;
+	
+	Ok(Ok(TotpEnrollment {
+		uri: secret.uri(&username),
+		qr_code: secret.qr_code(&username)?,
 	}))
 }
+
+/// Enables two-factor authentication once `code` shows the authenticator was set up with the secret,
+/// returns the backup codes, which are not shown again.
+/// The password was checked when the secret was generated, which is only returned to the one who entered it.
+#[server]
+pub async fn confirm_totp_enrollment(code: String) -> Result<Result<Vec<String>, TotpError>, ServerFnError> {
+	use std::time::SystemTime;
+	use crate::vault::Pepper;
+	
+	let Ok(username) = authenticate().await else {
+		return Ok(Err(TotpError::NotAuthenticated));
+	};
+	
+	let db = db::use_db();
+	let pepper: Pepper = use_context().unwrap();
+	
+	let Some(secret) = db.get_pending_totp_secret(&username)? else {
+		return Ok(Err(TotpError::NotEnrolling));
+	};
+	
+	let Some(step) = secret.verify(&code, SystemTime::now()) else {
+		return Ok(Err(TotpError::IncorrectCode));
+	};
+	
+	let backup_codes = generate_backup_codes()?;
+	let backup_code_hashes: Vec<_> = backup_codes.iter()
+		.map(|code| pepper.backup_code_hash(&normalize_backup_code(code).expect("Generated backup codes should be valid")))
+		.collect();
+	
+	if !db.enable_totp(&username, &secret, &backup_code_hashes)? {
+		return Ok(Err(TotpError::NotEnrolling));
+	}
+	
+	db.use_totp_step(&username, step)?;
+	
+	Ok(Ok(backup_codes))
+}
+
+/// Disables two-factor authentication after checking a TOTP or backup code, so a stolen session isn't enough.
+#[server]
+pub async fn disable_totp(code: String) -> Result<Result<(), TotpError>, ServerFnError> {
+	let Ok(username) = authenticate().await else {
+		return Ok(Err(TotpError::NotAuthenticated));
+	};
+	
+	let db = db::use_db();
+	
+	let Some(secret) = db.get_totp_secret(&username)? else {
+		return Ok(Ok(()));
+	};
+	
+	let attempt = match start_attempt(&username) {
+		Ok(attempt) => attempt,
+		Err(retry_after) => return Ok(Err(TotpError::TooManyAttempts(retry_after))),
+	};
+	
+	if !verify_second_factor(&db, &username, &secret, &code)? {
+		fail_attempt(&db, attempt, &username)?;
+		return Ok(Err(TotpError::IncorrectCode));
+	}
+	
+	attempt.succeeded();
+	db.disable_totp(&username)?;
+	
+	Ok(Ok(()))
+}
+
+/// An invite that can still be used, times are unix timestamps in seconds.
+#[derive(Clone, Serialize, Deserialize, Debug)]
+pub struct InviteInfo {
+	pub id: i64,
+	/// `None` if it wasn't created by a user or they were deleted.
+	pub created_by: Option<String>,
+	pub created_at: i64,
+	pub expires_at: i64,
+}
+
+#[derive(Clone, Copy, Serialize, Deserialize, Error, Debug)]
+pub enum AdminError {
+	#[error("Not authenticated")]
+	NotAuthenticated,
+	#[error("Only admins can do this")]
+	NotAdmin,
+}
+
+/// Resolves the user of a server function request and checks that they are an admin.
+#[cfg(feature = "ssr")]
+async fn authenticate_admin() -> Result<String, AdminError> {
+	let admins: Admins = use_context().unwrap();
+	
+	let username = authenticate().await
+		.map_err(|_| AdminError::NotAuthenticated)?;
+	
+	if !admins.contains(&username) {
+		return Err(AdminError::NotAdmin);
+	}
+	
+	Ok(username)
+}
+
+#[server]
+pub async fn get_invites() -> Result<Result<Vec<InviteInfo>, AdminError>, ServerFnError> {
+	if let Err(err) = authenticate_admin().await {
+		return Ok(Err(err));
+	}
+	
+	Ok(Ok(db::use_db().get_invites()?))
+}
+
+/// Returns the new invite code, only its hash is stored, so it can't be shown again.
+#[server]
+pub async fn create_invite() -> Result<Result<String, AdminError>, ServerFnError> {
+	use std::time::SystemTime;
+	
+	let username = match authenticate_admin().await {
+		Ok(username) => username,
+		Err(err) => return Ok(Err(err)),
+	};
+	
+	let code = generate_invite_code()?;
+	let code_hash = invite_code_hash(&code).expect("Generated invite codes should be valid");
+	db::use_db().insert_invite(&code_hash, Some(&username), SystemTime::now() + INVITE_LIFETIME)?;
+	
+	Ok(Ok(code))
+}
+
+/// Deletes an invite, so its code can no longer be used. Succeeds if it was already used or expired.
+#[server]
+pub async fn revoke_invite(id: i64) -> Result<Result<(), AdminError>, ServerFnError> {
+	if let Err(err) = authenticate_admin().await {
+		return Ok(Err(err));
+	}
+	
+	db::use_db().delete_invite(id)?;
+	
+	Ok(Ok(()))
+}
@@ -1,6 +1,3 @@
-use std::time::{Duration, SystemTime};
-
-use generic_array::{typenum::U32, GenericArray};
 use serde::{Deserialize, Serialize};
 use thiserror::Error;
 
@@ -8,14 +5,6 @@ use thiserror::Error;
 #[error("Authentication failed")]
 pub struct AuthError;
 
-#[derive(Clone, Serialize, Deserialize, Debug)]
-pub struct Auth {
-	username: String,
-	generated_at: SystemTime,
-	valid_for: Duration,
-	auth_code: GenericArray<u8, U32>,
-}
-
 #[cfg(feature = "ssr")]
 pub use server::*;
 
@@ -23,38 +12,181 @@ pub use server::*;
 mod server {
 	use super::*;
 	
-	use std::{fmt::{self, Debug}, hash::{Hash, Hasher}};
+	use std::{fmt::{self, Debug}, hash::{Hash, Hasher}, time::{Duration, SystemTime}};
+	use axum::{async_trait, extract::{FromRef, FromRequestParts, Request, State}, middleware::Next, response::{IntoResponse, Response}};
+	use generic_array::{typenum::U32, GenericArray};
+	use http::{header, request::Parts, HeaderMap, HeaderValue, StatusCode};
 	use leptos::use_context;
 	use sha2::digest::FixedOutput;
+	use crate::{db::Database, server::AppState, utils::{from_hex, to_hex}};
 	use hmac::Mac;
 	
 	type Hmac = hmac::Hmac<sha2::Sha256>;
 	
+	/// Cookie carrying the signed auth token, it is `HttpOnly` so scripts can't read it.
+	pub const AUTH_COOKIE: &str = "vault_auth";
+	
+	/// Random id of a row in the sessions table, deleting the row revokes every token referencing it.
+	#[derive(Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize, Debug)]
+	pub struct SessionId([u8; 16]);
+	
+	impl SessionId {
+		pub fn generate() -> Result<Self, getrandom::Error> {
+			let mut id = [0; 16];
+			getrandom::getrandom(&mut id)?;
+			
+			Ok(Self(id))
+		}
+		
+		pub fn as_bytes(&self) -> &[u8; 16] {
+			&self.0
+		}
+		
+		pub fn from_bytes(bytes: [u8; 16]) -> Self {
+			Self(bytes)
+		}
+	}
+	
+	#[derive(Clone, Serialize, Deserialize, Debug)]
+	pub struct Auth {
+		username: String,
+		session: SessionId,
+		generated_at: SystemTime,
+		valid_for: Duration,
+		auth_code: GenericArray<u8, U32>,
+	}
+	
 	impl Auth {
-		pub fn username(&self) -> Result<&str, AuthError> {
-			let authenticator: Authenticator = use_context().unwrap();
+		/// `Set-Cookie` header value that stores the token in the browser for as long as it is valid.
+		pub fn to_cookie(&self) -> HeaderValue {
+			let json = serde_json::to_vec(self).expect("Auth should be serializable");
 			
-			if authenticator.validate(self) {
-				Ok(&self.username)
-			} else {
-				Err(AuthError)
-			}
+			HeaderValue::try_from(format!(
+				"{AUTH_COOKIE}={}; Path=/; Max-Age={}; Secure; HttpOnly; SameSite=Strict",
+				to_hex(&json),
+				self.valid_for.as_secs(),
+			)).expect("Hex should be a valid header value")
+		}
+		
+		pub fn from_cookies(headers: &HeaderMap) -> Option<Self> {
+			let value = headers.get_all(header::COOKIE).iter()
+				.filter_map(|cookies| cookies.to_str().ok())
+				.flat_map(|cookies| cookies.split(';'))
+				.filter_map(|cookie| cookie.trim().split_once('='))
+				.find(|(name, _)| *name == AUTH_COOKIE)
+				.map(|(_, value)| value)?;
+			
+			serde_json::from_slice(&from_hex(value)?).ok()
+		}
+		
+		/// `Set-Cookie` header value that removes the token from the browser.
+		pub fn clear_cookie() -> HeaderValue {
+			HeaderValue::try_from(format!("{AUTH_COOKIE}=; Path=/; Max-Age=0; Secure; HttpOnly; SameSite=Strict"))
+				.expect("Cookie should be a valid header value")
+		}
+	}
+	
+	/// The session a request was made in, resolved from the auth cookie.
+	#[derive(Clone, Debug)]
+	pub struct Session {
+		pub id: SessionId,
+		pub username: String,
+	}
+	
+	#[async_trait]
+	impl<S> FromRequestParts<S> for Session
+	where
+		S: Send + Sync,
+		Authenticator: FromRef<S>,
+		Database: FromRef<S>,
+	{
+		type Rejection = AuthError;
+		
+		async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, AuthError> {
+			let auth = Auth::from_cookies(&parts.headers).ok_or(AuthError)?;
+			
+			Authenticator::from_ref(state).session(&auth, &Database::from_ref(state))
+		}
+	}
+	
+	/// The user a request was made by, resolved from the auth cookie.
+	pub struct Username(pub String);
+	
+	#[async_trait]
+	impl<S> FromRequestParts<S> for Username
+	where
+		S: Send + Sync,
+		Authenticator: FromRef<S>,
+		Database: FromRef<S>,
+	{
+		type Rejection = AuthError;
+		
+		async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, AuthError> {
+			Session::from_request_parts(parts, state).await
+				.map(|session| Self(session.username))
+		}
+	}
+	
+	impl IntoResponse for AuthError {
+		fn into_response(self) -> Response {
+			StatusCode::UNAUTHORIZED.into_response()
 		}
 	}
 	
+	/// Resolves the user of a server function request with the [Username] extractor.
+	pub async fn authenticate() -> Result<String, AuthError> {
+		current_session().await
+			.map(|session| session.username)
+	}
+	
+	/// Resolves the session of a server function request with the [Session] extractor.
+	pub async fn current_session() -> Result<Session, AuthError> {
+		let app_state: AppState = use_context().unwrap();
+		
+		leptos_axum::extract_with_state::<Session, _>(&app_state).await
+			.map_err(|_| AuthError)
+	}
+	
+	/// Middleware that sends a new token for the same session with responses to requests whose token
+	/// has used up more than half of its lifetime, so sessions in use don't expire.
+	pub async fn renew_auth(State(authenticator): State<Authenticator>, State(db): State<Database>, request: Request, next: Next) -> Response {
+		let auth = Auth::from_cookies(request.headers());
+		
+		let mut response = next.run(request).await;
+		
+		// logging in or out already replaces the cookie
+		if response.headers().contains_key(header::SET_COOKIE) {
+			return response;
+		}
+		
+		if let Some(renewed) = auth.and_then(|auth| authenticator.renew(&auth, &db)) {
+			response.headers_mut().append(header::SET_COOKIE, renewed.to_cookie());
+		}
+		
+		response
+	}
+	
 	#[derive(Clone)]
 	pub struct Authenticator {
-		key: [u8; 64]
+		key: [u8; 64],
+		session_lifetime: Duration,
 	}
 	
 	impl Authenticator {
-		pub fn new(key: [u8; 64]) -> Self {
+		pub fn new(key: [u8; 64], session_lifetime: Duration) -> Self {
 			Self {
 				key,
+				session_lifetime,
 			}
 		}
 		
-		pub(in super::super) fn sign(&self, username: String, valid_for: Duration) -> Auth {
+		/// How long tokens are valid for, and so how long a session can go unused before it expires.
+		pub fn session_lifetime(&self) -> Duration {
+			self.session_lifetime
+		}
+		
+		pub(in super::super) fn sign(&self, username: String, session: SessionId) -> Auth {
+			let valid_for = self.session_lifetime;
 			let generated_at = SystemTime::now();
 			
 			let mut hmac = Hmac::new_from_slice(&self.key)
@@ -62,6 +194,7 @@ mod server {
 			let mut hasher = HmacHasher(&mut hmac);
 			
 			username.hash(&mut hasher);
+			session.hash(&mut hasher);
 			generated_at.hash(&mut hasher);
 			valid_for.hash(&mut hasher);
 			
@@ -69,13 +202,46 @@ mod server {
 			
 			Auth {
 				username,
+				session,
 				generated_at,
 				valid_for,
 				auth_code,
 			}
 		}
 		
-		pub(super) fn validate(&self, auth: &Auth) -> bool {
+		/// Checks the token and that its session wasn't revoked, and records that the session was seen.
+		pub fn session(&self, auth: &Auth, db: &Database) -> Result<Session, AuthError> {
+			if !self.validate(auth) {
+				return Err(AuthError);
+			}
+			
+			match db.touch_session(auth.session, &auth.username) {
+				Ok(true) => Ok(Session {
+					id: auth.session,
+					username: auth.username.clone(),
+				}),
+				Ok(false) => Err(AuthError),
+				Err(err) => {
+					eprintln!("Error checking session: {err}");
+					Err(AuthError)
+				},
+			}
+		}
+		
+		/// Signs a new token for the session of `auth` if it is valid but past half of its lifetime.
+		fn renew(&self, auth: &Auth, db: &Database) -> Option<Auth> {
+			let elapsed_time = auth.generated_at.elapsed().ok()?;
+			
+			if elapsed_time < auth.valid_for / 2 {
+				return None;
+			}
+			
+			let session = self.session(auth, db).ok()?;
+			
+			Some(self.sign(session.username, session.id))
+		}
+		
+		fn validate(&self, auth: &Auth) -> bool {
 			let Ok(elapsed_time) = auth.generated_at.elapsed() else {
 				return false;
 			};
@@ -89,6 +255,7 @@ mod server {
 			let mut hasher = HmacHasher(&mut hmac);
 			
 			auth.username.hash(&mut hasher);
+			auth.session.hash(&mut hasher);
 			auth.generated_at.hash(&mut hasher);
 			auth.valid_for.hash(&mut hasher);
 			
@@ -0,0 +1,329 @@
+use std::{collections::HashMap, net::{IpAddr, SocketAddr}, sync::{Arc, Mutex}, time::{Duration, Instant}};
+
+use axum::extract::ConnectInfo;
+use http::request::Parts;
+use leptos::{logging, use_context};
+
+/// Failed attempts for a username before each further one blocks it for an exponentially growing delay.
+const FREE_USER_FAILURES: u32 = 5;
+/// Addresses get more, since several people may share one.
+const FREE_IP_FAILURES: u32 = 20;
+const BASE_DELAY: Duration = Duration::from_secs(1);
+const MAX_DELAY: Duration = Duration::from_hours(1);
+/// Failures are forgotten once there were none for this long.
+const FORGET_AFTER: Duration = Duration::from_days(1);
+const PRUNE_INTERVAL: Duration = Duration::from_mins(10);
+
+#[derive(Clone, PartialEq, Eq, Hash, Debug)]
+enum Key {
+	Ip(IpAddr),
+	User(String),
+}
+
+impl Key {
+	fn free_failures(&self) -> u32 {
+		match self {
+			Key::Ip(_) => FREE_IP_FAILURES,
+			Key::User(_) => FREE_USER_FAILURES,
+		}
+	}
+}
+
+#[derive(Clone, Copy, Debug)]
+struct Failures {
+	count: u32,
+	last_failure: Instant,
+	blocked_until: Instant,
+}
+
+#[derive(Debug)]
+struct State {
+	failures: HashMap<Key, Failures>,
+	/// Attempts that were started but whose result isn't known yet, counted as if they will fail,
+	/// so concurrent requests can't all get past the check before the first failure is recorded.
+	in_flight: HashMap<Key, u32>,
+	last_prune: Instant,
+}
+
+impl State {
+	fn blocked_for(&self, ip: Option<IpAddr>, username: &str, now: Instant) -> Option<Duration> {
+		keys(ip, username)
+			.filter_map(|key| {
+				let failures = self.failures.get(&key)
+					.filter(|failures| now.duration_since(failures.last_failure) < FORGET_AFTER);
+				let count = failures.map_or(0, |failures| failures.count);
+				let in_flight = self.in_flight.get(&key).copied().unwrap_or(0);
+				
+				// the attempts in flight would block further ones if they all failed
+				if in_flight > 0 && count + in_flight > key.free_failures() {
+					return delay(count + in_flight, key.free_failures());
+				}
+				
+				failures.and_then(|failures| failures.blocked_until.checked_duration_since(now))
+			})
+			.filter(|duration| !duration.is_zero())
+			.max()
+	}
+	
+	fn record_failure(&mut self, ip: Option<IpAddr>, username: &str, now: Instant) {
+		if now.duration_since(self.last_prune) > PRUNE_INTERVAL {
+			self.failures.retain(|_, failures| now.duration_since(failures.last_failure) < FORGET_AFTER);
+			self.last_prune = now;
+		}
+		
+		for key in keys(ip, username) {
+			let free_failures = key.free_failures();
+			
+			let failures = self.failures.entry(key.clone())
+				.and_modify(|failures| {
+					if now.duration_since(failures.last_failure) >= FORGET_AFTER {
+						failures.count = 0;
+					}
+				})
+				.or_insert(Failures {
+					count: 0,
+					last_failure: now,
+					blocked_until: now,
+				});
+			
+			failures.count += 1;
+			failures.last_failure = now;
+			
+			if let Some(delay) = delay(failures.count, free_failures) {
+				failures.blocked_until = now + delay;
+				
+				if failures.count == free_failures + 1 {
+					logging::log!("Rate limiting login attempts for {key:?} after {} failures", failures.count);
+				}
+			}
+		}
+	}
+	
+	fn end_attempt(&mut self, ip: Option<IpAddr>, username: &str) {
+		for key in keys(ip, username) {
+			if let Some(in_flight) = self.in_flight.get_mut(&key) {
+				*in_flight -= 1;
+				
+				if *in_flight == 0 {
+					self.in_flight.remove(&key);
+				}
+			}
+		}
+	}
+}
+
+/// How long `count` failures block further attempts for, `None` while they are within `free_failures`.
+fn delay(count: u32, free_failures: u32) -> Option<Duration> {
+	let exponent = count.checked_sub(free_failures + 1)?;
+	
+	Some(BASE_DELAY.saturating_mul(1 << exponent.min(16)).min(MAX_DELAY))
+}
+
+/// Limits how often passwords and recovery codes can be guessed, per client address and per username.
+/// Failures are only kept in memory, so restarting the server resets them.
+#[derive(Clone, Debug)]
+pub struct RateLimiter {
+	state: Arc<Mutex<State>>,
+	trust_proxy: bool,
+}
+
+impl RateLimiter {
+	/// With `trust_proxy` the client address is taken from the `X-Forwarded-For` header the reverse proxy appends to.
+	pub fn new(trust_proxy: bool) -> Self {
+		Self {
+			state: Arc::new(Mutex::new(State {
+				failures: HashMap::new(),
+				in_flight: HashMap::new(),
+				last_prune: Instant::now(),
+			})),
+			trust_proxy,
+		}
+	}
+	
+	/// Starts an attempt for `username` from the client of the current server function request,
+	/// or returns how long until the next one is allowed.
+	pub fn attempt(&self, username: &str) -> Result<Attempt, Duration> {
+		self.start_attempt(self.client_ip(), username)
+	}
+	
+	fn start_attempt(&self, ip: Option<IpAddr>, username: &str) -> Result<Attempt, Duration> {
+		let mut state = self.state.lock().unwrap();
+		
+		if let Some(delay) = state.blocked_for(ip, username, Instant::now()) {
+			return Err(delay);
+		}
+		
+		for key in keys(ip, username) {
+			*state.in_flight.entry(key).or_default() += 1;
+		}
+		
+		Ok(Attempt {
+			limiter: self.clone(),
+			ip,
+			username: username.to_owned(),
+			finished: false,
+		})
+	}
+	
+	/// The address the current server function request came from.
+	fn client_ip(&self) -> Option<IpAddr> {
+		let parts: Parts = use_context()?;
+		
+		if self.trust_proxy {
+			return parts.headers.get_all("x-forwarded-for").iter()
+				.last()
+				.and_then(|value| value.to_str().ok())
+				.and_then(|value| value.rsplit(',').next())
+				.and_then(|ip| ip.trim().parse().ok());
+		}
+		
+		parts.extensions.get::<ConnectInfo<SocketAddr>>()
+			.map(|ConnectInfo(address)| address.ip())
+	}
+	
+	/// How long until the next attempt for `username` from `ip` is allowed, `None` if it is allowed now.
+	#[cfg(test)]
+	fn blocked_for(&self, ip: Option<IpAddr>, username: &str) -> Option<Duration> {
+		self.state.lock().unwrap().blocked_for(ip, username, Instant::now())
+	}
+	
+	#[cfg(test)]
+	fn record_failure(&self, ip: Option<IpAddr>, username: &str) {
+		self.state.lock().unwrap().record_failure(ip, username, Instant::now());
+	}
+	
+	/// Forgets the failures for the username, the ones for the address are kept,
+	/// so an account of their own doesn't let anyone keep guessing others.
+	#[cfg(test)]
+	fn record_success(&self, username: &str) {
+		self.state.lock().unwrap().failures.remove(&Key::User(username.to_owned()));
+	}
+}
+
+/// A guess of a password or recovery code, counted as in flight until it is known whether it was correct.
+/// Dropping it without a result, like when the request fails, neither counts as a failure nor a success.
+pub struct Attempt {
+	limiter: RateLimiter,
+	ip: Option<IpAddr>,
+	username: String,
+	finished: bool,
+}
+
+impl Attempt {
+	pub fn failed(mut self) {
+		let mut state = self.limiter.state.lock().unwrap();
+		state.end_attempt(self.ip, &self.username);
+		state.record_failure(self.ip, &self.username, Instant::now());
+		drop(state);
+		
+		self.finished = true;
+	}
+	
+	/// Forgets the failures for the username, the ones for the address are kept,
+	/// so an account of their own doesn't let anyone keep guessing others.
+	pub fn succeeded(mut self) {
+		let mut state = self.limiter.state.lock().unwrap();
+		state.end_attempt(self.ip, &self.username);
+		state.failures.remove(&Key::User(self.username.clone()));
+		drop(state);
+		
+		self.finished = true;
+	}
+}
+
+impl Drop for Attempt {
+	fn drop(&mut self) {
+		if !self.finished {
+			self.limiter.state.lock().unwrap().end_attempt(self.ip, &self.username);
+		}
+	}
+}
+
+fn keys(ip: Option<IpAddr>, username: &str) -> impl Iterator<Item = Key> {
+	ip.map(Key::Ip).into_iter().chain([Key::User(username.to_owned())])
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	
+	fn ip(byte: u8) -> Option<IpAddr> {
+		Some(IpAddr::from([10, 0, 0, byte]))
+	}
+	
+	#[test]
+	fn blocks_after_free_failures() {
+		let limiter = RateLimiter::new(false);
+		
+		for _ in 0..FREE_USER_FAILURES {
+			limiter.record_failure(ip(1), "alice");
+			assert_eq!(limiter.blocked_for(ip(1), "alice"), None);
+		}
+		
+		limiter.record_failure(ip(1), "alice");
+		assert!(limiter.blocked_for(ip(1), "alice").is_some_and(|delay| delay <= BASE_DELAY));
+		// from another address as well
+		assert!(limiter.blocked_for(ip(2), "alice").is_some());
+		assert_eq!(limiter.blocked_for(ip(1), "bob"), None);
+		
+		limiter.record_failure(ip(1), "alice");
+		assert!(limiter.blocked_for(ip(1), "alice").is_some_and(|delay| delay > BASE_DELAY));
+	}
+	
+	#[test]
+	fn blocks_addresses_guessing_many_users() {
+		let limiter = RateLimiter::new(false);
+		
+		for user in 0..=FREE_IP_FAILURES {
+			limiter.record_failure(ip(1), &user.to_string());
+		}
+		
+		assert!(limiter.blocked_for(ip(1), "alice").is_some());
+		assert_eq!(limiter.blocked_for(ip(2), "alice"), None);
+	}
+	
+	#[test]
+	fn counts_concurrent_attempts() {
+		let limiter = RateLimiter::new(false);
+		
+		// as many as are allowed one after another before the first failure blocks the user
+		let attempts: Vec<Attempt> = (0..=FREE_USER_FAILURES)
+			.map(|_| limiter.start_attempt(ip(1), "alice").unwrap())
+			.collect();
+		
+		assert!(limiter.start_attempt(ip(2), "alice").is_err());
+		assert!(limiter.start_attempt(ip(1), "bob").is_ok());
+		
+		for attempt in attempts {
+			attempt.failed();
+		}
+		
+		assert!(limiter.start_attempt(ip(1), "alice").is_err());
+	}
+	
+	#[test]
+	fn dropped_attempts_are_not_counted() {
+		let limiter = RateLimiter::new(false);
+		
+		let attempts: Vec<Attempt> = (0..=FREE_USER_FAILURES)
+			.map(|_| limiter.start_attempt(ip(1), "alice").unwrap())
+			.collect();
+		
+		drop(attempts);
+		
+		assert_eq!(limiter.blocked_for(ip(1), "alice"), None);
+		assert!(limiter.start_attempt(ip(1), "alice").is_ok());
+	}
+	
+	#[test]
+	fn success_forgets_user_failures() {
+		let limiter = RateLimiter::new(false);
+		
+		for _ in 0..=FREE_USER_FAILURES {
+			limiter.record_failure(None, "alice");
+		}
+		
+		limiter.record_success("alice");
+		assert_eq!(limiter.blocked_for(None, "alice"), None);
+	}
+}
@@ -0,0 +1,107 @@
+use std::str::FromStr;
+
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+/// Who can create accounts, configured with `VAULT_REGISTRATION`.
+#[derive(Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize, Debug)]
+pub enum RegistrationMode {
+	/// Anyone who can reach the server.
+	#[default]
+	Open,
+	/// Only with an invite code created by an admin.
+	Invite,
+	Disabled,
+}
+
+#[derive(Clone, Copy, Error, Debug)]
+#[error("Registration mode must be one of open, invite or disabled")]
+pub struct InvalidRegistrationMode;
+
+impl FromStr for RegistrationMode {
+	type Err = InvalidRegistrationMode;
+	
+	fn from_str(s: &str) -> Result<Self, Self::Err> {
+		match s {
+			"open" => Ok(Self::Open),
+			"invite" => Ok(Self::Invite),
+			"disabled" => Ok(Self::Disabled),
+			_ => Err(InvalidRegistrationMode),
+		}
+	}
+}
+
+#[cfg(feature = "ssr")]
+pub use server::*;
+
+#[cfg(feature = "ssr")]
+mod server {
+	use std::{collections::HashSet, sync::Arc, time::Duration};
+	
+	use sha2::{Digest, Sha256};
+	
+	use super::super::totp::BASE32_ALPHABET;
+	
+	/// How long invite codes can be used for after they were created.
+	pub const INVITE_LIFETIME: Duration = Duration::from_days(7);
+	/// Characters of an invite code, each one is a base32 digit, so codes have 80 bits of entropy.
+	const INVITE_CODE_LENGTH: usize = 16;
+	
+	/// Usernames of the users that can create invite codes, configured with `VAULT_ADMINS`.
+	/// These names can't be registered, so admins need to create their account before being added.
+	#[derive(Clone, Default, Debug)]
+	pub struct Admins(Arc<HashSet<String>>);
+	
+	impl Admins {
+		pub fn new(usernames: impl IntoIterator<Item = String>) -> Self {
+			Self(Arc::new(usernames.into_iter().collect()))
+		}
+		
+		pub fn contains(&self, username: &str) -> bool {
+			self.0.contains(username)
+		}
+	}
+	
+	/// Random code that lets one account be created while registration is [RegistrationMode::Invite](super::RegistrationMode::Invite).
+	pub fn generate_invite_code() -> Result<String, getrandom::Error> {
+		let mut bytes = [0; INVITE_CODE_LENGTH];
+		getrandom::getrandom(&mut bytes)?;
+		
+		let code: Vec<String> = bytes.chunks(4)
+			.map(|chunk| chunk.iter().map(|byte| BASE32_ALPHABET[(byte & 31) as usize].to_ascii_lowercase() as char).collect())
+			.collect();
+		
+		Ok(code.join("-"))
+	}
+	
+	/// Hash an invite code is stored as, ignoring case, spaces and dashes of how it was typed.
+	/// Returns `None` if it can't be an invite code. Codes are random, so a fast hash is enough.
+	pub fn invite_code_hash(code: &str) -> Option<[u8; 32]> {
+		let code: String = code.chars()
+			.filter(|char| !char.is_whitespace() && *char != '-')
+			.map(|char| char.to_ascii_uppercase())
+			.collect();
+		
+		if code.len() != INVITE_CODE_LENGTH || !code.bytes().all(|byte| BASE32_ALPHABET.contains(&byte)) {
+			return None;
+		}
+		
+		Some(Sha256::digest(code.to_ascii_lowercase()).into())
+	}
+	
+	#[cfg(test)]
+	mod tests {
+		use super::*;
+		
+		#[test]
+		fn hashes_invite_codes_as_typed() {
+			let code = generate_invite_code().unwrap();
+			let hash = invite_code_hash(&code).unwrap();
+			
+			assert_eq!(code.len(), INVITE_CODE_LENGTH + 3);
+			assert_eq!(invite_code_hash(&code.to_uppercase().replace('-', " ")), Some(hash));
+			assert_ne!(invite_code_hash(&generate_invite_code().unwrap()), Some(hash));
+			assert_eq!(invite_code_hash(&code[1..]), None);
+		}
+	}
+}
@@ -0,0 +1,210 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use hmac::{Hmac, Mac};
+use qrcode::{render::svg, types::QrError, QrCode};
+use sha1::Sha1;
+use subtle::ConstantTimeEq;
+
+/// Seconds each code is valid for.
+const PERIOD: u64 = 30;
+const DIGITS: u32 = 6;
+/// Codes of this many steps before or after the current one are accepted too, since clocks drift.
+const ALLOWED_DRIFT: u64 = 1;
+const ISSUER: &str = "Vault";
+pub(super) const BASE32_ALPHABET: &[u8; 32] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZ234567";
+
+const BACKUP_CODE_COUNT: usize = 10;
+/// Characters of a backup code, each one is a base32 digit, so codes have 50 bits of entropy.
+const BACKUP_CODE_LENGTH: usize = 10;
+
+/// Shared secret of RFC 6238 time-based one-time passwords, as generated by authenticator apps.
+#[derive(Clone, PartialEq, Eq)]
+pub struct TotpSecret([u8; 20]);
+
+impl TotpSecret {
+	pub fn generate() -> Result<Self, getrandom::Error> {
+		let mut secret = [0; 20];
+		getrandom::getrandom(&mut secret)?;
+		
+		Ok(Self(secret))
+	}
+	
+	pub fn as_bytes(&self) -> &[u8; 20] {
+		&self.0
+	}
+	
+	pub fn from_bytes(bytes: [u8; 20]) -> Self {
+		Self(bytes)
+	}
+	
+	/// `otpauth://` URI that authenticator apps are set up with, usually by scanning it as a QR code.
+	pub fn uri(&self, username: &str) -> String {
+		format!(
+			"otpauth://totp/{ISSUER}:{}?secret={}&issuer={ISSUER}&algorithm=SHA1&digits={DIGITS}&period={PERIOD}",
+			percent_encode(username),
+			base32(&self.0),
+		)
+	}
+	
+	/// The URI of [TotpSecret::uri] as an SVG image of a QR code.
+	pub fn qr_code(&self, username: &str) -> Result<String, QrError> {
+		let qr_code = QrCode::new(self.uri(username))?;
+		
+		Ok(qr_code.render::<svg::Color>()
+			.min_dimensions(200, 200)
+			.build())
+	}
+	
+	/// Returns the time step `code` was generated for if it is valid at `time`,
+	/// so it can be recorded as used.
+	pub fn verify(&self, code: &str, time: SystemTime) -> Option<u64> {
+		let code = code.trim();
+		
+		if code.len() != DIGITS as usize || !code.bytes().all(|byte| byte.is_ascii_digit()) {
+			return None;
+		}
+		
+		let code: u32 = code.parse().ok()?;
+		let current_step = time.duration_since(UNIX_EPOCH).ok()?.as_secs() / PERIOD;
+		
+		(current_step.saturating_sub(ALLOWED_DRIFT)..=current_step + ALLOWED_DRIFT)
+			.find(|&step| bool::from(self.code(step, DIGITS).ct_eq(&code)))
+	}
+	
+	/// HOTP value of RFC 4226 for the counter `step`.
+	fn code(&self, step: u64, digits: u32) -> u32 {
+		let mut hmac = Hmac::<Sha1>::new_from_slice(&self.0).expect("HMAC takes keys of any size");
+		hmac.update(&step.to_be_bytes());
+		let hash = hmac.finalize().into_bytes();
+		
+		let offset = (hash[hash.len() - 1] & 0xf) as usize;
+		let value = u32::from_be_bytes(hash[offset..offset + 4].try_into().unwrap()) & 0x7fff_ffff;
+		
+		value % 10u32.pow(digits)
+	}
+}
+
+/// One-time codes to log in with instead of a TOTP code, in case the authenticator is lost.
+pub fn generate_backup_codes() -> Result<Vec<String>, getrandom::Error> {
+	(0..BACKUP_CODE_COUNT)
+		.map(|_| {
+			let mut bytes = [0; BACKUP_CODE_LENGTH];
+			getrandom::getrandom(&mut bytes)?;
+			
+			let code: String = bytes.iter()
+				.map(|byte| BASE32_ALPHABET[(byte & 31) as usize].to_ascii_lowercase() as char)
+				.collect();
+			let (first, second) = code.split_at(BACKUP_CODE_LENGTH / 2);
+			
+			Ok(format!("{first}-{second}"))
+		})
+		.collect()
+}
+
+/// Brings a backup code into the form it is hashed in, ignoring case, spaces and dashes.
+/// Returns `None` if it can't be a backup code.
+pub fn normalize_backup_code(code: &str) -> Option<String> {
+	let code: String = code.chars()
+		.filter(|char| !char.is_whitespace() && *char != '-')
+		.map(|char| char.to_ascii_uppercase())
+		.collect();
+	
+	if code.len() != BACKUP_CODE_LENGTH || !code.bytes().all(|byte| BASE32_ALPHABET.contains(&byte)) {
+		return None;
+	}
+	
+	Some(code.to_ascii_lowercase())
+}
+
+/// Base32 of RFC 4648 without padding, which is how authenticator apps expect secrets.
+fn base32(bytes: &[u8]) -> String {
+	let mut encoded = String::with_capacity(bytes.len().div_ceil(5) * 8);
+	let mut buffer = 0u16;
+	let mut bits = 0;
+	
+	for &byte in bytes {
+		buffer = (buffer << 8) | byte as u16;
+		bits += 8;
+		
+		while bits >= 5 {
+			bits -= 5;
+			encoded.push(BASE32_ALPHABET[((buffer >> bits) & 31) as usize] as char);
+		}
+	}
+	
+	if bits > 0 {
+		encoded.push(BASE32_ALPHABET[((buffer << (5 - bits)) & 31) as usize] as char);
+	}
+	
+	encoded
+}
+
+/// Escapes everything but unreserved characters, for the label of the URI.
+fn percent_encode(string: &str) -> String {
+	string.bytes()
+		.map(|byte| match byte {
+			b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => (byte as char).to_string(),
+			_ => format!("%{byte:02X}"),
+		})
+		.collect()
+}
+
+#[cfg(test)]
+mod tests {
+	use std::time::Duration;
+	
+	use super::*;
+	
+	/// Secret of the SHA-1 test vectors in appendix B of RFC 6238.
+	fn rfc_secret() -> TotpSecret {
+		TotpSecret(*b"12345678901234567890")
+	}
+	
+	fn at(seconds: u64) -> SystemTime {
+		UNIX_EPOCH + Duration::from_secs(seconds)
+	}
+	
+	#[test]
+	fn matches_rfc_test_vectors() {
+		let secret = rfc_secret();
+		
+		for (time, code) in [(59, 94287082), (1111111109, 7081804), (1111111111, 14050471), (1234567890, 89005924), (2000000000, 69279037)] {
+			assert_eq!(secret.code(time / PERIOD, 8), code);
+		}
+	}
+	
+	#[test]
+	fn verifies_codes_with_drift() {
+		let secret = rfc_secret();
+		
+		assert_eq!(secret.verify("287082", at(59)), Some(1));
+		assert_eq!(secret.verify(" 287082 ", at(89)), Some(1));
+		assert_eq!(secret.verify("287082", at(120)), None);
+		assert_eq!(secret.verify("287083", at(59)), None);
+		assert_eq!(secret.verify("28708", at(59)), None);
+		assert_eq!(secret.verify("+87082", at(59)), None);
+	}
+	
+	#[test]
+	fn encodes_base32() {
+		assert_eq!(base32(b""), "");
+		assert_eq!(base32(b"f"), "MY");
+		assert_eq!(base32(b"foobar"), "MZXW6YTBOI");
+		assert_eq!(base32(b"12345678901234567890"), "GEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQ");
+	}
+	
+	#[test]
+	fn normalizes_backup_codes() {
+		let codes = generate_backup_codes().unwrap();
+		
+		assert_eq!(codes.len(), BACKUP_CODE_COUNT);
+		
+		for code in codes {
+			assert_eq!(normalize_backup_code(&code), Some(code.replace('-', "")));
+			assert_eq!(normalize_backup_code(&code.to_uppercase().replace('-', " ")), Some(code.replace('-', "")));
+		}
+		
+		assert_eq!(normalize_backup_code("abcde-fghi"), None);
+		assert_eq!(normalize_backup_code("abcde-fgh1j"), None);
+	}
+}
@@ -1,4 +1,4 @@
-use crate::{account::Auth, app::file_area::FileArea, app_error_view::{AppError, AppErrorView}, file_store::FileStore, vault::{Cipher, FolderName, Vault}};
+use crate::{app::file_area::FileArea, app_error_view::{AppError, AppErrorView}, file_store::FileStore, files::FolderEntry, vault::Vault};
 use leptos::*;
 use leptos_meta::*;
 use leptos_router::*;
@@ -11,18 +11,21 @@ mod local_image;
 mod login;
 mod folders;
 mod file_area;
+mod settings;
 
 use notify::NotifyProvider;
-use login::Login;
+use login::{Login, ReauthenticatePrompt};
 use folders::Folders;
+use settings::Settings;
+
+pub use login::Reauthenticate;
 
 import_style!(style, "app.css");
 
 #[derive(Clone, Debug)]
 struct UserData {
 	vault: Vault,
-	auth: Auth,
-	initial_folders: Vec<Cipher<FolderName>>,
+	initial_folders: Vec<FolderEntry>,
 }
 
 #[derive(Params, Clone, PartialEq, Eq, Debug)]
@@ -35,8 +38,9 @@ pub fn App() -> impl IntoView {
 	provide_meta_context();
 	
 	let (user_data, set_user_data) = create_signal::<Option<UserData>>(None);
+	let reauthenticate = Reauthenticate::provide();
 	let file_store = create_owning_memo(move |_| (with!(|user_data| user_data.as_ref().map(|user_data|
-		FileStore::new(user_data.vault.clone(), user_data.auth.clone())
+		FileStore::new(user_data.vault.clone(), reauthenticate)
 	)), true));
 	
 	view! {
@@ -53,6 +57,12 @@ pub fn App() -> impl IntoView {
 		}>
 			<header class=style::title>Vault</header>
 			<NotifyProvider>
+				{move || reauthenticate.is_prompting().then(|| user_data.with(|user_data| user_data.as_ref().map(|user_data| view! {
+					<ReauthenticatePrompt
+						username=user_data.vault.username().to_owned()
+						log_out=move || set_user_data(None)
+					/>
+				})))}
 				<main class=style::content>
 					<Routes>
 						<Route path="/" view=move || view! {
@@ -62,7 +72,12 @@ pub fn App() -> impl IntoView {
 								<Login set_user_data />
 							</Show>
 							{move || user_data.with(|user_data| user_data.as_ref().map(|user_data| view! {
-								<Folders vault=user_data.vault.clone() auth=user_data.auth.clone() initial_folders=user_data.initial_folders.clone()>
+								<Folders
+									vault=user_data.vault.clone()
+									file_store=file_store().expect("FileStore should exist when user data is present")
+									initial_folders=user_data.initial_folders.clone()
+									log_out=move || set_user_data(None)
+								>
 									<Outlet />
 								</Folders>
 							}))}
@@ -73,6 +88,11 @@ pub fn App() -> impl IntoView {
 									<FileArea file_store />
 								})
 							} />
+							<Route path="/settings" view=move || {
+								user_data.with(|user_data| user_data.as_ref().map(|user_data| view! {
+									<Settings vault=user_data.vault.clone() log_out=move || set_user_data(None) />
+								}))
+							} />
 						</Route>
 					</Routes>
 				</main>
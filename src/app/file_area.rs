@@ -66,7 +66,7 @@ pub fn FileArea(file_store: FileStore) -> impl IntoView {
 		}
 		
 		let file_store = file_store.get_value();
-		file_store.add_files(folder, files).await;
+		file_store.add_files(notify, folder, files).await;
 	};
 	
 	let handle_drag = move |event: ev::DragEvent| {
@@ -126,7 +126,7 @@ pub fn FileArea(file_store: FileStore) -> impl IntoView {
 					<For
 						// TODO why does this need to be cloned?
 						each=move || files.clone()
-						key=|file| file.id.clone()
+						key=|file| file.id
 						children=move |file| view! {
 							<File file_store file />
 						}
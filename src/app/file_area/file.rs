@@ -1,17 +1,25 @@
 use leptos::*;
 use gloo_file::{Blob, ObjectUrl};
+use stylance::{classes, import_style};
+use cache_bust::asset;
 
-use crate::{app::local_image::LocalImage, file_store::{FileData, FileStore}};
+use crate::{app::{folders::CurrentFolder, local_image::LocalImage, notify::Notify, Reauthenticate}, file_store::{FileData, FileStore}, files::FilesError, utils::ToPrettyError};
+
+import_style!(style, "file.scss");
 
 #[component]
 pub fn File(file_store: StoredValue<FileStore>, file: FileData) -> impl IntoView {
 	let show_preview = file.info.reveal_secret().mime_type.starts_with("image/");
 	
-	let file_id_cloned = file.id.clone();
+	let CurrentFolder(current_folder) = use_context().unwrap();
+	let notify = Notify::from_context();
+	let reauthenticate = Reauthenticate::from_context();
+	
+	let (is_deleting, set_deleting) = create_signal(false);
 	
 	let preview_url = move || {
 		show_preview.then(|| {
-			with!(|file_store| file_store.with_file_content_tracked(file.id.clone(), |content| {
+			with!(|file_store| file_store.with_file_content_tracked(file.id, |content| {
 				let blob = Blob::new(&*content.reveal_secret().data);
 				ObjectUrl::from(blob)
 			}))
@@ -20,15 +28,45 @@ pub fn File(file_store: StoredValue<FileStore>, file: FileData) -> impl IntoView
 	
 	// TODO temporary workaround for weird behavior with the effect not updating properly
 	create_effect(move |_| {
-		with!(|file_store| file_store.with_file_content_tracked(file_id_cloned.clone(), |_| ()));
+		with!(|file_store| file_store.with_file_content_tracked(file.id, |_| ()));
 	});
 	
+	let delete_file = move |_| {
+		let Some(folder) = current_folder.get_untracked() else {
+			return;
+		};
+		
+		set_deleting(true);
+		
+		spawn_local(async move {
+			let file_store = file_store.get_value();
+			
+			match file_store.delete_file(folder, file.id).await {
+				Err(ServerFnError::WrappedServerError(FilesError::NotAuthenticated)) => {
+					reauthenticate.prompt();
+				},
+				Err(err) => {
+					notify.error(err.to_pretty_error());
+					leptos_dom::error!("Error deleting file: {err}");
+				},
+				Ok(()) => return,
+			}
+			
+			set_deleting(false);
+		});
+	};
+	
 	view! {
-		<div>
-			{move || preview_url().map(|preview_url| view! {
-				<LocalImage src=preview_url />
-			}.into_view())}
+		<div class=move || classes!(style::file, is_deleting().then_some(style::deleting))>
+			<div class=style::preview>
+				{move || preview_url().map(|preview_url| view! {
+					<LocalImage src=preview_url />
+				}.into_view())}
+			</div>
+			<p class=style::name>{file.info.into_revealed_secret().name}</p>
+			<button class=style::delete_button disabled=is_deleting on:click=delete_file>
+				<img class=style::icon src=asset!("/cross.svg") alt="Delete" />
+			</button>
 		</div>
-		<p>{file.info.into_revealed_secret().name}</p>
 	}
 }
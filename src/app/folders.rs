@@ -3,32 +3,39 @@ use leptos_router::{use_location, use_navigate};
 use stylance::{classes, import_style};
 use cache_bust::asset;
 
-use crate::{account::Auth, app::notify::Notify, files::{self, FilesError}, utils::ToPrettyError, vault::{Cipher, FolderName, Secret, Vault}};
+use crate::{account, app::{notify::Notify, Reauthenticate}, file_store::FileStore, files::{self, FilesError, FolderEntry, FolderId}, utils::ToPrettyError, vault::{FolderName, Secret, Vault}};
 
 use super::input::TextInput;
 
 mod folder;
+mod trash;
 
 use folder::*;
+use trash::*;
 
 import_style!(style, "folders.css");
 
 #[derive(Clone, Debug)]
-pub struct CurrentFolder(pub Memo<Option<Cipher<FolderName>>>);
+pub struct CurrentFolder(pub Memo<Option<FolderId>>);
 
 #[component]
-pub fn Folders(
+pub fn Folders<L>(
 	vault: Vault,
-	auth: Auth,
-	initial_folders: Vec<Cipher<FolderName>>,
+	file_store: FileStore,
+	initial_folders: Vec<FolderEntry>,
+	log_out: L,
 	children: Children,
-) -> impl IntoView {
+) -> impl IntoView
+where
+	L: Fn() + Copy + 'static,
+{
 	let notify = Notify::from_context();
+	let reauthenticate = Reauthenticate::from_context();
 	
 	let initial_folders: Vec<_> = initial_folders.into_iter()
 		.enumerate()
-		.filter_map(|(index, folder_name)| {
-			let name = match vault.decrypt(&folder_name) {
+		.filter_map(|(index, folder)| {
+			let name = match vault.decrypt(&folder.name, &folder.id) {
 				Ok(name) => name,
 				Err(err) => {
 					notify.error(format!("Error decrypting folder name for folder {index}"));
@@ -38,17 +45,21 @@ pub fn Folders(
 			};
 			
 			Some(FolderData {
-				id: folder_name,
+				id: folder.id,
 				index: create_rw_signal(index),
 				name: create_rw_signal(name),
 			})
 		})
 		.collect();
 	
+	let vault = store_value(vault);
+	let file_store = store_value(file_store);
+	
 	let new_folder_name = create_rw_signal(String::new());
 	let folder_name_error = create_rw_signal(None);
 	let (folders, set_folders) = create_signal(initial_folders);
 	let (is_sidebar_open, set_sidebar_open) = create_signal(true);
+	let (is_trash_open, set_trash_open) = create_signal(false);
 	
 	let location = use_location();
 	
@@ -58,7 +69,7 @@ pub fn Folders(
 				// TODO handle # and ? in url?
 				folder_index.parse::<usize>().ok()
 			).and_then(|index|
-				folders().get(index).map(|folder_data| folder_data.id.clone())
+				folders().get(index).map(|folder_data| folder_data.id)
 			)
 		})
 	});
@@ -79,22 +90,32 @@ pub fn Folders(
 			name: new_folder_name.get_untracked(),
 		});
 		
-		let cipher_folder_name = match vault.encrypt(&folder_name) {
-			Ok(name) => name,
-			Err(err) => {
-				notify.error("Failed to encrypt folder name");
-				leptos_dom::error!("Failed to encrypt folder name: {err}");
-				return;
-			},
-		};
-		
-		let auth = auth.clone();
-		
 		spawn_local(async move {
-			match files::create_folder(auth, cipher_folder_name.clone()).await {
+			let folder_id = match files::new_folder_id().await {
+				Err(ServerFnError::WrappedServerError(FilesError::NotAuthenticated)) => {
+					reauthenticate.prompt();
+					return;
+				},
+				Err(err) => {
+					notify.error(err.to_pretty_error());
+					leptos_dom::error!("Error creating folder: {err}");
+					return;
+				},
+				Ok(folder_id) => folder_id,
+			};
+			
+			let cipher_folder_name = match vault.with_value(|vault| vault.encrypt(&folder_name, &folder_id)) {
+				Ok(name) => name,
+				Err(err) => {
+					notify.error("Failed to encrypt folder name");
+					leptos_dom::error!("Failed to encrypt folder name: {err}");
+					return;
+				},
+			};
+			
+			match files::create_folder(folder_id, cipher_folder_name).await {
 				Err(ServerFnError::WrappedServerError(FilesError::NotAuthenticated)) => {
-					notify.error("Not authenticated");
-					// TODO prompt to login again
+					reauthenticate.prompt();
 					return;
 				},
 				Err(err) => {
@@ -108,7 +129,7 @@ pub fn Folders(
 			set_folders.update(|folders| {
 				folders.push(
 					FolderData {
-						id: cipher_folder_name,
+						id: folder_id,
 						index: create_rw_signal(folders.len()),
 						name: create_rw_signal(folder_name),
 					}
@@ -119,6 +140,7 @@ pub fn Folders(
 		});
 	};
 	
+	let navigate = use_navigate();
 	let remove_folder = move |index: usize, is_selected| {
 		set_folders.update(|folders| {
 			folders[index].dispose();
@@ -130,12 +152,107 @@ pub fn Folders(
 		});
 		
 		if is_selected {
-			let navigate = use_navigate();
 			navigate("/", Default::default());
 		}
 	};
 	
-	let create_folder_cloned = create_folder.clone();
+	let delete_folder = move |id: FolderId, is_selected| {
+		let remove_folder = remove_folder.clone();
+		
+		spawn_local(async move {
+			match files::delete_folder(id).await {
+				Err(ServerFnError::WrappedServerError(FilesError::NotAuthenticated)) => {
+					reauthenticate.prompt();
+					return;
+				},
+				Err(err) => {
+					notify.error(err.to_pretty_error());
+					leptos_dom::error!("Error deleting folder: {err}");
+					return;
+				},
+				Ok(()) => (),
+			};
+			
+			let Some(index) = folders.with_untracked(|folders| folders.iter().position(|folder| folder.id == id)) else {
+				return;
+			};
+			
+			remove_folder(index, is_selected);
+		});
+	};
+	
+	let rename_folder = move |id: FolderId, name: RwSignal<Secret<FolderName>>, new_name: Secret<FolderName>| {
+		let cipher_new_name = match vault.with_value(|vault| vault.encrypt(&new_name, &id)) {
+			Ok(name) => name,
+			Err(err) => {
+				notify.error("Failed to encrypt folder name");
+				leptos_dom::error!("Failed to encrypt folder name: {err}");
+				return;
+			},
+		};
+		
+		spawn_local(async move {
+			match files::rename_folder(id, cipher_new_name).await {
+				Err(ServerFnError::WrappedServerError(FilesError::NotAuthenticated)) => {
+					reauthenticate.prompt();
+					return;
+				},
+				Err(err) => {
+					notify.error(err.to_pretty_error());
+					leptos_dom::error!("Error renaming folder: {err}");
+					return;
+				},
+				Ok(()) => (),
+			};
+			
+			name.set(new_name);
+		});
+	};
+	
+	let open_settings = {
+		let navigate = use_navigate();
+		move |_| navigate("/settings", Default::default())
+	};
+	
+	let (is_logging_out, set_logging_out) = create_signal(false);
+	
+	let log_out = {
+		let navigate = use_navigate();
+		move |_| {
+			if is_logging_out.get_untracked() {
+				return;
+			}
+			
+			set_logging_out(true);
+			
+			let navigate = navigate.clone();
+			spawn_local(async move {
+				let result = account::logout().await;
+				set_logging_out(false);
+				
+				if let Err(err) = result {
+					notify.error(err.to_pretty_error());
+					leptos_dom::error!("Error logging out: {err}");
+					return;
+				}
+				
+				log_out();
+				navigate("/", Default::default());
+			});
+		}
+	};
+	
+	let restore_folder = move |id: FolderId, name: Secret<FolderName>| {
+		set_folders.update(|folders| {
+			folders.push(
+				FolderData {
+					id,
+					index: create_rw_signal(folders.len()),
+					name: create_rw_signal(name),
+				}
+			);
+		});
+	};
 	
 	view! {
 		<button class=style::sidebar_button on:click=move |_| set_sidebar_open(true)>
@@ -146,26 +263,38 @@ pub fn Folders(
 				<button class=classes!(style::sidebar_button, style::sidebar_back_button) on:click=move |_| set_sidebar_open(false)>
 					<img class=style::icon src=asset!("/back_arrow.svg") alt="Close" />
 				</button>
-				<For
-					each=move || folders()
-					key=|folder| folder.id.clone()
-					children=move |data| {
-						let index = data.index;
-						view! {
-							<Folder
-								data
-								delete_folder=move |is_selected| remove_folder(index.get_untracked(), is_selected)
-							/>
+				<div hidden=is_trash_open>
+					<For
+						each=move || folders()
+						key=|folder| folder.id
+						children=move |data| {
+							let FolderData {id, name, ..} = data;
+							let delete_folder = delete_folder.clone();
+							view! {
+								<Folder
+									data
+									rename_folder=move |new_name| rename_folder(id, name, new_name)
+									delete_folder=move |is_selected| delete_folder(id, is_selected)
+								/>
+							}
 						}
-					}
-				/>
-				<p class=style::label>Add folder:</p>
-				<div class=style::add_folder>
-					<div class=style::input>
-						<TextInput value=new_folder_name error=folder_name_error on_submit=create_folder_cloned />
-					</div>
-					<button class=style::button on:click=move |_| create_folder(())>Add</button>
+					/>
+					<p class=style::label>Add folder:</p>
+					<div class=style::add_folder>
+						<div class=style::input>
+							<TextInput value=new_folder_name error=folder_name_error on_submit=create_folder />
+						</div>
+						<button class=style::button on:click=move |_| create_folder(())>Add</button>
 				</div>
+				</div>
+				{move || is_trash_open().then(|| view! {
+					<Trash vault file_store restore_folder />
+				})}
+				<button class=style::trash_button on:click=move |_| set_trash_open.update(|is_open| *is_open = !*is_open)>
+					{move || if is_trash_open() {"Back to folders"} else {"Trash"}}
+				</button>
+				<button class=style::settings_button on:click=open_settings>Settings</button>
+				<button class=style::settings_button disabled=is_logging_out on:click=log_out>Log out</button>
 			</div>
 		</div>
 		<div class=style::content>
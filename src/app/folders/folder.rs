@@ -5,13 +5,13 @@ use leptos_router::A;
 use stylance::{classes, import_style};
 use cache_bust::asset;
 
-use crate::{app::folders::CurrentFolder, vault::{Cipher, FolderName, Secret}};
+use crate::{app::folders::CurrentFolder, files::FolderId, vault::{FolderName, Secret}};
 
 import_style!(style, "folder.scss");
 
 #[derive(Clone, Debug)]
 pub struct FolderData {
-	pub id: Cipher<FolderName>,
+	pub id: FolderId,
 	pub index: RwSignal<usize>,
 	pub name: RwSignal<Secret<FolderName>>,
 }
@@ -24,11 +24,13 @@ impl FolderData {
 }
 
 #[component]
-pub fn Folder<D>(
+pub fn Folder<R, D>(
 	data: FolderData,
+	rename_folder: R,
 	delete_folder: D,
 ) -> impl IntoView
 where
+	R: Fn(Secret<FolderName>) + Copy + 'static,
 	D: Fn(bool) + 'static,
 {
 	let FolderData {id, index, name} = data;
@@ -65,8 +67,12 @@ where
 	
 	let on_keydown = move |ev: ev::KeyboardEvent| {
 		if ev.key_code() == 13 { // enter
-			name.set(new_folder_name.get_untracked());
+			let new_name = new_folder_name.get_untracked();
 			set_editing(false);
+			
+			if !new_name.reveal_secret().name.is_empty() {
+				rename_folder(new_name);
+			}
 		} else if ev.key_code() == 27 { // escape
 			cancel_name_edit();
 		}
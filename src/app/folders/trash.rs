@@ -0,0 +1,182 @@
+use leptos::*;
+use stylance::import_style;
+
+use crate::{app::{notify::Notify, Reauthenticate}, file_store::{FileData, FileStore}, files::{self, FileId, FilesError, FolderId}, utils::ToPrettyError, vault::{FileInfo, FolderName, Secret, Vault}};
+
+import_style!(style, "trash.scss");
+
+#[derive(Clone, Debug)]
+struct TrashedFolderData {
+	id: FolderId,
+	name: Secret<FolderName>,
+}
+
+#[derive(Clone, Debug)]
+struct TrashedFileData {
+	id: FileId,
+	folder: FolderId,
+	info: Secret<FileInfo>,
+}
+
+#[component]
+pub fn Trash<R>(
+	vault: StoredValue<Vault>,
+	file_store: StoredValue<FileStore>,
+	restore_folder: R,
+) -> impl IntoView
+where
+	R: Fn(FolderId, Secret<FolderName>) + Copy + 'static,
+{
+	let notify = Notify::from_context();
+	let reauthenticate = Reauthenticate::from_context();
+	
+	let (folders, set_folders) = create_signal(None::<Vec<TrashedFolderData>>);
+	let (files, set_files) = create_signal(None::<Vec<TrashedFileData>>);
+	
+	let handle_error = move |err: ServerFnError<FilesError>, action: &str| {
+		match err {
+			ServerFnError::WrappedServerError(FilesError::NotAuthenticated) => {
+				reauthenticate.prompt();
+			},
+			err => {
+				notify.error(err.to_pretty_error());
+				leptos_dom::error!("Error {action}: {err}");
+			},
+		}
+	};
+	
+	spawn_local(async move {
+		let trash = match files::get_trash().await {
+			Ok(trash) => trash,
+			Err(err) => {
+				handle_error(err, "loading trash");
+				return;
+			},
+		};
+		
+		let trashed_folders = vault.with_value(|vault| trash.folders.into_iter()
+			.filter_map(|folder| match vault.decrypt(&folder.name, &folder.id) {
+				Ok(name) => Some(TrashedFolderData {
+					id: folder.id,
+					name,
+				}),
+				Err(err) => {
+					notify.error("Error decrypting folder name");
+					leptos_dom::error!("Error decrypting folder name: {err}");
+					None
+				},
+			})
+			.collect()
+		);
+		
+		let trashed_files = vault.with_value(|vault| trash.files.into_iter()
+			.filter_map(|file| match vault.decrypt(&file.info, &(file.folder, file.id)) {
+				Ok(info) => Some(TrashedFileData {
+					id: file.id,
+					folder: file.folder,
+					info,
+				}),
+				Err(err) => {
+					notify.error("Encountered corrupted file info");
+					leptos_dom::error!("Error decrypting file info: {err}");
+					None
+				},
+			})
+			.collect()
+		);
+		
+		set_folders(Some(trashed_folders));
+		set_files(Some(trashed_files));
+	});
+	
+	let restore_trashed_folder = move |folder: TrashedFolderData| {
+		spawn_local(async move {
+			if let Err(err) = files::restore_folder(folder.id).await {
+				handle_error(err, "restoring folder");
+				return;
+			}
+			
+			set_folders.update(|folders| {
+				if let Some(folders) = folders {
+					folders.retain(|trashed_folder| trashed_folder.id != folder.id);
+				}
+			});
+			
+			restore_folder(folder.id, folder.name);
+		});
+	};
+	
+	let restore_trashed_file = move |file: TrashedFileData| {
+		spawn_local(async move {
+			if let Err(err) = files::restore_file(file.id).await {
+				handle_error(err, "restoring file");
+				return;
+			}
+			
+			set_files.update(|files| {
+				if let Some(files) = files {
+					files.retain(|trashed_file| trashed_file.id != file.id);
+				}
+			});
+			
+			file_store.with_value(|file_store| file_store.restore_file(file.folder, FileData {
+				id: file.id,
+				info: file.info,
+			}));
+		});
+	};
+	
+	let empty_trash = move |_| {
+		spawn_local(async move {
+			if let Err(err) = files::empty_trash().await {
+				handle_error(err, "emptying trash");
+				return;
+			}
+			
+			set_folders(Some(Vec::new()));
+			set_files(Some(Vec::new()));
+		});
+	};
+	
+	let is_empty = move || {
+		folders.with(|folders| folders.as_ref().is_some_and(Vec::is_empty))
+			&& files.with(|files| files.as_ref().is_some_and(Vec::is_empty))
+	};
+	
+	view! {
+		<p class=style::label>Trash</p>
+		{move || folders.with(Option::is_none).then(|| view! {
+			<p class=style::message>Loading...</p>
+		})}
+		<Show when=is_empty>
+			<p class=style::message>The trash is empty</p>
+		</Show>
+		<For
+			each=move || folders().unwrap_or_default()
+			key=|folder| folder.id
+			children=move |folder| {
+				let name = folder.name.reveal_secret().name.clone();
+				view! {
+					<div class=style::item>
+						<p class=style::name>{name}/</p>
+						<button class=style::button on:click=move |_| restore_trashed_folder(folder.clone())>Restore</button>
+					</div>
+				}
+			}
+		/>
+		<For
+			each=move || files().unwrap_or_default()
+			key=|file| file.id
+			children=move |file| {
+				let name = file.info.reveal_secret().name.clone();
+				view! {
+					<div class=style::item>
+						<p class=style::name>{name}</p>
+						<button class=style::button on:click=move |_| restore_trashed_file(file.clone())>Restore</button>
+					</div>
+				}
+			}
+		/>
+		<button class=style::button disabled=is_empty on:click=empty_trash>Empty trash</button>
+	}
+}
@@ -2,7 +2,7 @@ use leptos::*;
 use stylance::{classes, import_style};
 use cache_bust::asset;
 
-use crate::{account::{self, CreateAccountError, LoginError}, utils::ToPrettyError, vault::{Password, Salt, Vault}};
+use crate::{account::{self, CreateAccountError, LoginData, LoginError, NewPassword, RecoverAccountError, Recovery, RegistrationMode}, utils::ToPrettyError, vault::{DerivedKeys, KdfParameters, Password, RecoveryCode, Salt, Vault}};
 
 use super::{input::{TextInput, InputType}, notify::Notify, UserData};
 
@@ -12,6 +12,7 @@ import_style!(style, "login.css");
 enum Form {
 	Login,
 	CreateAccount,
+	RecoverAccount,
 }
 
 #[component]
@@ -24,6 +25,7 @@ pub fn Login(
 		<div class={style::login_box}>
 			<LoginForm set_form set_user_data shown=move || form() == Form::Login />
 			<CreateAccountForm set_form set_user_data shown=move || form() == Form::CreateAccount />
+			<RecoverAccountForm set_form set_user_data shown=move || form() == Form::RecoverAccount />
 		</div>
 	}
 }
@@ -39,8 +41,12 @@ where
 {
 	let username = create_rw_signal(String::new());
 	let password = create_rw_signal(String::new());
+	let totp_code = create_rw_signal(String::new());
 	let username_error = create_rw_signal(None);
 	let password_error = create_rw_signal(None);
+	let totp_code_error = create_rw_signal(None);
+	// second step for accounts with two-factor authentication, the password is sent again along with the code
+	let (totp_required, set_totp_required) = create_signal(false);
 	
 	let notify = Notify::from_context();
 	
@@ -52,6 +58,10 @@ where
 		if password_error.with_untracked(Option::is_some) {
 			password_error.set(None);
 		}
+		
+		if totp_code_error.with_untracked(Option::is_some) {
+			totp_code_error.set(None);
+		}
 	};
 	
 	let login = move |()| {
@@ -75,67 +85,365 @@ where
 			return;
 		};
 		
+		let totp_code = if totp_required.get_untracked() {
+			let code = totp_code.get_untracked();
+			
+			if code.trim().is_empty() {
+				totp_code_error.set(Some("Please enter a code"));
+				return;
+			}
+			
+			Some(code)
+		} else {
+			None
+		};
+		
+		spawn_local(async move {
+			match log_in(notify, &username, &password, totp_code).await {
+				None => (),
+				Some(Err(LoginError::IncorrectPassword)) => {
+					set_totp_required(false);
+					password_error.set(Some("Incorrect username or password"));
+				},
+				Some(Err(err @ (LoginError::TooManyAttempts(_) | LoginError::AccountDisabled))) => {
+					notify.error(err.to_string());
+				},
+				Some(Err(LoginError::TotpRequired)) => {
+					set_totp_required(true);
+				},
+				Some(Err(LoginError::IncorrectTotpCode)) => {
+					totp_code_error.set(Some("Incorrect code"));
+				},
+				Some(Ok((vault, login_data))) => {
+					set_user_data(Some(UserData {
+						vault,
+						initial_folders: login_data.folders,
+					}));
+				},
+			}
+		});
+	};
+	
+	let back_to_password = move |_| {
+		set_totp_required(false);
+		totp_code.set(String::new());
+		clear_errors();
+	};
+	
+	view! {
+		<div hidden=move || !shown()>
+			<div hidden=totp_required>
+				<p class={style::prompt}>Login</p>
+				<p class={style::label}>Username</p>
+				<TextInput value={username} error={username_error} on_submit={login} />
+				<p class={style::label}>Password</p>
+				<TextInput value={password} error={password_error} input_type={InputType::Password} on_submit={login} />
+				<button class={style::button} on:click=move |_| login(())>Login</button>
+				<button class={style::link_button} on:click=move |_| set_form(Form::RecoverAccount)>Forgot password?</button>
+				<hr class={style::hr} />
+				<button
+					class=classes!(style::button, style::switch_button)
+					on:click=move |_| set_form(Form::CreateAccount)
+				>
+					Create account
+				</button>
+			</div>
+			<div hidden=move || !totp_required()>
+				<button
+					class=classes!(style::button, style::back_button)
+					on:click=back_to_password
+				>
+					<img src=asset!("/back_arrow.svg") alt="Back" />
+				</button>
+				<p class={style::prompt}>Two-factor authentication</p>
+				<p class={style::text}>Enter the code from your authenticator app, or one of your backup codes.</p>
+				<p class={style::label}>Code</p>
+				<TextInput value={totp_code} error={totp_code_error} on_submit={login} />
+				<button class={style::button} on:click=move |_| login(())>Continue</button>
+			</div>
+		</div>
+	}
+}
+
+#[component]
+fn CreateAccountForm<F>(
+	set_form: WriteSignal<Form>,
+	set_user_data: WriteSignal<Option<UserData>>,
+	shown: F,
+) -> impl IntoView
+where
+	F: Fn() -> bool + Copy + 'static
+{
+	let username = create_rw_signal(String::new());
+	let password = create_rw_signal(String::new());
+	let password_confirm = create_rw_signal(String::new());
+	let invite_code = create_rw_signal(String::new());
+	let username_error = create_rw_signal(None);
+	let password_error = create_rw_signal(None);
+	let password_confirm_error = create_rw_signal(None);
+	let invite_code_error = create_rw_signal(None);
+	let (registration_mode, set_registration_mode) = create_signal(None::<RegistrationMode>);
+	let (with_recovery_code, set_with_recovery_code) = create_signal(true);
+	// shown once the account was created, the user is only logged in after writing it down
+	let (recovery_code, set_recovery_code) = create_signal(None);
+	let created_user_data = store_value(None);
+	
+	let notify = Notify::from_context();
+	
+	let clear_errors = move || {
+		if username_error.with_untracked(Option::is_some) {
+			username_error.set(None);
+		}
+		
+		if password_error.with_untracked(Option::is_some) {
+			password_error.set(None);
+		}
+		
+		if password_confirm_error.with_untracked(Option::is_some) {
+			password_confirm_error.set(None);
+		}
+		
+		if invite_code_error.with_untracked(Option::is_some) {
+			invite_code_error.set(None);
+		}
+	};
+	
+	// only loaded once the form is shown, effects don't run while rendering on the server
+	create_effect(move |_| {
+		if !shown() || registration_mode.with_untracked(Option::is_some) {
+			return;
+		}
+		
+		spawn_local(async move {
+			match account::get_registration_mode().await {
+				Ok(mode) => set_registration_mode(Some(mode)),
+				Err(err) => leptos_dom::error!("Error retrieving registration mode: {err}"),
+			}
+		});
+	});
+	
+	let create_account = move |()| {
+		clear_errors();
+		
+		let invite_code = if registration_mode.get_untracked() == Some(RegistrationMode::Invite) {
+			let code = invite_code.get_untracked();
+			
+			if code.trim().is_empty() {
+				invite_code_error.set(Some("Please enter your invite code"));
+			}
+			
+			Some(code)
+		} else {
+			None
+		};
+		
+		let Some((username, password)) = with!(|username, password, password_confirm| {
+			if username.is_empty() {
+				username_error.set(Some("Please enter a username"));
+			}
+			
+			if password.is_empty() {
+				password_error.set(Some("Please enter a password"));
+			}
+			
+			if password_confirm.is_empty() {
+				password_confirm_error.set(Some("Please confirm your password"));
+			} else if password != password_confirm {
+				password_confirm_error.set(Some("Passwords don't match"));
+			}
+			
+			if username.is_empty() || password.is_empty() || password != password_confirm {
+				return None;
+			}
+			
+			Some((username.clone(), Password::new(password.clone())))
+		}) else {
+			return;
+		};
+		
+		if invite_code_error.with_untracked(Option::is_some) {
+			return;
+		}
+		
 		spawn_local(async move {
-			let salt = match account::get_user_salt(username.clone()).await {
+			let kdf = match account::get_kdf_parameters().await {
+				Ok(kdf) => kdf,
 				Err(err) => {
 					notify.error(err.to_pretty_error());
-					leptos_dom::error!("Error retrieving salt: {err}");
+					leptos_dom::error!("Error retrieving key derivation parameters: {err}");
 					return;
 				},
-				Ok(None) => {
-					username_error.set(Some("Unknown user"));
+			};
+			
+			let salt = match Salt::generate() {
+				Ok(salt) => salt,
+				Err(err) => {
+					notify.error("Could not generate random salt");
+					leptos_dom::error!("Error generating salt: {err}");
+					return;
+				},
+			};
+		
+			let keys = match password.derive_keys(&salt, &kdf) {
+				Ok(keys) => keys,
+				Err(err) => {
+					notify.error("Could not derive keys from password");
+					leptos_dom::error!("Error deriving keys: {err}");
+					return;
+				},
+			};
+		
+			let key_ring = match keys.new_key_ring(&username, false) {
+				Ok(key_ring) => key_ring,
+				Err(err) => {
+					notify.error("Could not generate master key");
+					leptos_dom::error!("Error generating key ring: {err}");
 					return;
 				},
-				Ok(Some(salt)) => salt,
 			};
+		
+			let recovery = if with_recovery_code.get_untracked() {
+				let code = match RecoveryCode::generate() {
+					Ok(code) => code,
+					Err(err) => {
+						notify.error("Could not generate recovery code");
+						leptos_dom::error!("Error generating recovery code: {err}");
+						return;
+					},
+				};
+			
+				let recovery_keys = code.derive_keys();
+			
+				let recovery_key_ring = Vault::open(&username, &keys, &key_ring)
+					.map_err(|err| err.to_string())
+					.and_then(|vault| vault.rewrap_key_ring(&recovery_keys).map_err(|err| err.to_string()));
 			
-			let hash = password.hash(&salt);
+				match recovery_key_ring {
+					Ok(recovery_key_ring) => Some((code, Recovery {
+						auth_key: recovery_keys.auth_key(),
+						key_ring: recovery_key_ring,
+					})),
+					Err(err) => {
+						notify.error("Could not encrypt master key");
+						leptos_dom::error!("Error wrapping key ring with recovery code: {err}");
+						return;
+					},
+				}
+			} else {
+				None
+			};
+		
+			let (code, recovery) = recovery.unzip();
+		
+			let new_password = NewPassword {
+				salt,
+				kdf,
+				auth_key: keys.auth_key(),
+				key_ring,
+			};
 			
-			match account::login(username, hash).await {
+			match account::create_account(username.clone(), new_password, recovery, invite_code).await {
 				Err(err) => {
 					notify.error(err.to_pretty_error());
-					leptos_dom::error!("Error logging in: {err}");
+					leptos_dom::error!("Error creating account: {err}");
 				},
-				Ok(Err(LoginError::UnknownUser)) => {
-					username_error.set(Some("Unknown user"));
+				Ok(Err(CreateAccountError::UsernameTaken)) => {
+					username_error.set(Some("Username is already taken"));
 				},
-				Ok(Err(LoginError::IncorrectPassword)) => {
-					password_error.set(Some("Incorrect password"));
+				// the registration mode changed since it was loaded
+				Ok(Err(CreateAccountError::RegistrationDisabled)) => {
+					set_registration_mode(Some(RegistrationMode::Disabled));
+				},
+				Ok(Err(CreateAccountError::InviteRequired)) => {
+					set_registration_mode(Some(RegistrationMode::Invite));
+					invite_code_error.set(Some("Please enter your invite code"));
+				},
+				Ok(Err(CreateAccountError::InvalidInvite)) => {
+					invite_code_error.set(Some("Invalid or expired invite code"));
 				},
 				Ok(Ok(login_data)) => {
-					let vault = Vault::new(password, salt);
+					let vault = match Vault::open(&username, &keys, &login_data.key_ring) {
+						Ok(vault) => vault,
+						Err(err) => {
+							notify.error("Could not decrypt master key");
+							leptos_dom::error!("Error decrypting key ring: {err}");
+							return;
+						},
+					};
 					
-					set_user_data(Some(UserData {
+					let user_data = UserData {
 						vault,
-						auth: login_data.auth,
-						initial_folders: login_data.folder_names,
-					}));
+						initial_folders: login_data.folders,
+					};
+					
+					match code {
+						Some(code) => {
+							created_user_data.set_value(Some(user_data));
+							set_recovery_code(Some(code.to_string()));
+						},
+						None => set_user_data(Some(user_data)),
+					}
 				},
 			}
 		});
 	};
 	
+	let continue_to_account = move |_| {
+		if let Some(user_data) = created_user_data.try_update_value(Option::take).flatten() {
+			set_user_data(Some(user_data));
+		}
+	};
+	
 	view! {
 		<div hidden=move || !shown()>
-			<p class={style::prompt}>Login</p>
-			<p class={style::label}>Username</p>
-			<TextInput value={username} error={username_error} on_submit={login} />
-			<p class={style::label}>Password</p>
-			<TextInput value={password} error={password_error} input_type={InputType::Password} on_submit={login} />
-			<button class={style::button} on:click=move |_| login(())>Login</button>
-			<hr class={style::hr} />
-			<button
-				class=classes!(style::button, style::switch_button)
-				on:click=move |_| set_form(Form::CreateAccount)
-			>
-				Create account
-			</button>
+			<div hidden=move || recovery_code.with(Option::is_none)>
+				<p class={style::prompt}>Recovery code</p>
+				<p class={style::text}>
+					"Write this code down and keep it somewhere safe. "
+					"It is the only way to get to your files if you forget your password, and it will not be shown again."
+				</p>
+				<p class={style::recovery_code}>{recovery_code}</p>
+				<button class={style::button} on:click=continue_to_account>Continue</button>
+			</div>
+			<div hidden=move || recovery_code.with(Option::is_some)>
+				<button
+					class=classes!(style::button, style::back_button)
+					on:click=move |_| set_form(Form::Login)
+				>
+					<img src=asset!("/back_arrow.svg") alt="Back" />
+				</button>
+				<p class={style::prompt}>Create account</p>
+				<p class={style::text} hidden=move || registration_mode() != Some(RegistrationMode::Disabled)>
+					"Creating accounts is disabled on this server."
+				</p>
+				<div hidden=move || registration_mode() == Some(RegistrationMode::Disabled)>
+					<p class={style::label}>Username</p>
+					<TextInput value={username} error={username_error} on_submit={create_account} />
+					<p class={style::label}>Password</p>
+					<TextInput value={password} error={password_error} input_type={InputType::Password} on_submit={create_account} />
+					<p class={style::label}>Confirm password</p>
+					<TextInput value={password_confirm} error={password_confirm_error} input_type={InputType::Password} on_submit={create_account} />
+					<div hidden=move || registration_mode() != Some(RegistrationMode::Invite)>
+						<p class={style::label}>Invite code</p>
+						<TextInput value={invite_code} error={invite_code_error} on_submit={create_account} />
+					</div>
+					<label class={style::checkbox}>
+						<input
+							type="checkbox"
+							prop:checked=with_recovery_code
+							on:change=move |event| set_with_recovery_code(event_target_checked(&event))
+						/>
+						Generate a recovery code
+					</label>
+					<button class={style::button} on:click=move |_| create_account(())>Create account</button>
+				</div>
+			</div>
 		</div>
 	}
 }
 
 #[component]
-fn CreateAccountForm<F>(
+fn RecoverAccountForm<F>(
 	set_form: WriteSignal<Form>,
 	set_user_data: WriteSignal<Option<UserData>>,
 	shown: F,
@@ -144,11 +452,17 @@ where
 	F: Fn() -> bool + 'static
 {
 	let username = create_rw_signal(String::new());
+	let recovery_code = create_rw_signal(String::new());
 	let password = create_rw_signal(String::new());
 	let password_confirm = create_rw_signal(String::new());
+	let totp_code = create_rw_signal(String::new());
 	let username_error = create_rw_signal(None);
+	let recovery_code_error = create_rw_signal(None);
 	let password_error = create_rw_signal(None);
 	let password_confirm_error = create_rw_signal(None);
+	let totp_code_error = create_rw_signal(None);
+	// only asked for once the server says the account has two-factor authentication enabled
+	let (totp_required, set_totp_required) = create_signal(false);
 	
 	let notify = Notify::from_context();
 	
@@ -157,6 +471,14 @@ where
 			username_error.set(None);
 		}
 		
+		if recovery_code_error.with_untracked(Option::is_some) {
+			recovery_code_error.set(None);
+		}
+		
+		if totp_code_error.with_untracked(Option::is_some) {
+			totp_code_error.set(None);
+		}
+		
 		if password_error.with_untracked(Option::is_some) {
 			password_error.set(None);
 		}
@@ -166,20 +488,28 @@ where
 		}
 	};
 	
-	let create_account = move |()| {
+	let recover_account = move |()| {
 		clear_errors();
 		
-		let Some((username, password)) = with!(|username, password, password_confirm| {
+		let Some((username, code, password)) = with!(|username, recovery_code, password, password_confirm| {
 			if username.is_empty() {
 				username_error.set(Some("Please enter a username"));
 			}
 			
+			let code = RecoveryCode::parse(recovery_code);
+			
+			if recovery_code.is_empty() {
+				recovery_code_error.set(Some("Please enter your recovery code"));
+			} else if code.is_none() {
+				recovery_code_error.set(Some("Invalid recovery code"));
+			}
+			
 			if password.is_empty() {
-				password_error.set(Some("Please enter a password"));
+				password_error.set(Some("Please enter a new password"));
 			}
 			
 			if password_confirm.is_empty() {
-				password_confirm_error.set(Some("Please confirm your password"));
+				password_confirm_error.set(Some("Please confirm your new password"));
 			} else if password != password_confirm {
 				password_confirm_error.set(Some("Passwords don't match"));
 			}
@@ -188,12 +518,19 @@ where
 				return None;
 			}
 			
-			Some((username.clone(), Password::new(password.clone())))
+			Some((username.clone(), code?, Password::new(password.clone())))
 		}) else {
 			return;
 		};
 		
-		let salt = match Salt::generate() {
+		let totp_code = totp_required.get_untracked().then(|| totp_code.get_untracked());
+		
+		if totp_code.as_ref().is_some_and(|code| code.trim().is_empty()) {
+			totp_code_error.set(Some("Please enter a code"));
+			return;
+		}
+		
+		let new_salt = match Salt::generate() {
 			Ok(salt) => salt,
 			Err(err) => {
 				notify.error("Could not generate random salt");
@@ -202,24 +539,92 @@ where
 			},
 		};
 		
-		let hash = password.hash(&salt);
+		let recovery_keys = code.derive_keys();
 		
 		spawn_local(async move {
-			match account::create_account(username, salt.clone(), hash).await {
+			let show_error = move |err: RecoverAccountError| match err {
+				RecoverAccountError::IncorrectRecoveryCode => recovery_code_error.set(Some("Incorrect username or recovery code")),
+				RecoverAccountError::TotpRequired => set_totp_required(true),
+				RecoverAccountError::IncorrectTotpCode => totp_code_error.set(Some("Incorrect code")),
+				err @ (RecoverAccountError::TooManyAttempts(_) | RecoverAccountError::AccountDisabled) => notify.error(err.to_string()),
+			};
+			
+			let recovery_key_ring = match account::get_recovery_key_ring(username.clone(), recovery_keys.auth_key()).await {
 				Err(err) => {
 					notify.error(err.to_pretty_error());
-					leptos_dom::error!("Error creating account: {err}");
+					leptos_dom::error!("Error retrieving recovery key ring: {err}");
+					return;
 				},
-				Ok(Err(CreateAccountError::UsernameTaken)) => {
-					username_error.set(Some("Username is already taken"));
+				Ok(Err(err)) => {
+					show_error(err);
+					return;
+				},
+				Ok(Ok(key_ring)) => key_ring,
+			};
+			
+			let kdf = match account::get_kdf_parameters().await {
+				Ok(kdf) => kdf,
+				Err(err) => {
+					notify.error(err.to_pretty_error());
+					leptos_dom::error!("Error retrieving key derivation parameters: {err}");
+					return;
 				},
+			};
+			
+			let new_keys = match password.derive_keys(&new_salt, &kdf) {
+				Ok(keys) => keys,
+				Err(err) => {
+					notify.error("Could not derive keys from password");
+					leptos_dom::error!("Error deriving keys: {err}");
+					return;
+				},
+			};
+			
+			let new_key_ring = Vault::open(&username, &recovery_keys, &recovery_key_ring)
+				.map_err(|err| err.to_string())
+				.and_then(|vault| vault.rewrap_key_ring(&new_keys).map_err(|err| err.to_string()));
+			
+			let new_key_ring = match new_key_ring {
+				Ok(key_ring) => key_ring,
+				Err(err) => {
+					notify.error("Could not decrypt master key");
+					leptos_dom::error!("Error rewrapping recovery key ring: {err}");
+					return;
+				},
+			};
+			
+			let new_password = NewPassword {
+				salt: new_salt,
+				kdf,
+				auth_key: new_keys.auth_key(),
+				key_ring: new_key_ring,
+			};
+			
+			match account::recover_account(username.clone(), recovery_keys.auth_key(), new_password, totp_code).await {
+				Err(err) => {
+					notify.error(err.to_pretty_error());
+					leptos_dom::error!("Error recovering account: {err}");
+				},
+				Ok(Err(err)) => show_error(err),
 				Ok(Ok(login_data)) => {
-					let vault = Vault::new(password, salt);
+					let vault = match Vault::open(&username, &new_keys, &login_data.key_ring) {
+						Ok(vault) => vault,
+						Err(err) => {
+							notify.error("Could not decrypt master key");
+							leptos_dom::error!("Error decrypting key ring: {err}");
+							return;
+						},
+					};
+					
+					notify.info("Password changed");
+					
+					if login_data.failed_logins > 0 {
+						notify.error(format!("There were {} failed attempts to log in since you last logged in", login_data.failed_logins));
+					}
 					
 					set_user_data(Some(UserData {
 						vault,
-						auth: login_data.auth,
-						initial_folders: login_data.folder_names,
+						initial_folders: login_data.folders,
 					}));
 				},
 			}
@@ -234,14 +639,251 @@ where
 			>
 				<img src=asset!("/back_arrow.svg") alt="Back" />
 			</button>
-			<p class={style::prompt}>Create account</p>
+			<p class={style::prompt}>Recover account</p>
 			<p class={style::label}>Username</p>
-			<TextInput value={username} error={username_error} on_submit={create_account} />
-			<p class={style::label}>Password</p>
-			<TextInput value={password} error={password_error} input_type={InputType::Password} on_submit={create_account} />
-			<p class={style::label}>Confirm password</p>
-			<TextInput value={password_confirm} error={password_confirm_error} input_type={InputType::Password} on_submit={create_account} />
-			<button class={style::button} on:click=move |_| create_account(())>Create account</button>
+			<TextInput value={username} error={username_error} on_submit={recover_account} />
+			<p class={style::label}>Recovery code</p>
+			<TextInput value={recovery_code} error={recovery_code_error} on_submit={recover_account} />
+			<p class={style::label}>New password</p>
+			<TextInput value={password} error={password_error} input_type={InputType::Password} on_submit={recover_account} />
+			<p class={style::label}>Confirm new password</p>
+			<TextInput value={password_confirm} error={password_confirm_error} input_type={InputType::Password} on_submit={recover_account} />
+			<div hidden=move || !totp_required()>
+				<p class={style::label}>Code from your authenticator app or backup code</p>
+				<TextInput value={totp_code} error={totp_code_error} on_submit={recover_account} />
+			</div>
+			<button class={style::button} on:click=move |_| recover_account(())>Set new password</button>
+		</div>
+	}
+}
+
+/// Asks for the password again once the session expired, so the user can continue where they left off.
+/// Set up with [Reauthenticate::provide] and shown with [Reauthenticate::prompt].
+#[derive(Clone, Copy, Debug)]
+pub struct Reauthenticate(RwSignal<bool>);
+
+impl Reauthenticate {
+	pub fn provide() -> Self {
+		let reauthenticate = Self(create_rw_signal(false));
+		provide_context(reauthenticate);
+		reauthenticate
+	}
+	
+	pub fn from_context() -> Self {
+		use_context().unwrap()
+	}
+	
+	pub fn prompt(&self) {
+		if !self.0.get_untracked() {
+			self.0.set(true);
+		}
+	}
+	
+	pub fn is_prompting(&self) -> bool {
+		self.0.get()
+	}
+	
+	fn done(&self) {
+		self.0.set(false);
+	}
+}
+
+#[component]
+pub fn ReauthenticatePrompt<L>(
+	username: String,
+	log_out: L,
+) -> impl IntoView
+where
+	L: Fn() + Copy + 'static,
+{
+	let username = store_value(username);
+	let password = create_rw_signal(String::new());
+	let totp_code = create_rw_signal(String::new());
+	let password_error = create_rw_signal(None);
+	let totp_code_error = create_rw_signal(None);
+	let (totp_required, set_totp_required) = create_signal(false);
+	let (is_logging_in, set_logging_in) = create_signal(false);
+	
+	let notify = Notify::from_context();
+	let reauthenticate = Reauthenticate::from_context();
+	
+	let login = move |()| {
+		if is_logging_in.get_untracked() {
+			return;
+		}
+		
+		if password_error.with_untracked(Option::is_some) {
+			password_error.set(None);
+		}
+		
+		if totp_code_error.with_untracked(Option::is_some) {
+			totp_code_error.set(None);
+		}
+		
+		let Some(password) = password.with(|password| {
+			if password.is_empty() {
+				password_error.set(Some("Please enter your password"));
+				return None;
+			}
+			
+			Some(Password::new(password.clone()))
+		}) else {
+			return;
+		};
+		
+		let totp_code = if totp_required.get_untracked() {
+			let code = totp_code.get_untracked();
+			
+			if code.trim().is_empty() {
+				totp_code_error.set(Some("Please enter a code"));
+				return;
+			}
+			
+			Some(code)
+		} else {
+			None
+		};
+		
+		set_logging_in(true);
+		
+		spawn_local(async move {
+			let result = log_in(notify, &username.get_value(), &password, totp_code).await;
+			set_logging_in(false);
+			
+			match result {
+				None => (),
+				Some(Err(LoginError::IncorrectPassword)) => {
+					password_error.set(Some("Incorrect password"));
+				},
+				Some(Err(err @ (LoginError::TooManyAttempts(_) | LoginError::AccountDisabled))) => {
+					notify.error(err.to_string());
+				},
+				Some(Err(LoginError::TotpRequired)) => {
+					set_totp_required(true);
+				},
+				Some(Err(LoginError::IncorrectTotpCode)) => {
+					totp_code_error.set(Some("Incorrect code"));
+				},
+				// the master key never changes, so the open vault keeps working with the new session
+				Some(Ok(_)) => {
+					reauthenticate.done();
+				},
+			}
+		});
+	};
+	
+	let cancel = move |_| {
+		reauthenticate.done();
+		log_out();
+	};
+	
+	view! {
+		<div class={style::overlay}>
+			<div class={style::login_box}>
+				<p class={style::prompt}>Session expired</p>
+				<p class={style::text}>"Enter the password of " {username.get_value()} " to continue."</p>
+				<p class={style::label}>Password</p>
+				<TextInput value={password} error={password_error} input_type={InputType::Password} on_submit={login} />
+				<div hidden=move || !totp_required()>
+					<p class={style::label}>Code from your authenticator app</p>
+					<TextInput value={totp_code} error={totp_code_error} on_submit={login} />
+				</div>
+				<button class={style::button} disabled=is_logging_in on:click=move |_| login(())>
+					{move || if is_logging_in() {"Logging in..."} else {"Continue"}}
+				</button>
+				<button class={style::link_button} on:click=cancel>Log out instead</button>
+			</div>
 		</div>
 	}
 }
+
+/// Derives the keys of the password, logs in and opens the vault, upgrading the key derivation parameters if needed.
+/// `totp_code` is only needed once logging in without it returned [LoginError::TotpRequired].
+/// Other errors are notified, and return `None`.
+async fn log_in(notify: Notify, username: &str, password: &Password, totp_code: Option<String>) -> Option<Result<(Vault, LoginData), LoginError>> {
+	let parameters = match account::get_login_parameters(username.to_owned()).await {
+		Err(err) => {
+			notify.error(err.to_pretty_error());
+			leptos_dom::error!("Error retrieving login parameters: {err}");
+			return None;
+		},
+		Ok(Err(err)) => return Some(Err(err)),
+		Ok(Ok(parameters)) => parameters,
+	};
+	
+	let keys = match password.derive_keys(&parameters.salt, &parameters.kdf) {
+		Ok(keys) => keys,
+		Err(err) => {
+			notify.error("Could not derive keys from password");
+			leptos_dom::error!("Error deriving keys: {err}");
+			return None;
+		},
+	};
+	let legacy_hash = parameters.legacy.then(|| password.legacy_hash(&parameters.salt));
+	
+	let new_key_ring = if parameters.has_key_ring {
+		None
+	} else {
+		// accounts from before key rings have secrets encrypted with the keys derived from the password
+		match keys.new_key_ring(username, true) {
+			Ok(key_ring) => Some(key_ring),
+			Err(err) => {
+				notify.error("Could not generate master key");
+				leptos_dom::error!("Error generating key ring: {err}");
+				return None;
+			},
+		}
+	};
+	
+	let login_data = match account::login(username.to_owned(), keys.auth_key(), legacy_hash, new_key_ring, totp_code).await {
+		Err(err) => {
+			notify.error(err.to_pretty_error());
+			leptos_dom::error!("Error logging in: {err}");
+			return None;
+		},
+		Ok(Err(err)) => return Some(Err(err)),
+		Ok(Ok(login_data)) => login_data,
+	};
+	
+	let vault = match Vault::open(username, &keys, &login_data.key_ring) {
+		Ok(vault) => vault,
+		Err(err) => {
+			notify.error("Could not decrypt master key");
+			leptos_dom::error!("Error decrypting key ring: {err}");
+			return None;
+		},
+	};
+	
+	if login_data.failed_logins > 0 {
+		notify.error(format!("There were {} failed attempts to log in since you last logged in", login_data.failed_logins));
+	}
+	
+	if parameters.new_kdf.is_stronger_than(&parameters.kdf) {
+		// logging in worked either way, the upgrade is tried again next time
+		if let Err(err) = upgrade_kdf(password, &keys, &vault, parameters.new_kdf).await {
+			leptos_dom::error!("Error upgrading key derivation parameters: {err}");
+		}
+	}
+	
+	Some(Ok((vault, login_data)))
+}
+
+/// Derives the keys of the password again with the parameters the server is configured with now.
+async fn upgrade_kdf(password: &Password, keys: &DerivedKeys, vault: &Vault, new_kdf: KdfParameters) -> Result<(), String> {
+	let salt = Salt::generate().map_err(|err| err.to_string())?;
+	let new_keys = password.derive_keys(&salt, &new_kdf).map_err(|err| err.to_string())?;
+	let key_ring = vault.rewrap_key_ring(&new_keys).map_err(|err| err.to_string())?;
+	
+	let new_password = NewPassword {
+		salt,
+		kdf: new_kdf,
+		auth_key: new_keys.auth_key(),
+		key_ring,
+	};
+	
+	match account::upgrade_kdf(keys.auth_key(), new_password).await {
+		Ok(Ok(())) => Ok(()),
+		Ok(Err(err)) => Err(err.to_string()),
+		Err(err) => Err(err.to_string()),
+	}
+}
@@ -0,0 +1,695 @@
+use leptos::*;
+use leptos_router::use_navigate;
+use stylance::{classes, import_style};
+
+use crate::{account::{self, AdminError, ChangePasswordError, DeleteAccountError, InviteInfo, NewPassword, SessionInfo, TotpEnrollment, TotpError, TotpStatus}, utils::ToPrettyError, vault::{AuthKey, Password, PasswordHash, Salt, Vault}};
+
+use super::{input::{TextInput, InputType}, notify::Notify, Reauthenticate};
+
+import_style!(style, "settings.css");
+
+#[component]
+pub fn Settings<L>(
+	vault: Vault,
+	log_out: L,
+) -> impl IntoView
+where
+	L: Fn() + Copy + 'static,
+{
+	let vault = store_value(vault);
+	
+	let password = create_rw_signal(String::new());
+	let new_password = create_rw_signal(String::new());
+	let new_password_confirm = create_rw_signal(String::new());
+	let password_error = create_rw_signal(None);
+	let new_password_error = create_rw_signal(None);
+	let new_password_confirm_error = create_rw_signal(None);
+	let (is_changing, set_changing) = create_signal(false);
+	
+	let notify = Notify::from_context();
+	let reauthenticate = Reauthenticate::from_context();
+	
+	let clear_errors = move || {
+		if password_error.with_untracked(Option::is_some) {
+			password_error.set(None);
+		}
+		
+		if new_password_error.with_untracked(Option::is_some) {
+			new_password_error.set(None);
+		}
+		
+		if new_password_confirm_error.with_untracked(Option::is_some) {
+			new_password_confirm_error.set(None);
+		}
+	};
+	
+	let clear_inputs = move || {
+		password.set(String::new());
+		new_password.set(String::new());
+		new_password_confirm.set(String::new());
+	};
+	
+	let change_password = move |()| {
+		if is_changing.get_untracked() {
+			return;
+		}
+		
+		clear_errors();
+		
+		let Some((password, new_password)) = with!(|password, new_password, new_password_confirm| {
+			if password.is_empty() {
+				password_error.set(Some("Please enter your current password"));
+			}
+			
+			if new_password.is_empty() {
+				new_password_error.set(Some("Please enter a new password"));
+			}
+			
+			if new_password_confirm.is_empty() {
+				new_password_confirm_error.set(Some("Please confirm your new password"));
+			} else if new_password != new_password_confirm {
+				new_password_confirm_error.set(Some("Passwords don't match"));
+			}
+			
+			if password.is_empty() || new_password.is_empty() || new_password != new_password_confirm {
+				return None;
+			}
+			
+			Some((Password::new(password.clone()), Password::new(new_password.clone())))
+		}) else {
+			return;
+		};
+		
+		let new_salt = match Salt::generate() {
+			Ok(salt) => salt,
+			Err(err) => {
+				notify.error("Could not generate random salt");
+				leptos_dom::error!("Error generating salt: {err}");
+				return;
+			},
+		};
+		
+		set_changing(true);
+		
+		spawn_local(async move {
+			let username = vault.with_value(|vault| vault.username().to_owned());
+			
+			let parameters = match account::get_login_parameters(username).await {
+				Ok(Ok(parameters)) => parameters,
+				Ok(Err(err)) => {
+					notify.error(err.to_string());
+					set_changing(false);
+					return;
+				},
+				Err(err) => {
+					notify.error(err.to_pretty_error());
+					leptos_dom::error!("Error retrieving login parameters: {err}");
+					set_changing(false);
+					return;
+				},
+			};
+			
+			let keys = password.derive_keys(&parameters.salt, &parameters.kdf)
+				.and_then(|keys| Ok((keys, new_password.derive_keys(&new_salt, &parameters.new_kdf)?)));
+			
+			let (keys, new_keys) = match keys {
+				Ok(keys) => keys,
+				Err(err) => {
+					notify.error("Could not derive keys from password");
+					leptos_dom::error!("Error deriving keys: {err}");
+					set_changing(false);
+					return;
+				},
+			};
+			
+			let new_key_ring = match vault.with_value(|vault| vault.rewrap_key_ring(&new_keys)) {
+				Ok(key_ring) => key_ring,
+				Err(err) => {
+					notify.error("Could not encrypt master key");
+					leptos_dom::error!("Error wrapping key ring: {err}");
+					set_changing(false);
+					return;
+				},
+			};
+			
+			let new_password = NewPassword {
+				salt: new_salt,
+				kdf: parameters.new_kdf,
+				auth_key: new_keys.auth_key(),
+				key_ring: new_key_ring,
+			};
+			
+			let legacy_hash = parameters.legacy.then(|| password.legacy_hash(&parameters.salt));
+			let result = account::change_password(keys.auth_key(), legacy_hash, new_password).await;
+			set_changing(false);
+			
+			match result {
+				Err(err) => {
+					notify.error(err.to_pretty_error());
+					leptos_dom::error!("Error changing password: {err}");
+				},
+				Ok(Err(ChangePasswordError::NotAuthenticated)) => {
+					reauthenticate.prompt();
+				},
+				Ok(Err(ChangePasswordError::IncorrectPassword)) => {
+					password_error.set(Some("Incorrect password"));
+				},
+				Ok(Err(err @ ChangePasswordError::TooManyAttempts(_))) => {
+					notify.error(err.to_string());
+				},
+				Ok(Ok(())) => {
+					// the master key stays the same, so the vault doesn't need to be opened again
+					notify.info("Password changed");
+					
+					clear_inputs();
+				},
+			}
+		});
+	};
+	
+	let (sessions, set_sessions) = create_signal(None::<Vec<SessionInfo>>);
+	let (is_signing_out, set_signing_out) = create_signal(false);
+	
+	let load_sessions = move || spawn_local(async move {
+		match account::get_sessions().await {
+			Err(err) => {
+				notify.error(err.to_pretty_error());
+				leptos_dom::error!("Error loading sessions: {err}");
+			},
+			Ok(Err(_)) => {
+				reauthenticate.prompt();
+			},
+			Ok(Ok(sessions)) => set_sessions(Some(sessions)),
+		}
+	});
+	
+	load_sessions();
+	
+	let sign_out_other_sessions = move |_| {
+		if is_signing_out.get_untracked() {
+			return;
+		}
+		
+		set_signing_out(true);
+		
+		spawn_local(async move {
+			let result = account::sign_out_other_sessions().await;
+			set_signing_out(false);
+			
+			match result {
+				Err(err) => {
+					notify.error(err.to_pretty_error());
+					leptos_dom::error!("Error signing out other sessions: {err}");
+				},
+				Ok(Err(_)) => {
+					reauthenticate.prompt();
+				},
+				Ok(Ok(())) => {
+					notify.info("Signed out all other devices");
+					load_sessions();
+				},
+			}
+		});
+	};
+	
+	let (totp_status, set_totp_status) = create_signal(None::<TotpStatus>);
+	// the secret being set up, until it is confirmed with a code
+	let (enrollment, set_enrollment) = create_signal(None::<TotpEnrollment>);
+	// shown once after enabling two-factor authentication
+	let (backup_codes, set_backup_codes) = create_signal(None::<Vec<String>>);
+	let totp_password = create_rw_signal(String::new());
+	let totp_password_error = create_rw_signal(None);
+	let totp_code = create_rw_signal(String::new());
+	let totp_code_error = create_rw_signal(None);
+	let (is_updating_totp, set_updating_totp) = create_signal(false);
+	
+	let load_totp_status = move || spawn_local(async move {
+		match account::get_totp_status().await {
+			Err(err) => {
+				notify.error(err.to_pretty_error());
+				leptos_dom::error!("Error loading two-factor authentication status: {err}");
+			},
+			Ok(Err(_)) => {
+				reauthenticate.prompt();
+			},
+			Ok(Ok(status)) => set_totp_status(Some(status)),
+		}
+	});
+	
+	load_totp_status();
+	
+	let show_totp_error = move |err: TotpError| match err {
+		TotpError::NotAuthenticated => reauthenticate.prompt(),
+		TotpError::IncorrectPassword => totp_password_error.set(Some("Incorrect password")),
+		TotpError::IncorrectCode => totp_code_error.set(Some("Incorrect code")),
+		err @ (TotpError::AlreadyEnabled | TotpError::NotEnrolling | TotpError::TooManyAttempts(_)) => {
+			notify.error(err.to_string());
+			set_enrollment(None);
+			load_totp_status();
+		},
+	};
+	
+	let start_enrollment = move |()| {
+		if is_updating_totp.get_untracked() {
+			return;
+		}
+		
+		if totp_password_error.with_untracked(Option::is_some) {
+			totp_password_error.set(None);
+		}
+		
+		let Some(password) = totp_password.with_untracked(|password| {
+			if password.is_empty() {
+				totp_password_error.set(Some("Please enter your password"));
+				return None;
+			}
+			
+			Some(Password::new(password.clone()))
+		}) else {
+			return;
+		};
+		
+		set_updating_totp(true);
+		
+		spawn_local(async move {
+			let username = vault.with_value(|vault| vault.username().to_owned());
+			
+			let Some((auth_key, legacy_hash)) = current_auth_key(notify, username, &password).await else {
+				set_updating_totp(false);
+				return;
+			};
+			
+			let result = account::start_totp_enrollment(auth_key, legacy_hash).await;
+			set_updating_totp(false);
+			
+			match result {
+				Err(err) => {
+					notify.error(err.to_pretty_error());
+					leptos_dom::error!("Error starting two-factor authentication setup: {err}");
+				},
+				Ok(Err(err)) => show_totp_error(err),
+				Ok(Ok(new_enrollment)) => {
+					totp_password.set(String::new());
+					totp_code.set(String::new());
+					set_enrollment(Some(new_enrollment));
+				},
+			}
+		});
+	};
+	
+	// confirms the secret being set up, or disables two-factor authentication if it is enabled
+	let submit_totp_code = move |()| {
+		if is_updating_totp.get_untracked() {
+			return;
+		}
+		
+		if totp_code_error.with_untracked(Option::is_some) {
+			totp_code_error.set(None);
+		}
+		
+		let code = totp_code.get_untracked();
+		
+		if code.trim().is_empty() {
+			totp_code_error.set(Some("Please enter a code"));
+			return;
+		}
+		
+		let enrolling = enrollment.with_untracked(Option::is_some);
+		
+		set_updating_totp(true);
+		
+		spawn_local(async move {
+			if enrolling {
+				let result = account::confirm_totp_enrollment(code).await;
+				set_updating_totp(false);
+				
+				match result {
+					Err(err) => {
+						notify.error(err.to_pretty_error());
+						leptos_dom::error!("Error enabling two-factor authentication: {err}");
+					},
+					Ok(Err(err)) => show_totp_error(err),
+					Ok(Ok(codes)) => {
+						notify.info("Two-factor authentication enabled");
+						totp_code.set(String::new());
+						set_enrollment(None);
+						set_backup_codes(Some(codes));
+						load_totp_status();
+					},
+				}
+			} else {
+				let result = account::disable_totp(code).await;
+				set_updating_totp(false);
+				
+				match result {
+					Err(err) => {
+						notify.error(err.to_pretty_error());
+						leptos_dom::error!("Error disabling two-factor authentication: {err}");
+					},
+					Ok(Err(err)) => show_totp_error(err),
+					Ok(Ok(())) => {
+						notify.info("Two-factor authentication disabled");
+						totp_code.set(String::new());
+						load_totp_status();
+					},
+				}
+			}
+		});
+	};
+	
+	let totp_view = move || {
+		if let Some(codes) = backup_codes() {
+			return view! {
+				<p class=style::text>
+					"Write these backup codes down and keep them somewhere safe. "
+					"Each one can be used once instead of a code from your authenticator app, and they will not be shown again."
+				</p>
+				<div class=style::backup_codes>
+					{codes.into_iter().map(|code| view! { <p class=style::backup_code>{code}</p> }).collect_view()}
+				</div>
+				<button class=style::button on:click=move |_| set_backup_codes(None)>Done</button>
+			}.into_view();
+		}
+		
+		if let Some(TotpEnrollment { uri, qr_code }) = enrollment() {
+			return view! {
+				<p class=style::text>"Scan this QR code with your authenticator app, or enter the link below into it."</p>
+				<div class=style::qr_code inner_html=qr_code />
+				<p class=style::totp_uri>{uri}</p>
+				<p class=style::label>Code from your authenticator app</p>
+				<TextInput value=totp_code error=totp_code_error on_submit=submit_totp_code />
+				<button class=style::button disabled=is_updating_totp on:click=move |_| submit_totp_code(())>
+					{move || if is_updating_totp() {"Enabling..."} else {"Enable"}}
+				</button>
+			}.into_view();
+		}
+		
+		match totp_status() {
+			None => ().into_view(),
+			Some(TotpStatus { enabled: true, backup_codes_left }) => view! {
+				<p class=style::text>"Enabled, " {backup_codes_left} " backup codes left."</p>
+				<p class=style::label>Code from your authenticator app or backup code</p>
+				<TextInput value=totp_code error=totp_code_error on_submit=submit_totp_code />
+				<button class=style::button disabled=is_updating_totp on:click=move |_| submit_totp_code(())>
+					{move || if is_updating_totp() {"Disabling..."} else {"Disable two-factor authentication"}}
+				</button>
+			}.into_view(),
+			Some(TotpStatus { enabled: false, .. }) => view! {
+				<p class=style::text>"Ask for a code from an authenticator app in addition to your password when logging in."</p>
+				<p class=style::label>Password</p>
+				<TextInput value=totp_password error=totp_password_error input_type=InputType::Password on_submit=start_enrollment />
+				<button class=style::button disabled=is_updating_totp on:click=move |_| start_enrollment(())>
+					{move || if is_updating_totp() {"Checking password..."} else {"Set up two-factor authentication"}}
+				</button>
+			}.into_view(),
+		}
+	};
+	
+	// only loaded for admins
+	let (invites, set_invites) = create_signal(None::<Vec<InviteInfo>>);
+	let (new_invite_code, set_new_invite_code) = create_signal(None::<String>);
+	let (is_creating_invite, set_creating_invite) = create_signal(false);
+	
+	let load_invites = move || spawn_local(async move {
+		match account::get_invites().await {
+			Err(err) => {
+				notify.error(err.to_pretty_error());
+				leptos_dom::error!("Error loading invites: {err}");
+			},
+			Ok(Err(AdminError::NotAuthenticated)) => {
+				reauthenticate.prompt();
+			},
+			Ok(Err(AdminError::NotAdmin)) => (),
+			Ok(Ok(loaded_invites)) => set_invites(Some(loaded_invites)),
+		}
+	});
+	
+	load_invites();
+	
+	let show_admin_error = move |err: AdminError| match err {
+		AdminError::NotAuthenticated => reauthenticate.prompt(),
+		AdminError::NotAdmin => {
+			notify.error(err.to_string());
+			set_invites(None);
+		},
+	};
+	
+	let create_invite = move |_| {
+		if is_creating_invite.get_untracked() {
+			return;
+		}
+		
+		set_creating_invite(true);
+		
+		spawn_local(async move {
+			let result = account::create_invite().await;
+			set_creating_invite(false);
+			
+			match result {
+				Err(err) => {
+					notify.error(err.to_pretty_error());
+					leptos_dom::error!("Error creating invite: {err}");
+				},
+				Ok(Err(err)) => show_admin_error(err),
+				Ok(Ok(code)) => {
+					set_new_invite_code(Some(code));
+					load_invites();
+				},
+			}
+		});
+	};
+	
+	let revoke_invite = move |id| spawn_local(async move {
+		match account::revoke_invite(id).await {
+			Err(err) => {
+				notify.error(err.to_pretty_error());
+				leptos_dom::error!("Error revoking invite: {err}");
+			},
+			Ok(Err(err)) => show_admin_error(err),
+			Ok(Ok(())) => {
+				notify.info("Invite revoked");
+				load_invites();
+			},
+		}
+	});
+	
+	let invite_view = move |invite: InviteInfo| view! {
+		<div class=style::session>
+			<p class=style::session_device>
+				"Created by " {invite.created_by.unwrap_or_else(|| "an administrator".to_owned())}
+			</p>
+			<p class=style::session_time>"Created " {format_time(invite.created_at)}</p>
+			<p class=style::session_time>"Expires " {format_time(invite.expires_at)}</p>
+			<button class=style::small_button on:click=move |_| revoke_invite(invite.id)>Revoke</button>
+		</div>
+	};
+	
+	let invites_view = move || invites().map(|invites| view! {
+		<p class=classes!(style::prompt, style::section)>Invites</p>
+		{move || new_invite_code().map(|code| view! {
+			<p class=style::text>"Send this invite code to the person who should create an account, it will not be shown again."</p>
+			<p class=style::invite_code>{code}</p>
+		})}
+		{invites.into_iter().map(invite_view).collect_view()}
+		<button class=style::button disabled=is_creating_invite on:click=create_invite>
+			{move || if is_creating_invite() {"Creating invite..."} else {"Create invite"}}
+		</button>
+	});
+	
+	// the form to delete the account is only shown after asking for confirmation
+	let (is_confirming_delete, set_confirming_delete) = create_signal(false);
+	let delete_password = create_rw_signal(String::new());
+	let delete_totp_code = create_rw_signal(String::new());
+	let delete_password_error = create_rw_signal(None);
+	let delete_totp_code_error = create_rw_signal(None);
+	let (is_deleting, set_deleting) = create_signal(false);
+	let (is_deleted, set_deleted) = create_signal(false);
+	
+	// logging out has to happen outside of the input callbacks
+	create_effect({
+		let navigate = use_navigate();
+		move |_| {
+			if is_deleted() {
+				log_out();
+				navigate("/", Default::default());
+			}
+		}
+	});
+	
+	let cancel_delete = move |_| {
+		set_confirming_delete(false);
+		delete_password.set(String::new());
+		delete_totp_code.set(String::new());
+		delete_password_error.set(None);
+		delete_totp_code_error.set(None);
+	};
+	
+	let delete_account = move |()| {
+		if is_deleting.get_untracked() {
+			return;
+		}
+		
+		if delete_password_error.with_untracked(Option::is_some) {
+			delete_password_error.set(None);
+		}
+		
+		if delete_totp_code_error.with_untracked(Option::is_some) {
+			delete_totp_code_error.set(None);
+		}
+		
+		let totp_enabled = totp_status.with_untracked(|status| status.as_ref().is_some_and(|status| status.enabled));
+		let totp_code = delete_totp_code.get_untracked();
+		
+		if totp_enabled && totp_code.trim().is_empty() {
+			delete_totp_code_error.set(Some("Please enter a code"));
+		}
+		
+		let Some(password) = delete_password.with_untracked(|password| {
+			if password.is_empty() {
+				delete_password_error.set(Some("Please enter your password"));
+				return None;
+			}
+			
+			Some(Password::new(password.clone()))
+		}) else {
+			return;
+		};
+		
+		if delete_totp_code_error.with_untracked(Option::is_some) {
+			return;
+		}
+		
+		set_deleting(true);
+		
+		spawn_local(async move {
+			let username = vault.with_value(|vault| vault.username().to_owned());
+			
+			let Some((auth_key, legacy_hash)) = current_auth_key(notify, username, &password).await else {
+				set_deleting(false);
+				return;
+			};
+			
+			let result = account::delete_account(auth_key, legacy_hash, totp_enabled.then_some(totp_code)).await;
+			set_deleting(false);
+			
+			match result {
+				Err(err) => {
+					notify.error(err.to_pretty_error());
+					leptos_dom::error!("Error deleting account: {err}");
+				},
+				Ok(Err(DeleteAccountError::NotAuthenticated)) => {
+					reauthenticate.prompt();
+				},
+				Ok(Err(DeleteAccountError::IncorrectPassword)) => {
+					delete_password_error.set(Some("Incorrect password"));
+				},
+				// two-factor authentication was enabled in another session
+				Ok(Err(DeleteAccountError::TotpRequired)) => {
+					load_totp_status();
+					delete_totp_code_error.set(Some("Please enter a code"));
+				},
+				Ok(Err(DeleteAccountError::IncorrectTotpCode)) => {
+					delete_totp_code_error.set(Some("Incorrect code"));
+				},
+				Ok(Err(err @ DeleteAccountError::TooManyAttempts(_))) => {
+					notify.error(err.to_string());
+				},
+				Ok(Ok(())) => {
+					notify.info("Account deleted");
+					set_deleted(true);
+				},
+			}
+		});
+	};
+	
+	let session_view = |session: SessionInfo| view! {
+		<div class=style::session>
+			<p class=style::session_device>
+				{session.user_agent.unwrap_or_else(|| "Unknown device".to_owned())}
+				{session.current.then_some(" (this device)")}
+			</p>
+			<p class=style::session_time>"Signed in " {format_time(session.created_at)}</p>
+			<p class=style::session_time>"Last seen " {format_time(session.last_seen_at)}</p>
+		</div>
+	};
+	
+	view! {
+		<div class=style::settings>
+			<p class=style::prompt>Change password</p>
+			<p class=style::label>Current password</p>
+			<TextInput value=password error=password_error input_type=InputType::Password on_submit=change_password />
+			<p class=style::label>New password</p>
+			<TextInput value=new_password error=new_password_error input_type=InputType::Password on_submit=change_password />
+			<p class=style::label>Confirm new password</p>
+			<TextInput value=new_password_confirm error=new_password_confirm_error input_type=InputType::Password on_submit=change_password />
+			<button class=style::button disabled=is_changing on:click=move |_| change_password(())>
+				{move || if is_changing() {"Changing password..."} else {"Change password"}}
+			</button>
+			<p class=classes!(style::prompt, style::section)>Two-factor authentication</p>
+			{totp_view}
+			<p class=classes!(style::prompt, style::section)>Sessions</p>
+			{move || sessions().map(|sessions| sessions.into_iter().map(session_view).collect_view())}
+			<button class=style::button disabled=is_signing_out on:click=sign_out_other_sessions>
+				{move || if is_signing_out() {"Signing out..."} else {"Sign out all other devices"}}
+			</button>
+			{invites_view}
+			<p class=classes!(style::prompt, style::section)>Delete account</p>
+			<div hidden=is_confirming_delete>
+				<button class=classes!(style::button, style::danger_button) on:click=move |_| set_confirming_delete(true)>
+					"Delete account"
+				</button>
+			</div>
+			<div hidden=move || !is_confirming_delete()>
+				<p class=style::text>
+					"This permanently deletes your account with all of your folders and files, and signs out all of your devices. "
+					"It can't be undone."
+				</p>
+				<p class=style::label>Password</p>
+				<TextInput value=delete_password error=delete_password_error input_type=InputType::Password on_submit=delete_account />
+				<div hidden=move || !totp_status.with(|status| status.as_ref().is_some_and(|status| status.enabled))>
+					<p class=style::label>Code from your authenticator app or backup code</p>
+					<TextInput value=delete_totp_code error=delete_totp_code_error on_submit=delete_account />
+				</div>
+				<button class=classes!(style::button, style::danger_button) disabled=is_deleting on:click=move |_| delete_account(())>
+					{move || if is_deleting() {"Deleting account..."} else {"Delete account permanently"}}
+				</button>
+				<button class=style::button disabled=is_deleting on:click=cancel_delete>Cancel</button>
+			</div>
+		</div>
+	}
+}
+
+/// Derives the auth key of the current password, which the server checks before sensitive changes to the account,
+/// with the legacy hash if the server still needs it. Errors are shown as notifications.
+async fn current_auth_key(notify: Notify, username: String, password: &Password) -> Option<(AuthKey, Option<PasswordHash>)> {
+	let parameters = match account::get_login_parameters(username).await {
+		Ok(Ok(parameters)) => parameters,
+		Ok(Err(err)) => {
+			notify.error(err.to_string());
+			return None;
+		},
+		Err(err) => {
+			notify.error(err.to_pretty_error());
+			leptos_dom::error!("Error retrieving login parameters: {err}");
+			return None;
+		},
+	};
+	
+	let keys = match password.derive_keys(&parameters.salt, &parameters.kdf) {
+		Ok(keys) => keys,
+		Err(err) => {
+			notify.error("Could not derive keys from password");
+			leptos_dom::error!("Error deriving keys: {err}");
+			return None;
+		},
+	};
+	
+	Some((keys.auth_key(), parameters.legacy.then(|| password.legacy_hash(&parameters.salt))))
+}
+
+/// Formats a unix timestamp in seconds as a local date and time.
+fn format_time(timestamp: i64) -> String {
+	let date = js_sys::Date::new(&(timestamp as f64 * 1000.0).into());
+	
+	date.to_locale_string("default", &wasm_bindgen::JsValue::UNDEFINED).into()
+}
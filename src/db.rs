@@ -1,9 +1,11 @@
-use std::{fs::create_dir_all, io, path::Path, sync::{Arc, Mutex}};
+use std::{fs::create_dir_all, io, path::Path, sync::{Arc, Mutex}, time::{SystemTime, UNIX_EPOCH}};
 use leptos::use_context;
 use rusqlite::Connection;
 use thiserror::Error;
 
-use crate::vault::{Cipher, FileInfo, FolderName, PasswordHash, Salt};
+mod migrations;
+
+use crate::{account::{InviteInfo, LoginParameters, SessionId, SessionInfo, TotpSecret}, files::{FileEntry, FileId, FolderEntry, FolderId, Trash, TrashedFile}, vault::{AuthKeyHash, Cipher, FileInfo, FolderName, KdfParameters, KeyRing, PasswordHash, Salt, Verifier}};
 
 pub struct Token(());
 
@@ -17,12 +19,38 @@ pub fn use_db() -> Database {
 
 #[derive(Error, Debug)]
 pub enum Error {
-	#[error("Error opening database file: {0}")]
+	#[error("File error: {0}")]
 	FileError(#[from] io::Error),
 	#[error("SQLite error: ${0}")]
 	SQLiteError(#[from] rusqlite::Error),
+	#[error("Database schema version {0} is newer than the supported version {}", migrations::SCHEMA_VERSION)]
+	UnsupportedSchemaVersion(u32),
+	#[error("Migration to schema version {0} failed the foreign key check")]
+	MigrationFailed(u32),
 	#[error("Not found")]
 	NotFound,
+	#[error("ID was not reserved for the user or is already used")]
+	IdNotReserved,
+}
+
+/// Overview of a user for administration.
+#[derive(Clone, Debug)]
+pub struct UserSummary {
+	pub name: String,
+	pub disabled: bool,
+	pub totp_enabled: bool,
+	pub folders: u32,
+	/// Including the ones in the trash.
+	pub files: u32,
+	pub sessions: u32,
+}
+
+/// Everything stored for a user that depends on their password.
+pub struct Credentials<'a> {
+	pub salt: &'a Salt,
+	pub kdf: KdfParameters,
+	pub auth_key_hash: &'a AuthKeyHash,
+	pub key_ring: &'a Cipher<KeyRing>,
 }
 
 #[derive(Clone, Debug)]
@@ -40,43 +68,56 @@ impl Database {
 			}
 		}
 		
-		let connection = Connection::open(path)?;
-		
-		connection.execute_batch("
-			BEGIN;
-			CREATE TABLE IF NOT EXISTS users (
-				name TEXT NOT NULL PRIMARY KEY,
-				salt BLOB NOT NULL,
-				password_hash BLOB NOT NULL
-			);
-			CREATE TABLE IF NOT EXISTS folders (
-				name BLOB NOT NULL,
-				user TEXT NOT NULL,
-				PRIMARY KEY(name),
-				FOREIGN KEY(user) REFERENCES users(name) ON UPDATE CASCADE ON DELETE CASCADE
-			);
-			CREATE TABLE IF NOT EXISTS files (
-				folder BLOB NOT NULL,
-				info BLOB NOT NULL,
-				file_id TEXT NOT NULL,
-				PRIMARY KEY(info),
-				FOREIGN KEY(folder) REFERENCES folders(name) ON UPDATE CASCADE ON DELETE CASCADE
-			);
-			COMMIT;
-		")?;
+		let mut connection = Connection::open(path)?;
+		
+		migrations::migrate(&mut connection)?;
+		
+		connection.pragma_update(None, "foreign_keys", true)?;
 		
 		Ok(Self {
 			connection: Arc::new(Mutex::new(connection)),
 		})
 	}
 	
-	pub fn insert_user(&mut self, username: &str, salt: Salt, password_hash: PasswordHash) -> Result<(), Error> {
-		let connection = self.connection.lock().unwrap();
-		let mut statement = connection.prepare_cached("INSERT INTO users (name, salt, password_hash) VALUES (?1, ?2, ?3)")?;
+	/// Uses up the invite with the code hash along with creating the user, returns `false` without creating them
+	/// if it doesn't exist or has expired.
+	pub fn insert_user(&mut self, username: &str, credentials: &Credentials, recovery: Option<(&AuthKeyHash, &Cipher<KeyRing>)>, invite: Option<&[u8; 32]>) -> Result<bool, Error> {
+		let mut connection = self.connection.lock().unwrap();
+		let transaction = connection.transaction()?;
+		
+		if let Some(code_hash) = invite {
+			let used = transaction.execute(
+				"DELETE FROM invites WHERE code_hash=?1 AND expires_at>?2",
+				(code_hash, timestamp(SystemTime::now())),
+			)?;
+			
+			if used == 0 {
+				return Ok(false);
+			}
+		}
+		
+		transaction.execute("
+			INSERT INTO users (
+				name, salt, auth_key_hash, key_ring,
+				kdf_algorithm, kdf_memory_cost, kdf_time_cost, kdf_parallelism,
+				recovery_auth_key_hash, recovery_key_ring
+			) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10)
+		", (
+			username,
+			credentials.salt.to_db(token()),
+			credentials.auth_key_hash.to_db(token()),
+			credentials.key_ring.as_bytes(),
+			credentials.kdf.algorithm,
+			credentials.kdf.memory_cost,
+			credentials.kdf.time_cost,
+			credentials.kdf.parallelism,
+			recovery.map(|(hash, _)| hash.to_db(token())),
+			recovery.map(|(_, key_ring)| key_ring.as_bytes()),
+		))?;
 		
-		statement.execute((username, salt.to_db(token()), password_hash.to_db(token())))?;
+		transaction.commit()?;
 		
-		Ok(())
+		Ok(true)
 	}
 	
 	pub fn is_user(&self, username: &str) -> Result<bool, Error> {
@@ -90,106 +131,862 @@ impl Database {
 		Ok(results.next().transpose()?.is_some())
 	}
 	
-	pub fn get_salt(&self, username: &str) -> Result<Option<Salt>, Error> {
+	/// `new_kdf` are the parameters the server is configured with, which the user should upgrade to.
+	pub fn get_login_parameters(&self, username: &str, new_kdf: KdfParameters) -> Result<Option<LoginParameters>, Error> {
 		let connection = self.connection.lock().unwrap();
-		let mut statement = connection.prepare_cached("SELECT salt FROM users WHERE name=?1")?;
+		let mut statement = connection.prepare_cached("
+			SELECT salt, kdf_algorithm, kdf_memory_cost, kdf_time_cost, kdf_parallelism, auth_key_hash IS NULL, key_ring IS NOT NULL
+				FROM users
+				WHERE name=?1
+		")?;
 		
-		let mut results = statement.query_map([username.to_string()], |row|
-			Ok(Salt::from_db(row.get(0)?, token()))
+		let mut results = statement.query_map([username], |row|
+			Ok(LoginParameters {
+				salt: Salt::from_db(row.get(0)?, token()),
+				kdf: KdfParameters {
+					algorithm: row.get(1)?,
+					memory_cost: row.get(2)?,
+					time_cost: row.get(3)?,
+					parallelism: row.get(4)?,
+				},
+				new_kdf,
+				legacy: row.get(5)?,
+				has_key_ring: row.get(6)?,
+			})
 		)?;
 		
 		Ok(results.next().transpose()?)
 	}
 	
-	pub fn get_password_hash(&self, username: &str) -> Result<Option<PasswordHash>, Error> {
+	/// Parameters for an unknown user, taken from the existing user at `index` so they can't be told apart,
+	/// only the salt is made up. Without any users they are those of a new account.
+	pub fn get_decoy_login_parameters(&self, salt: Salt, index: u32, new_kdf: KdfParameters) -> Result<LoginParameters, Error> {
 		let connection = self.connection.lock().unwrap();
-		let mut statement = connection.prepare_cached("SELECT password_hash FROM users WHERE name=?1")?;
+		let mut statement = connection.prepare_cached("
+			SELECT kdf_algorithm, kdf_memory_cost, kdf_time_cost, kdf_parallelism, auth_key_hash IS NULL, key_ring IS NOT NULL
+				FROM users
+				ORDER BY name
+				LIMIT 1 OFFSET ?1 % MAX(1, (SELECT COUNT(*) FROM users))
+		")?;
 		
-		let mut results = statement.query_map([username], |row|
-			Ok(PasswordHash::from_db(row.get(0)?, token()))
+		let mut results = statement.query_map([index], |row|
+			Ok((
+				KdfParameters {
+					algorithm: row.get(0)?,
+					memory_cost: row.get(1)?,
+					time_cost: row.get(2)?,
+					parallelism: row.get(3)?,
+				},
+				row.get(4)?,
+				row.get(5)?,
+			))
 		)?;
 		
+		let (kdf, legacy, has_key_ring) = results.next().transpose()?.unwrap_or((new_kdf, false, true));
+		
+		Ok(LoginParameters {
+			salt,
+			kdf,
+			new_kdf,
+			legacy,
+			has_key_ring,
+		})
+	}
+	
+	pub fn get_verifier(&self, username: &str) -> Result<Option<Verifier>, Error> {
+		let connection = self.connection.lock().unwrap();
+		let mut statement = connection.prepare_cached("SELECT password_hash, auth_key_hash FROM users WHERE name=?1")?;
+		
+		let mut results = statement.query_map([username], |row| {
+			let auth_key_hash: Option<String> = row.get(1)?;
+			
+			Ok(match auth_key_hash {
+				Some(hash) => Verifier::AuthKey(AuthKeyHash::from_db(hash, token())),
+				None => Verifier::Legacy(PasswordHash::from_db(row.get(0)?, token())),
+			})
+		})?;
+		
 		Ok(results.next().transpose()?)
 	}
 	
-	pub fn get_folders(&self, username: &str) -> Result<Vec<Cipher<FolderName>>, Error> {
+	/// Replaces the login credential of a user, dropping the legacy password hash if there was one.
+	pub fn set_auth_key_hash(&self, username: &str, auth_key_hash: &AuthKeyHash) -> Result<(), Error> {
+		let connection = self.connection.lock().unwrap();
+		let mut statement = connection.prepare_cached("UPDATE users SET password_hash=NULL, auth_key_hash=?2 WHERE name=?1")?;
+		
+		if statement.execute((username, auth_key_hash.to_db(token())))? == 0 {
+			return Err(Error::NotFound);
+		}
+		
+		Ok(())
+	}
+	
+	/// Replaces everything derived from the password at once.
+	pub fn change_password(&self, username: &str, credentials: &Credentials) -> Result<(), Error> {
 		let connection = self.connection.lock().unwrap();
-		let mut statement = connection.prepare_cached("SELECT name FROM folders WHERE user=?1")?;
+		let mut statement = connection.prepare_cached("
+			UPDATE users
+				SET salt=?2, password_hash=NULL, auth_key_hash=?3, key_ring=?4,
+					kdf_algorithm=?5, kdf_memory_cost=?6, kdf_time_cost=?7, kdf_parallelism=?8
+				WHERE name=?1
+		")?;
 		
-		let results = statement.query_map([username], |row| -> Result<Cipher<FolderName>, _> {
-			Ok(Cipher::<FolderName>::from_bytes(row.get(0)?))
+		if statement.execute((
+			username,
+			credentials.salt.to_db(token()),
+			credentials.auth_key_hash.to_db(token()),
+			credentials.key_ring.as_bytes(),
+			credentials.kdf.algorithm,
+			credentials.kdf.memory_cost,
+			credentials.kdf.time_cost,
+			credentials.kdf.parallelism,
+		))? == 0 {
+			return Err(Error::NotFound);
+		}
+		
+		Ok(())
+	}
+	
+	pub fn get_users(&self) -> Result<Vec<UserSummary>, Error> {
+		let connection = self.connection.lock().unwrap();
+		let mut statement = connection.prepare_cached("
+			SELECT name, disabled_at IS NOT NULL, totp_secret IS NOT NULL,
+				(SELECT COUNT(*) FROM folders WHERE folders.user=users.name),
+				(SELECT COUNT(*) FROM files JOIN folders ON files.folder=folders.id WHERE folders.user=users.name),
+				(SELECT COUNT(*) FROM sessions WHERE sessions.user=users.name)
+				FROM users
+				ORDER BY name
+		")?;
+		
+		let users = statement.query_map((), |row| Ok(UserSummary {
+			name: row.get(0)?,
+			disabled: row.get(1)?,
+			totp_enabled: row.get(2)?,
+			folders: row.get(3)?,
+			files: row.get(4)?,
+			sessions: row.get(5)?,
+		}))?;
+		
+		Ok(users.collect::<Result<_, _>>()?)
+	}
+	
+	/// Whether the user was disabled by an operator, `false` if the user doesn't exist.
+	pub fn is_user_disabled(&self, username: &str) -> Result<bool, Error> {
+		let connection = self.connection.lock().unwrap();
+		let mut statement = connection.prepare_cached("SELECT 1 FROM users WHERE name=?1 AND disabled_at IS NOT NULL")?;
+		
+		Ok(statement.exists([username])?)
+	}
+	
+	/// Disabling keeps the user from logging in, but keeps their data. Their sessions have to be deleted separately.
+	pub fn set_user_disabled(&self, username: &str, disabled: bool) -> Result<(), Error> {
+		let connection = self.connection.lock().unwrap();
+		let mut statement = connection.prepare_cached("
+			UPDATE users SET disabled_at=CASE WHEN ?2 THEN coalesce(disabled_at, ?3) ELSE NULL END
+				WHERE name=?1
+		")?;
+		
+		if statement.execute((username, disabled, timestamp(SystemTime::now())))? == 0 {
+			return Err(Error::NotFound);
+		}
+		
+		Ok(())
+	}
+	
+	/// The owner and blob id of every file, including the ones in the trash.
+	pub fn get_blobs(&self) -> Result<Vec<(String, String)>, Error> {
+		let connection = self.connection.lock().unwrap();
+		let mut statement = connection.prepare_cached("
+			SELECT folders.user, files.blob_id
+				FROM files JOIN folders ON files.folder=folders.id
+		")?;
+		
+		let blobs = statement.query_map((), |row| Ok((row.get(0)?, row.get(1)?)))?;
+		
+		Ok(blobs.collect::<Result<_, _>>()?)
+	}
+	
+	/// Runs the integrity and foreign key checks of SQLite, returns the problems they found.
+	pub fn check(&self) -> Result<Vec<String>, Error> {
+		let connection = self.connection.lock().unwrap();
+		
+		let mut problems: Vec<String> = {
+			let mut statement = connection.prepare("PRAGMA integrity_check")?;
+			let results = statement.query_map((), |row| row.get(0))?;
+			
+			results.collect::<Result<_, _>>()?
+		};
+		
+		problems.retain(|problem| problem != "ok");
+		
+		let mut statement = connection.prepare("PRAGMA foreign_key_check")?;
+		let results = statement.query_map((), |row| {
+			let table: String = row.get(0)?;
+			let row_id: Option<i64> = row.get(1)?;
+			let parent: String = row.get(2)?;
+			
+			Ok(format!("Row {} of {table} references a missing row of {parent}", row_id.map_or("?".to_owned(), |id| id.to_string())))
 		})?;
 		
-		Ok(results.collect::<Result<_, _>>()?)
+		for problem in results {
+			problems.push(problem?);
+		}
+		
+		Ok(problems)
 	}
 	
-	pub fn add_folder(&self, username: &str, folder_name: &Cipher<FolderName>) -> Result<(), Error> {
+	/// Counts a failed login attempt for the user, does nothing if the user doesn't exist.
+	pub fn record_failed_login(&self, username: &str) -> Result<(), Error> {
 		let connection = self.connection.lock().unwrap();
-		let mut statement = connection.prepare_cached("INSERT INTO folders (name, user) VALUES (?1, ?2)")?;
+		let mut statement = connection.prepare_cached("UPDATE users SET failed_logins=failed_logins+1 WHERE name=?1")?;
+		
+		statement.execute([username])?;
 		
-		let name = folder_name.as_bytes();
+		Ok(())
+	}
+	
+	/// Returns the number of failed login attempts since the last call and resets it.
+	pub fn take_failed_logins(&self, username: &str) -> Result<u32, Error> {
+		let mut connection = self.connection.lock().unwrap();
+		let transaction = connection.transaction()?;
 		
-		statement.execute((name, username))?;
+		let failed_logins = transaction.query_row("SELECT failed_logins FROM users WHERE name=?1", [username], |row| row.get(0))?;
+		transaction.execute("UPDATE users SET failed_logins=0 WHERE name=?1", [username])?;
+		
+		transaction.commit()?;
+		
+		Ok(failed_logins)
+	}
+	
+	pub fn get_key_ring(&self, username: &str) -> Result<Option<Cipher<KeyRing>>, Error> {
+		let connection = self.connection.lock().unwrap();
+		let mut statement = connection.prepare_cached("SELECT key_ring FROM users WHERE name=?1 AND key_ring IS NOT NULL")?;
+		
+		let mut results = statement.query_map([username], |row|
+			Ok(Cipher::from_bytes(row.get(0)?))
+		)?;
+		
+		Ok(results.next().transpose()?)
+	}
+	
+	/// Returns the hash of the auth key derived from the recovery code and the key ring wrapped with it,
+	/// `None` if the user doesn't exist or has no recovery code.
+	pub fn get_recovery(&self, username: &str) -> Result<Option<(AuthKeyHash, Cipher<KeyRing>)>, Error> {
+		let connection = self.connection.lock().unwrap();
+		let mut statement = connection.prepare_cached("
+			SELECT recovery_auth_key_hash, recovery_key_ring
+				FROM users
+				WHERE name=?1 AND recovery_auth_key_hash IS NOT NULL AND recovery_key_ring IS NOT NULL
+		")?;
+		
+		let mut results = statement.query_map([username], |row|
+			Ok((AuthKeyHash::from_db(row.get(0)?, token()), Cipher::from_bytes(row.get(1)?)))
+		)?;
+		
+		Ok(results.next().transpose()?)
+	}
+	
+	/// Stores the key ring of a user that doesn't have one yet, an existing one is never replaced
+	/// since secrets may already be encrypted with it.
+	pub fn init_key_ring(&self, username: &str, key_ring: &Cipher<KeyRing>) -> Result<(), Error> {
+		let connection = self.connection.lock().unwrap();
+		let mut statement = connection.prepare_cached("UPDATE users SET key_ring=?2 WHERE name=?1 AND key_ring IS NULL")?;
+		
+		statement.execute((username, key_ring.as_bytes()))?;
+		
+		Ok(())
+	}
+	
+	/// The TOTP secret of the user, `None` if two-factor authentication isn't enabled.
+	pub fn get_totp_secret(&self, username: &str) -> Result<Option<TotpSecret>, Error> {
+		let connection = self.connection.lock().unwrap();
+		let mut statement = connection.prepare_cached("SELECT totp_secret FROM users WHERE name=?1 AND totp_secret IS NOT NULL")?;
+		
+		let mut results = statement.query_map([username], |row|
+			Ok(TotpSecret::from_bytes(row.get(0)?))
+		)?;
+		
+		Ok(results.next().transpose()?)
+	}
+	
+	/// Stores a TOTP secret that is being set up, replacing one that was being set up before.
+	pub fn set_pending_totp_secret(&self, username: &str, secret: &TotpSecret) -> Result<(), Error> {
+		let connection = self.connection.lock().unwrap();
+		let mut statement = connection.prepare_cached("UPDATE users SET totp_pending_secret=?2 WHERE name=?1")?;
+		
+		if statement.execute((username, secret.as_bytes()))? == 0 {
+			return Err(Error::NotFound);
+		}
+		
+		Ok(())
+	}
+	
+	pub fn get_pending_totp_secret(&self, username: &str) -> Result<Option<TotpSecret>, Error> {
+		let connection = self.connection.lock().unwrap();
+		let mut statement = connection.prepare_cached("SELECT totp_pending_secret FROM users WHERE name=?1 AND totp_pending_secret IS NOT NULL")?;
+		
+		let mut results = statement.query_map([username], |row|
+			Ok(TotpSecret::from_bytes(row.get(0)?))
+		)?;
+		
+		Ok(results.next().transpose()?)
+	}
+	
+	/// Enables two-factor authentication with `secret` if it is still the one being set up,
+	/// and replaces the backup codes. Returns `false` if another secret is being set up by now.
+	pub fn enable_totp(&self, username: &str, secret: &TotpSecret, backup_code_hashes: &[[u8; 32]]) -> Result<bool, Error> {
+		let mut connection = self.connection.lock().unwrap();
+		let transaction = connection.transaction()?;
+		
+		let updated = transaction.execute("
+			UPDATE users SET totp_secret=?2, totp_pending_secret=NULL, totp_last_step=NULL
+				WHERE name=?1 AND totp_pending_secret=?2
+		", (username, secret.as_bytes()))?;
+		
+		if updated == 0 {
+			return Ok(false);
+		}
+		
+		transaction.execute("DELETE FROM backup_codes WHERE user=?1", [username])?;
+		
+		{
+			let mut statement = transaction.prepare("INSERT INTO backup_codes (user, code_hash) VALUES (?1, ?2)")?;
+			
+			for code_hash in backup_code_hashes {
+				statement.execute((username, code_hash))?;
+			}
+		}
+		
+		transaction.commit()?;
+		
+		Ok(true)
+	}
+	
+	pub fn disable_totp(&self, username: &str) -> Result<(), Error> {
+		let mut connection = self.connection.lock().unwrap();
+		let transaction = connection.transaction()?;
+		
+		transaction.execute("
+			UPDATE users SET totp_secret=NULL, totp_pending_secret=NULL, totp_last_step=NULL
+				WHERE name=?1
+		", [username])?;
+		transaction.execute("DELETE FROM backup_codes WHERE user=?1", [username])?;
+		
+		transaction.commit()?;
 		
 		Ok(())
 	}
 	
-	pub fn get_files(&self, username: &str, folder: &Cipher<FolderName>) -> Result<Vec<Cipher<FileInfo>>, Error> {
+	/// Records that a TOTP code of time step `step` was used, returns `false` if one of this
+	/// or a later step was already used, so the code must be rejected.
+	pub fn use_totp_step(&self, username: &str, step: u64) -> Result<bool, Error> {
 		let connection = self.connection.lock().unwrap();
 		let mut statement = connection.prepare_cached("
-			SELECT files.info
-				FROM files JOIN folders ON files.folder=folders.name
-				WHERE folders.user=?1 AND files.folder=?2
+			UPDATE users SET totp_last_step=?2
+				WHERE name=?1 AND (totp_last_step IS NULL OR totp_last_step<?2)
+		")?;
+		
+		Ok(statement.execute((username, step as i64))? != 0)
+	}
+	
+	/// Deletes the backup code with the hash, returns `false` if the user doesn't have it.
+	pub fn use_backup_code(&self, username: &str, code_hash: &[u8; 32]) -> Result<bool, Error> {
+		let connection = self.connection.lock().unwrap();
+		let mut statement = connection.prepare_cached("DELETE FROM backup_codes WHERE user=?1 AND code_hash=?2")?;
+		
+		Ok(statement.execute((username, code_hash))? != 0)
+	}
+	
+	pub fn count_backup_codes(&self, username: &str) -> Result<u32, Error> {
+		let connection = self.connection.lock().unwrap();
+		let mut statement = connection.prepare_cached("SELECT COUNT(*) FROM backup_codes WHERE user=?1")?;
+		
+		Ok(statement.query_row([username], |row| row.get(0))?)
+	}
+	
+	/// Stores an invite, also deletes expired ones.
+	pub fn insert_invite(&self, code_hash: &[u8; 32], created_by: Option<&str>, expires_at: SystemTime) -> Result<(), Error> {
+		let connection = self.connection.lock().unwrap();
+		let now = timestamp(SystemTime::now());
+		
+		connection.prepare_cached("DELETE FROM invites WHERE expires_at<=?1")?
+			.execute([now])?;
+		
+		connection.prepare_cached("INSERT INTO invites (code_hash, created_by, created_at, expires_at) VALUES (?1, ?2, ?3, ?4)")?
+			.execute((code_hash, created_by, now, timestamp(expires_at)))?;
+		
+		Ok(())
+	}
+	
+	/// Invites that haven't been used or expired yet, newest first.
+	pub fn get_invites(&self) -> Result<Vec<InviteInfo>, Error> {
+		let connection = self.connection.lock().unwrap();
+		let mut statement = connection.prepare_cached("
+			SELECT id, created_by, created_at, expires_at
+				FROM invites
+				WHERE expires_at>?1
+				ORDER BY created_at DESC, id DESC
+		")?;
+		
+		let invites = statement.query_map([timestamp(SystemTime::now())], |row| Ok(InviteInfo {
+			id: row.get(0)?,
+			created_by: row.get(1)?,
+			created_at: row.get(2)?,
+			expires_at: row.get(3)?,
+		}))?;
+		
+		Ok(invites.collect::<Result<_, _>>()?)
+	}
+	
+	/// Whether there's an unexpired invite with the code hash, without using it up.
+	pub fn is_invite_valid(&self, code_hash: &[u8; 32]) -> Result<bool, Error> {
+		let connection = self.connection.lock().unwrap();
+		let mut statement = connection.prepare_cached("SELECT id FROM invites WHERE code_hash=?1 AND expires_at>?2")?;
+		
+		let mut results = statement.query_map((code_hash, timestamp(SystemTime::now())), |_row|
+			Ok(())
+		)?;
+		
+		Ok(results.next().transpose()?.is_some())
+	}
+	
+	/// Returns `false` if there is no invite with the id.
+	pub fn delete_invite(&self, id: i64) -> Result<bool, Error> {
+		let connection = self.connection.lock().unwrap();
+		let mut statement = connection.prepare_cached("DELETE FROM invites WHERE id=?1")?;
+		
+		Ok(statement.execute([id])? != 0)
+	}
+	
+	pub fn insert_session(&self, id: SessionId, username: &str, user_agent: Option<&str>) -> Result<(), Error> {
+		let connection = self.connection.lock().unwrap();
+		let mut statement = connection.prepare_cached("
+			INSERT INTO sessions (id, user, created_at, last_seen_at, user_agent)
+				VALUES (?1, ?2, ?3, ?3, ?4)
+		")?;
+		
+		statement.execute((id.as_bytes(), username, timestamp(SystemTime::now()), user_agent))?;
+		
+		Ok(())
+	}
+	
+	/// Records that the session was seen, returns `false` if it doesn't exist, since it was revoked.
+	pub fn touch_session(&self, id: SessionId, username: &str) -> Result<bool, Error> {
+		let connection = self.connection.lock().unwrap();
+		let mut statement = connection.prepare_cached("UPDATE sessions SET last_seen_at=?3 WHERE id=?1 AND user=?2")?;
+		
+		Ok(statement.execute((id.as_bytes(), username, timestamp(SystemTime::now())))? != 0)
+	}
+	
+	/// Sessions of the user that were seen after `seen_after`, most recently seen first.
+	pub fn get_sessions(&self, username: &str, current: SessionId, seen_after: SystemTime) -> Result<Vec<SessionInfo>, Error> {
+		let connection = self.connection.lock().unwrap();
+		let mut statement = connection.prepare_cached("
+			SELECT id, created_at, last_seen_at, user_agent
+				FROM sessions
+				WHERE user=?1 AND last_seen_at>?2
+				ORDER BY last_seen_at DESC
 		")?;
 		
-		let folder = folder.as_bytes();
+		let sessions = statement.query_map((username, timestamp(seen_after)), |row| Ok(SessionInfo {
+			current: SessionId::from_bytes(row.get(0)?) == current,
+			created_at: row.get(1)?,
+			last_seen_at: row.get(2)?,
+			user_agent: row.get(3)?,
+		}))?;
+		
+		Ok(sessions.collect::<Result<_, _>>()?)
+	}
+	
+	pub fn delete_session(&self, id: SessionId, username: &str) -> Result<(), Error> {
+		let connection = self.connection.lock().unwrap();
+		let mut statement = connection.prepare_cached("DELETE FROM sessions WHERE id=?1 AND user=?2")?;
+		
+		statement.execute((id.as_bytes(), username))?;
+		
+		Ok(())
+	}
+	
+	/// Deletes all sessions of the user except `except`, returns how many were deleted.
+	pub fn delete_sessions(&self, username: &str, except: Option<SessionId>) -> Result<usize, Error> {
+		let connection = self.connection.lock().unwrap();
+		let mut statement = connection.prepare_cached("DELETE FROM sessions WHERE user=?1 AND (?2 IS NULL OR id!=?2)")?;
+		
+		Ok(statement.execute((username, except.as_ref().map(SessionId::as_bytes)))?)
+	}
+	
+	/// Deletes the sessions of all users that weren't seen since `seen_before`, their tokens have expired by then.
+	pub fn delete_expired_sessions(&self, seen_before: SystemTime) -> Result<usize, Error> {
+		let connection = self.connection.lock().unwrap();
+		let mut statement = connection.prepare_cached("DELETE FROM sessions WHERE last_seen_at<=?1")?;
 		
-		let results = statement.query_map((username, folder), |row| {
-			Ok(Cipher::<FileInfo>::from_bytes(row.get(0)?))
+		Ok(statement.execute([timestamp(seen_before)])?)
+	}
+	
+	pub fn get_folders(&self, username: &str) -> Result<Vec<FolderEntry>, Error> {
+		let connection = self.connection.lock().unwrap();
+		let mut statement = connection.prepare_cached("SELECT id, name FROM folders WHERE user=?1 AND deleted_at IS NULL")?;
+		
+		let results = statement.query_map([username], |row| {
+			Ok(FolderEntry {
+				id: FolderId::from_bytes(row.get(0)?),
+				name: Cipher::<FolderName>::from_bytes(row.get(1)?),
+			})
 		})?;
 		
 		Ok(results.collect::<Result<_, _>>()?)
 	}
 	
-	pub fn add_file(&self, username: &str, folder: &Cipher<FolderName>, file_info: &Cipher<FileInfo>, file_id: &str) -> Result<(), Error> {
+	/// Reserves a newly generated folder or file id for the user, also deletes reservations made before `pruned_before`.
+	pub fn reserve_id(&self, username: &str, id: &[u8], pruned_before: SystemTime) -> Result<(), Error> {
+		let connection = self.connection.lock().unwrap();
+		
+		connection.prepare_cached("DELETE FROM id_reservations WHERE created_at<?1")?
+			.execute([timestamp(pruned_before)])?;
+		
+		connection.prepare_cached("INSERT INTO id_reservations (id, user, created_at) VALUES (?1, ?2, ?3)")?
+			.execute((id, username, timestamp(SystemTime::now())))?;
+		
+		Ok(())
+	}
+	
+	pub fn is_id_reserved(&self, username: &str, id: &[u8]) -> Result<bool, Error> {
 		let connection = self.connection.lock().unwrap();
-		let mut statement = connection.prepare_cached("SELECT 1 FROM folders WHERE folders.user=?1 AND folders.name=?2")?;
+		let mut statement = connection.prepare_cached("SELECT 1 FROM id_reservations WHERE id=?1 AND user=?2")?;
 		
-		let folder = folder.as_bytes();
+		Ok(statement.exists((id, username))?)
+	}
+	
+	/// Uses up the reservation of `folder_id`, which has to be reserved for the user with [Database::reserve_id].
+	pub fn add_folder(&self, username: &str, folder_id: FolderId, folder_name: &Cipher<FolderName>) -> Result<(), Error> {
+		let mut connection = self.connection.lock().unwrap();
+		let transaction = connection.transaction()?;
+		
+		if !take_id_reservation(&transaction, username, folder_id.as_bytes())? {
+			return Err(Error::IdNotReserved);
+		}
+		
+		transaction.prepare_cached("INSERT INTO folders (id, user, name) VALUES (?1, ?2, ?3)")?
+			.execute((folder_id.as_bytes(), username, folder_name.as_bytes()))?;
+		
+		transaction.commit()?;
+		
+		Ok(())
+	}
+	
+	pub fn rename_folder(&self, username: &str, folder: FolderId, new_name: &Cipher<FolderName>) -> Result<(), Error> {
+		let connection = self.connection.lock().unwrap();
+		let mut statement = connection.prepare_cached("UPDATE folders SET name=?3 WHERE user=?1 AND id=?2 AND deleted_at IS NULL")?;
 		
-		let mut folder_result = statement.query((username, folder))?;
+		let new_name = new_name.as_bytes();
 		
-		if folder_result.next()?.is_none() {
+		if statement.execute((username, folder.as_bytes(), new_name))? == 0 {
 			return Err(Error::NotFound);
 		}
 		
-		let mut statement = connection.prepare_cached("INSERT INTO files (folder, info, file_id) VALUES (?1, ?2, ?3)")?;
+		Ok(())
+	}
+	
+	/// Whether the folder exists, belongs to the user and isn't in the trash.
+	pub fn has_folder(&self, username: &str, folder: FolderId) -> Result<bool, Error> {
+		let connection = self.connection.lock().unwrap();
+		let mut statement = connection.prepare_cached("SELECT 1 FROM folders WHERE user=?1 AND id=?2 AND deleted_at IS NULL")?;
 		
-		let file_info = file_info.as_bytes();
+		Ok(statement.exists((username, folder.as_bytes()))?)
+	}
+	
+	pub fn get_files(&self, username: &str, folder: FolderId) -> Result<Vec<FileEntry>, Error> {
+		let connection = self.connection.lock().unwrap();
+		let mut statement = connection.prepare_cached("
+			SELECT files.id, files.info
+				FROM files JOIN folders ON files.folder=folders.id
+				WHERE folders.user=?1 AND files.folder=?2
+					AND folders.deleted_at IS NULL AND files.deleted_at IS NULL
+		")?;
 		
-		statement.execute((
-			folder,
-			file_info,
-			file_id
-		))?;
+		let results = statement.query_map((username, folder.as_bytes()), |row| {
+			Ok(FileEntry {
+				id: FileId::from_bytes(row.get(0)?),
+				info: Cipher::<FileInfo>::from_bytes(row.get(1)?),
+			})
+		})?;
+		
+		Ok(results.collect::<Result<_, _>>()?)
+	}
+	
+	/// Uses up the reservation of `file_id`, which has to be reserved for the user with [Database::reserve_id].
+	pub fn add_file(&self, username: &str, folder: FolderId, file_id: FileId, file_info: &Cipher<FileInfo>, blob_id: &str) -> Result<(), Error> {
+		let mut connection = self.connection.lock().unwrap();
+		let transaction = connection.transaction()?;
+		
+		let mut statement = transaction.prepare_cached("SELECT 1 FROM folders WHERE folders.user=?1 AND folders.id=?2 AND folders.deleted_at IS NULL")?;
+		
+		if !statement.exists((username, folder.as_bytes()))? {
+			return Err(Error::NotFound);
+		}
+		
+		drop(statement);
+		
+		if !take_id_reservation(&transaction, username, file_id.as_bytes())? {
+			return Err(Error::IdNotReserved);
+		}
+		
+		transaction.prepare_cached("INSERT INTO files (id, folder, info, blob_id) VALUES (?1, ?2, ?3, ?4)")?
+			.execute((
+				file_id.as_bytes(),
+				folder.as_bytes(),
+				file_info.as_bytes(),
+				blob_id
+			))?;
+		
+		transaction.commit()?;
 		
 		Ok(())
 	}
 	
-	pub fn get_file_id(&self, username: &str, file: &Cipher<FileInfo>) -> Result<String, Error> {
+	pub fn get_blob_id(&self, username: &str, file: FileId) -> Result<String, Error> {
 		let connection = self.connection.lock().unwrap();
 		let mut statement = connection.prepare_cached("
-			SELECT file_id
-				FROM files JOIN folders ON files.folder=folders.name
-				WHERE folders.user=?1 AND files.info=?2
+			SELECT files.blob_id
+				FROM files JOIN folders ON files.folder=folders.id
+				WHERE folders.user=?1 AND files.id=?2
+					AND folders.deleted_at IS NULL AND files.deleted_at IS NULL
 			")?;
 		
-		let file_info = file.as_bytes();
-		
-		let mut results = statement.query_map((username, file_info), |row| {
+		let mut results = statement.query_map((username, file.as_bytes()), |row| {
 			row.get(0)
 		})?;
 		
 		results.next().transpose()?.ok_or(Error::NotFound)
 	}
+	
+	pub fn trash_file(&self, username: &str, file: FileId) -> Result<(), Error> {
+		let connection = self.connection.lock().unwrap();
+		let mut statement = connection.prepare_cached("
+			UPDATE files SET deleted_at=?3
+				WHERE id=?2 AND deleted_at IS NULL
+					AND folder IN (SELECT id FROM folders WHERE user=?1 AND deleted_at IS NULL)
+		")?;
+		
+		if statement.execute((username, file.as_bytes(), timestamp(SystemTime::now())))? == 0 {
+			return Err(Error::NotFound);
+		}
+		
+		Ok(())
+	}
+	
+	pub fn trash_folder(&self, username: &str, folder: FolderId) -> Result<(), Error> {
+		let connection = self.connection.lock().unwrap();
+		let mut statement = connection.prepare_cached("UPDATE folders SET deleted_at=?3 WHERE user=?1 AND id=?2 AND deleted_at IS NULL")?;
+		
+		if statement.execute((username, folder.as_bytes(), timestamp(SystemTime::now())))? == 0 {
+			return Err(Error::NotFound);
+		}
+		
+		Ok(())
+	}
+	
+	/// Returns trashed folders and trashed files in folders that aren't trashed themselves, most recently deleted first.
+	pub fn get_trash(&self, username: &str) -> Result<Trash, Error> {
+		let connection = self.connection.lock().unwrap();
+		let mut statement = connection.prepare_cached("
+			SELECT id, name
+				FROM folders
+				WHERE user=?1 AND deleted_at IS NOT NULL
+				ORDER BY deleted_at DESC
+		")?;
+		
+		let folders = statement.query_map([username], |row| {
+			Ok(FolderEntry {
+				id: FolderId::from_bytes(row.get(0)?),
+				name: Cipher::<FolderName>::from_bytes(row.get(1)?),
+			})
+		})?.collect::<Result<_, _>>()?;
+		
+		let mut statement = connection.prepare_cached("
+			SELECT files.id, files.folder, files.info
+				FROM files JOIN folders ON files.folder=folders.id
+				WHERE folders.user=?1 AND folders.deleted_at IS NULL AND files.deleted_at IS NOT NULL
+				ORDER BY files.deleted_at DESC
+		")?;
+		
+		let files = statement.query_map([username], |row| {
+			Ok(TrashedFile {
+				id: FileId::from_bytes(row.get(0)?),
+				folder: FolderId::from_bytes(row.get(1)?),
+				info: Cipher::<FileInfo>::from_bytes(row.get(2)?),
+			})
+		})?.collect::<Result<_, _>>()?;
+		
+		Ok(Trash {
+			folders,
+			files,
+		})
+	}
+	
+	pub fn restore_file(&self, username: &str, file: FileId) -> Result<(), Error> {
+		let connection = self.connection.lock().unwrap();
+		let mut statement = connection.prepare_cached("
+			UPDATE files SET deleted_at=NULL
+				WHERE id=?2 AND deleted_at IS NOT NULL
+					AND folder IN (SELECT id FROM folders WHERE user=?1 AND deleted_at IS NULL)
+		")?;
+		
+		if statement.execute((username, file.as_bytes()))? == 0 {
+			return Err(Error::NotFound);
+		}
+		
+		Ok(())
+	}
+	
+	pub fn restore_folder(&self, username: &str, folder: FolderId) -> Result<(), Error> {
+		let connection = self.connection.lock().unwrap();
+		let mut statement = connection.prepare_cached("UPDATE folders SET deleted_at=NULL WHERE user=?1 AND id=?2 AND deleted_at IS NOT NULL")?;
+		
+		if statement.execute((username, folder.as_bytes()))? == 0 {
+			return Err(Error::NotFound);
+		}
+		
+		Ok(())
+	}
+	
+	pub fn empty_trash(&self, username: &str, remove_blob: impl FnMut(&str) -> Result<(), io::Error>) -> Result<(), Error> {
+		self.purge(Some(username), i64::MAX, remove_blob)?;
+		
+		Ok(())
+	}
+	
+	/// Deletes the user with everything that references them, then calls `remove_blob` for the blob of each of their files.
+	/// Blobs that couldn't be removed are only logged, since nothing references them anymore.
+	/// Returns `false` if the user doesn't exist.
+	pub fn delete_user(&self, username: &str, mut remove_blob: impl FnMut(&str) -> Result<(), io::Error>) -> Result<bool, Error> {
+		let blob_ids: Vec<String> = {
+			let mut connection = self.connection.lock().unwrap();
+			let transaction = connection.transaction()?;
+			
+			let blob_ids = {
+				let mut statement = transaction.prepare_cached("
+					SELECT files.blob_id
+						FROM files JOIN folders ON files.folder=folders.id
+						WHERE folders.user=?1
+				")?;
+				
+				let results = statement.query_map([username], |row| row.get(0))?;
+				
+				results.collect::<Result<_, _>>()?
+			};
+			
+			// folders, files, sessions and backup codes are deleted by ON DELETE CASCADE
+			if transaction.execute("DELETE FROM users WHERE name=?1", [username])? == 0 {
+				return Ok(false);
+			}
+			
+			transaction.commit()?;
+			
+			blob_ids
+		};
+		
+		for blob_id in blob_ids {
+			if let Err(err) = remove_blob(&blob_id) {
+				eprintln!("Could not delete file {blob_id} of deleted user {username}: {err}");
+			}
+		}
+		
+		Ok(true)
+	}
+	
+	/// Permanently deletes everything that was moved to the trash before `deleted_before`, for all users.
+	/// Returns the number of deleted files.
+	pub fn purge_trash(&self, deleted_before: SystemTime, remove_blob: impl FnMut(&str) -> Result<(), io::Error>) -> Result<usize, Error> {
+		self.purge(None, timestamp(deleted_before), remove_blob)
+	}
+	
+	/// Deletes the rows first and only calls `remove_blob` once that was committed, like [Database::delete_user],
+	/// so that a failed commit never leaves rows without their blobs. Folders are only deleted once they contain no more files.
+	fn purge(&self, username: Option<&str>, deleted_before: i64, mut remove_blob: impl FnMut(&str) -> Result<(), io::Error>) -> Result<usize, Error> {
+		let blob_ids: Vec<String> = {
+			let mut connection = self.connection.lock().unwrap();
+			let transaction = connection.transaction()?;
+			
+			let blob_ids = {
+				let mut statement = transaction.prepare_cached("
+					DELETE FROM files
+						WHERE folder IN (SELECT id FROM folders WHERE ?1 IS NULL OR user=?1)
+							AND (deleted_at<=?2 OR folder IN (SELECT id FROM folders WHERE deleted_at<=?2))
+						RETURNING blob_id
+				")?;
+				
+				let results = statement.query_map((username, deleted_before), |row| row.get(0))?;
+				
+				results.collect::<Result<_, _>>()?
+			};
+			
+			transaction.execute("
+				DELETE FROM folders
+					WHERE (?1 IS NULL OR user=?1) AND deleted_at<=?2
+						AND NOT EXISTS (SELECT 1 FROM files WHERE files.folder=folders.id)
+			", (username, deleted_before))?;
+			
+			transaction.commit()?;
+			
+			blob_ids
+		};
+		
+		// nothing references the blobs anymore, so ones that couldn't be removed are only logged
+		for blob_id in &blob_ids {
+			if let Err(err) = remove_blob(blob_id) {
+				eprintln!("Could not delete file {blob_id}: {err}");
+			}
+		}
+		
+		Ok(blob_ids.len())
+	}
+}
+
+/// Deletes the reservation of the id, returns whether it was reserved for the user.
+fn take_id_reservation(connection: &Connection, username: &str, id: &[u8]) -> Result<bool, Error> {
+	let mut statement = connection.prepare_cached("DELETE FROM id_reservations WHERE id=?1 AND user=?2")?;
+	
+	Ok(statement.execute((id, username))? == 1)
+}
+
+fn timestamp(time: SystemTime) -> i64 {
+	time.duration_since(UNIX_EPOCH)
+		.map(|duration| duration.as_secs() as i64)
+		.unwrap_or(0)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	
+	#[test]
+	fn unknown_users_look_like_existing_ones() {
+		let db = Database::open(":memory:").unwrap();
+		let new_kdf = KdfParameters {
+			memory_cost: 65536,
+			..KdfParameters::default()
+		};
+		
+		// an account from before auth keys, key rings and configurable kdf parameters
+		db.connection.lock().unwrap().execute(
+			"INSERT INTO users (name, salt, password_hash) VALUES ('alice', ?1, ?2)",
+			([0u8; 32].as_slice(), [1u8; 64].as_slice()),
+		).unwrap();
+		
+		let legacy = db.get_login_parameters("alice", new_kdf).unwrap().unwrap();
+		
+		for index in [0, 1, u32::MAX] {
+			let unknown = db.get_decoy_login_parameters(Salt::from_db([2; 32], token()), index, new_kdf).unwrap();
+			
+			assert_eq!(unknown.kdf, legacy.kdf);
+			assert_eq!(unknown.new_kdf, legacy.new_kdf);
+			assert_eq!(unknown.legacy, legacy.legacy);
+			assert_eq!(unknown.has_key_ring, legacy.has_key_ring);
+		}
+		
+		assert!(legacy.legacy);
+		assert!(!legacy.has_key_ring);
+		assert_ne!(legacy.kdf, new_kdf);
+	}
 }
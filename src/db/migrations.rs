@@ -0,0 +1,298 @@
+use rusqlite::Connection;
+
+use super::Error;
+
+/// Schema migrations, applied in order.
+/// Migration `n` (counting from 0) upgrades a database from `user_version` `n` to `n + 1`.
+/// Existing migrations must never be changed, add a new one instead.
+const MIGRATIONS: &[&str] = &[
+	// 1: initial schema, also adopts databases created before versioning was introduced
+	"
+		CREATE TABLE IF NOT EXISTS users (
+			name TEXT NOT NULL PRIMARY KEY,
+			salt BLOB NOT NULL,
+			password_hash BLOB NOT NULL
+		);
+		CREATE TABLE IF NOT EXISTS folders (
+			name BLOB NOT NULL,
+			user TEXT NOT NULL,
+			PRIMARY KEY(name),
+			FOREIGN KEY(user) REFERENCES users(name) ON UPDATE CASCADE ON DELETE CASCADE
+		);
+		CREATE TABLE IF NOT EXISTS files (
+			folder BLOB NOT NULL,
+			info BLOB NOT NULL,
+			file_id TEXT NOT NULL,
+			PRIMARY KEY(info),
+			FOREIGN KEY(folder) REFERENCES folders(name) ON UPDATE CASCADE ON DELETE CASCADE
+		);
+	",
+	// 2: random IDs as primary keys for folders and files
+	"
+		CREATE TABLE new_folders (
+			id BLOB NOT NULL,
+			user TEXT NOT NULL,
+			name BLOB NOT NULL,
+			PRIMARY KEY(id),
+			FOREIGN KEY(user) REFERENCES users(name) ON UPDATE CASCADE ON DELETE CASCADE
+		);
+		INSERT INTO new_folders (id, user, name)
+			SELECT randomblob(16), user, name FROM folders;
+		CREATE TABLE new_files (
+			id BLOB NOT NULL,
+			folder BLOB NOT NULL,
+			info BLOB NOT NULL,
+			blob_id TEXT NOT NULL,
+			PRIMARY KEY(id),
+			FOREIGN KEY(folder) REFERENCES new_folders(id) ON UPDATE CASCADE ON DELETE CASCADE
+		);
+		INSERT INTO new_files (id, folder, info, blob_id)
+			SELECT randomblob(16), new_folders.id, files.info, files.file_id
+				FROM files JOIN new_folders ON files.folder=new_folders.name;
+		DROP TABLE files;
+		DROP TABLE folders;
+		ALTER TABLE new_folders RENAME TO folders;
+		ALTER TABLE new_files RENAME TO files;
+	",
+	// 3: trash, deleted_at is a unix timestamp in seconds or NULL if not deleted
+	"
+		ALTER TABLE folders ADD COLUMN deleted_at INTEGER;
+		ALTER TABLE files ADD COLUMN deleted_at INTEGER;
+	",
+	// 4: Argon2id hash of the auth key, replaces the SHA-512 password hash once a user logs in
+	"
+		CREATE TABLE new_users (
+			name TEXT NOT NULL PRIMARY KEY,
+			salt BLOB NOT NULL,
+			password_hash BLOB,
+			auth_key_hash TEXT,
+			CHECK(password_hash IS NOT NULL OR auth_key_hash IS NOT NULL)
+		);
+		INSERT INTO new_users (name, salt, password_hash)
+			SELECT name, salt, password_hash FROM users;
+		DROP TABLE users;
+		ALTER TABLE new_users RENAME TO users;
+	",
+	// 5: master key ring wrapped with the key derived from the password, NULL until the user logs in after it was introduced
+	"
+		ALTER TABLE users ADD COLUMN key_ring BLOB;
+	",
+	// 6: second copy of the key ring wrapped with a recovery code, with the hash of the auth key derived from it
+	"
+		ALTER TABLE users ADD COLUMN recovery_auth_key_hash TEXT;
+		ALTER TABLE users ADD COLUMN recovery_key_ring BLOB;
+	",
+	// 7: parameters the keys of each user were derived with, existing accounts used the defaults of the argon2 crate
+	"
+		ALTER TABLE users ADD COLUMN kdf_algorithm TEXT NOT NULL DEFAULT 'argon2id';
+		ALTER TABLE users ADD COLUMN kdf_memory_cost INTEGER NOT NULL DEFAULT 19456;
+		ALTER TABLE users ADD COLUMN kdf_time_cost INTEGER NOT NULL DEFAULT 2;
+		ALTER TABLE users ADD COLUMN kdf_parallelism INTEGER NOT NULL DEFAULT 1;
+	",
+	// 8: sessions referenced by auth tokens, so tokens from before are no longer valid
+	"
+		CREATE TABLE sessions (
+			id BLOB NOT NULL,
+			user TEXT NOT NULL,
+			created_at INTEGER NOT NULL,
+			last_seen_at INTEGER NOT NULL,
+			user_agent TEXT,
+			PRIMARY KEY(id),
+			FOREIGN KEY(user) REFERENCES users(name) ON UPDATE CASCADE ON DELETE CASCADE
+		);
+		CREATE INDEX sessions_user ON sessions(user);
+	",
+	// 9: failed login attempts since the last successful login, shown to the user on their next login
+	"
+		ALTER TABLE users ADD COLUMN failed_logins INTEGER NOT NULL DEFAULT 0;
+	",
+	// 10: TOTP two-factor authentication, the secret being set up is only used once a code generated with it was entered,
+	// the last time step a code was used for keeps codes from being replayed, backup codes are HMACs keyed with the pepper
+	"
+		ALTER TABLE users ADD COLUMN totp_secret BLOB;
+		ALTER TABLE users ADD COLUMN totp_pending_secret BLOB;
+		ALTER TABLE users ADD COLUMN totp_last_step INTEGER;
+		CREATE TABLE backup_codes (
+			user TEXT NOT NULL,
+			code_hash BLOB NOT NULL,
+			PRIMARY KEY(user, code_hash),
+			FOREIGN KEY(user) REFERENCES users(name) ON UPDATE CASCADE ON DELETE CASCADE
+		);
+	",
+	// 11: single use invite codes for creating an account while registration is invite only, stored as SHA-256 hashes,
+	// ids aren't reused so revoking an invite that was used already can't hit another one,
+	// created_by is NULL for invites that weren't created by a user, times are unix timestamps in seconds
+	"
+		CREATE TABLE invites (
+			id INTEGER NOT NULL PRIMARY KEY AUTOINCREMENT,
+			code_hash BLOB NOT NULL UNIQUE,
+			created_by TEXT,
+			created_at INTEGER NOT NULL,
+			expires_at INTEGER NOT NULL,
+			FOREIGN KEY(created_by) REFERENCES users(name) ON UPDATE CASCADE ON DELETE SET NULL
+		);
+	",
+	// 12: users disabled by an operator can't log in, unix timestamp in seconds or NULL if not disabled
+	"
+		ALTER TABLE users ADD COLUMN disabled_at INTEGER;
+	",
+	// 13: ids the server generated for new folders and files, only the user they were generated for can create one with it,
+	// created_at is a unix timestamp in seconds, so reservations that are never used can be pruned
+	"
+		CREATE TABLE id_reservations (
+			id BLOB NOT NULL,
+			user TEXT NOT NULL,
+			created_at INTEGER NOT NULL,
+			PRIMARY KEY(id),
+			FOREIGN KEY(user) REFERENCES users(name) ON UPDATE CASCADE ON DELETE CASCADE
+		);
+	",
+];
+
+pub const SCHEMA_VERSION: u32 = MIGRATIONS.len() as u32;
+
+/// Brings the schema up to [SCHEMA_VERSION], running each outstanding migration in its own transaction.
+/// Disables foreign keys, since migrations may need to rebuild tables, they have to be enabled again afterwards.
+pub fn migrate(connection: &mut Connection) -> Result<(), Error> {
+	// rebuilding a table would otherwise cascade into deleting everything that references it
+	connection.pragma_update(None, "foreign_keys", false)?;
+	
+	let version: u32 = connection.pragma_query_value(None, "user_version", |row| row.get(0))?;
+	
+	if version > SCHEMA_VERSION {
+		return Err(Error::UnsupportedSchemaVersion(version));
+	}
+	
+	for (index, migration) in MIGRATIONS.iter().enumerate().skip(version as usize) {
+		let transaction = connection.transaction()?;
+		
+		transaction.execute_batch(migration)?;
+		
+		let mut statement = transaction.prepare("PRAGMA foreign_key_check")?;
+		if statement.exists(())? {
+			return Err(Error::MigrationFailed(index as u32 + 1));
+		}
+		drop(statement);
+		
+		transaction.pragma_update(None, "user_version", index as u32 + 1)?;
+		transaction.commit()?;
+	}
+	
+	Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	
+	/// A database as it was created before schema versioning was introduced.
+	const VERSION_0_FIXTURE: &str = "
+		CREATE TABLE users (
+			name TEXT NOT NULL PRIMARY KEY,
+			salt BLOB NOT NULL,
+			password_hash BLOB NOT NULL
+		);
+		CREATE TABLE folders (
+			name BLOB NOT NULL,
+			user TEXT NOT NULL,
+			PRIMARY KEY(name),
+			FOREIGN KEY(user) REFERENCES users(name) ON UPDATE CASCADE ON DELETE CASCADE
+		);
+		CREATE TABLE files (
+			folder BLOB NOT NULL,
+			info BLOB NOT NULL,
+			file_id TEXT NOT NULL,
+			PRIMARY KEY(info),
+			FOREIGN KEY(folder) REFERENCES folders(name) ON UPDATE CASCADE ON DELETE CASCADE
+		);
+		INSERT INTO users VALUES ('alice', x'00', x'01');
+		INSERT INTO users VALUES ('bob', x'02', x'03');
+		INSERT INTO folders VALUES (x'a0', 'alice');
+		INSERT INTO folders VALUES (x'a1', 'alice');
+		INSERT INTO folders VALUES (x'b0', 'bob');
+		INSERT INTO files VALUES (x'a0', x'f0', 'blob0');
+		INSERT INTO files VALUES (x'a0', x'f1', 'blob1');
+		INSERT INTO files VALUES (x'b0', x'f2', 'blob2');
+	";
+	
+	fn user_version(connection: &Connection) -> u32 {
+		connection.pragma_query_value(None, "user_version", |row| row.get(0)).unwrap()
+	}
+	
+	#[test]
+	fn migrates_empty_database() {
+		let mut connection = Connection::open_in_memory().unwrap();
+		
+		migrate(&mut connection).unwrap();
+		
+		assert_eq!(user_version(&connection), SCHEMA_VERSION);
+	}
+	
+	#[test]
+	fn migrates_version_0_fixture() {
+		let mut connection = Connection::open_in_memory().unwrap();
+		connection.execute_batch(VERSION_0_FIXTURE).unwrap();
+		
+		migrate(&mut connection).unwrap();
+		
+		assert_eq!(user_version(&connection), SCHEMA_VERSION);
+		
+		let mut statement = connection.prepare("SELECT name, password_hash, auth_key_hash FROM users ORDER BY name").unwrap();
+		let users: Vec<(String, Option<Vec<u8>>, Option<String>)> = statement.query_map((), |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)))
+			.unwrap()
+			.collect::<Result<_, _>>()
+			.unwrap();
+		
+		assert_eq!(users, vec![
+			("alice".to_owned(), Some(vec![0x01]), None),
+			("bob".to_owned(), Some(vec![0x03]), None),
+		]);
+		drop(statement);
+		
+		// existing accounts derived their keys with the defaults of the argon2 crate
+		let kdf: (String, u32, u32, u32) = connection.query_row(
+			"SELECT kdf_algorithm, kdf_memory_cost, kdf_time_cost, kdf_parallelism FROM users WHERE name='alice'",
+			(),
+			|row| Ok((row.get(0)?, row.get(1)?, row.get(2)?, row.get(3)?)),
+		).unwrap();
+		assert_eq!(kdf, ("argon2id".to_owned(), argon2::Params::DEFAULT_M_COST, argon2::Params::DEFAULT_T_COST, argon2::Params::DEFAULT_P_COST));
+		
+		let mut statement = connection.prepare("
+			SELECT folders.user, folders.name, files.info, files.blob_id
+				FROM files JOIN folders ON files.folder=folders.id
+				ORDER BY files.blob_id
+		").unwrap();
+		let files: Vec<(String, Vec<u8>, Vec<u8>, String)> = statement.query_map((), |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?, row.get(3)?)))
+			.unwrap()
+			.collect::<Result<_, _>>()
+			.unwrap();
+		
+		assert_eq!(files, vec![
+			("alice".to_owned(), vec![0xa0], vec![0xf0], "blob0".to_owned()),
+			("alice".to_owned(), vec![0xa0], vec![0xf1], "blob1".to_owned()),
+			("bob".to_owned(), vec![0xb0], vec![0xf2], "blob2".to_owned()),
+		]);
+		
+		let folders: u32 = connection.query_row("SELECT COUNT(*) FROM folders WHERE length(id)=16", (), |row| row.get(0)).unwrap();
+		assert_eq!(folders, 3);
+	}
+	
+	#[test]
+	fn migration_is_idempotent() {
+		let mut connection = Connection::open_in_memory().unwrap();
+		connection.execute_batch(VERSION_0_FIXTURE).unwrap();
+		
+		migrate(&mut connection).unwrap();
+		migrate(&mut connection).unwrap();
+		
+		assert_eq!(user_version(&connection), SCHEMA_VERSION);
+	}
+	
+	#[test]
+	fn refuses_newer_schema() {
+		let mut connection = Connection::open_in_memory().unwrap();
+		connection.pragma_update(None, "user_version", SCHEMA_VERSION + 1).unwrap();
+		
+		assert!(matches!(migrate(&mut connection), Err(Error::UnsupportedSchemaVersion(version)) if version == SCHEMA_VERSION + 1));
+	}
+}
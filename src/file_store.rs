@@ -1,8 +1,8 @@
-use std::collections::HashMap;
+use std::{collections::HashMap, rc::Rc};
 
 use leptos::{create_rw_signal, leptos_dom, spawn_local, RwSignal, ServerFnError, SignalUpdate, SignalWith};
 
-use crate::{account::Auth, app::notify::Notify, files::{self, FilesError}, utils::ToPrettyError, vault::{Cipher, FileContent, FileInfo, FolderName, Secret, Vault}};
+use crate::{app::{notify::Notify, Reauthenticate}, files::{self, FileId, FilesError, FolderId}, utils::ToPrettyError, vault::{FileContent, FileInfo, Secret, Vault}};
 
 use self::folder_state::FolderState;
 
@@ -10,28 +10,28 @@ mod folder_state;
 
 #[derive(Clone, Debug)]
 pub struct FileData {
-	pub id: Cipher<FileInfo>,
+	pub id: FileId,
 	pub info: Secret<FileInfo>,
 }
 
-async fn load_folder(notify: Notify, auth: Auth, vault: Vault, folder: Cipher<FolderName>) -> Vec<FileData> {
-	let files = match files::get_files(auth, folder).await {
+/// Returns `None` if the session expired, after prompting to log in again.
+async fn load_folder(notify: Notify, reauthenticate: Reauthenticate, vault: Vault, folder: FolderId) -> Option<Vec<FileData>> {
+	let files = match files::get_files(folder).await {
 		Ok(files) => files,
 		Err(ServerFnError::WrappedServerError(FilesError::NotAuthenticated)) => {
-			notify.error("Not authenticated");
-			// TODO prompt to login again
-			return Vec::new();
+			reauthenticate.prompt();
+			return None;
 		}
 		Err(err) => {
 			notify.error(err.to_pretty_error());
 			leptos_dom::error!("Error fetching files: {err}");
-			return Vec::new();
+			return Some(Vec::new());
 		},
 	};
 	
-	files.into_iter()
-		.filter_map(|id| {
-			let info = match vault.decrypt(&id) {
+	Some(files.into_iter()
+		.filter_map(|file| {
+			let info = match vault.decrypt(&file.info, &(folder, file.id)) {
 				Ok(info) => info,
 				Err(err) => {
 					notify.error("Encountered corrupted file info");
@@ -41,90 +41,122 @@ async fn load_folder(notify: Notify, auth: Auth, vault: Vault, folder: Cipher<Fo
 			};
 			
 			Some(FileData {
-				id,
+				id: file.id,
 				info,
 			})
 		})
-		.collect()
+		.collect())
 }
 
-async fn load_file(auth: Auth, vault: Vault, file: Cipher<FileInfo>) -> Secret<FileContent> {
-	// TODO handle error and prompt to login when not authenticated
-	let content = files::download_file(auth, file).await.unwrap();
-	
-	// TODO handle error
-	vault.decrypt(&content).unwrap()
+/// Returns `None` if the session expired, after prompting to log in again.
+async fn load_file(reauthenticate: Reauthenticate, vault: Vault, file: FileId) -> Option<Rc<Secret<FileContent>>> {
+	match files::download_file(&vault, file).await {
+		Err(ServerFnError::WrappedServerError(FilesError::NotAuthenticated)) => {
+			reauthenticate.prompt();
+			None
+		},
+		// TODO handle error
+		result => Some(Rc::new(result.unwrap())),
+	}
 }
 
 #[derive(Clone, Debug)]
 pub struct FileStore {
 	vault: Vault,
-	auth: Auth,
-	folders: RwSignal<HashMap<Cipher<FolderName>, FolderState>>,
-	files: RwSignal<HashMap<Cipher<FileInfo>, Option<Secret<FileContent>>>>,
+	reauthenticate: Reauthenticate,
+	folders: RwSignal<HashMap<FolderId, FolderState>>,
+	/// Shared, so uploads can encrypt the contents without copying them.
+	files: RwSignal<HashMap<FileId, Option<Rc<Secret<FileContent>>>>>,
 }
 
 impl FileStore {
-	pub fn new(vault: Vault, auth: Auth) -> Self {
+	pub fn new(vault: Vault, reauthenticate: Reauthenticate) -> Self {
 		Self {
 			vault,
-			auth,
+			reauthenticate,
 			folders: create_rw_signal(HashMap::new()),
 			files: create_rw_signal(HashMap::new()),
 		}
 	}
 	
-	pub fn files_in_folder_tracked(&self, folder: Cipher<FolderName>) -> Option<Vec<FileData>> {
+	pub fn files_in_folder_tracked(&self, folder: FolderId) -> Option<Vec<FileData>> {
 		if let Some(entry) = self.folders.with(|folders| folders.get(&folder).cloned()) {
 			return entry.loaded_files();
 		}
 		
+		// loading starts again once the user logged in again
+		if self.reauthenticate.is_prompting() {
+			return None;
+		}
+		
 		self.folders.update(|folders| {
-			folders.insert(folder.clone(), Default::default());
+			folders.insert(folder, Default::default());
 		});
 		
 		let notify = Notify::from_context();
+		let reauthenticate = self.reauthenticate;
 		let vault = self.vault.clone();
-		let auth = self.auth.clone();
 		let folders = self.folders;
 		
 		spawn_local(async move {
-			let files = load_folder(notify, auth, vault, folder.clone()).await;
+			let files = load_folder(notify, reauthenticate, vault, folder).await;
 			
-			folders.update(|folders| {
-				let entry = folders.get_mut(&folder).expect("None was just inserted");
-				entry.add_remote_files(files);
+			folders.update(|folders| match files {
+				Some(files) => {
+					let entry = folders.get_mut(&folder).expect("None was just inserted");
+					entry.add_remote_files(files);
+				},
+				None => {
+					folders.remove(&folder);
+				},
 			});
 		});
 		
 		None
 	}
 	
-	pub async fn add_files(&self, folder: Cipher<FolderName>, new_files: Vec<(Secret<FileInfo>, Secret<FileContent>)>) {
+	pub async fn add_files(&self, notify: Notify, folder: FolderId, new_files: Vec<(Secret<FileInfo>, Secret<FileContent>)>) {
 		// TODO display loading files
-		let mut files_data = Vec::with_capacity(new_files.len());
-		let contents: Vec<_> = new_files.into_iter()
-			.map(|(info, content)| {
-				files_data.push(FileData {
-					// TODO handle errors
-					id: self.vault.encrypt(&info).unwrap(),
-					info,
-				});
-				
-				content
-			})
+		let new_files: Vec<_> = new_files.into_iter()
+			.map(|(info, content)| (info, Rc::new(content)))
 			.collect();
 		
-		let upload_futures = contents.iter()
-			// TODO handle errors
-			.map(|content| self.vault.encrypt(&content).unwrap())
-			.zip(files_data.iter())
-			.map(|(content, file_data)| files::upload_file(self.auth.clone(), folder.clone(), file_data.id.clone(), content));
+		let upload_futures = new_files.iter()
+			.map(|(info, content)| async move {
+				let id = files::new_file_id().await?;
+				
+				// TODO handle errors
+				let info = self.vault.encrypt(info, &(folder, id)).unwrap();
+				
+				files::upload_file(&self.vault, folder, id, &info, content.clone()).await?;
+				
+				Ok::<_, ServerFnError<FilesError>>(id)
+			});
 		
-		for result in futures::future::join_all(upload_futures).await {
-			// TODO handle errors and prompt to login when not authenticated
-			result.unwrap();
-		}
+		let (files_data, contents): (Vec<_>, Vec<_>) = futures::future::join_all(upload_futures).await
+			.into_iter()
+			.zip(new_files)
+			.filter_map(|(result, (info, content))| {
+				let id = match result {
+					Ok(id) => id,
+					Err(ServerFnError::WrappedServerError(FilesError::NotAuthenticated)) => {
+						notify.error(format!("Could not upload {}, the session expired", info.reveal_secret().name));
+						self.reauthenticate.prompt();
+						return None;
+					},
+					Err(err) => {
+						notify.error(format!("Error uploading {}: {}", info.reveal_secret().name, err.to_pretty_error()));
+						leptos_dom::error!("Error uploading file: {err}");
+						return None;
+					},
+				};
+				
+				Some((FileData {
+					id,
+					info,
+				}, content))
+			})
+			.unzip();
 		
 		self.folders.update(|folders| {
 			let entry = folders.get_mut(&folder).expect("Folder should have started loading before uploading files to it");
@@ -138,7 +170,32 @@ impl FileStore {
 		});
 	}
 	
-	pub fn with_file_content_tracked<T>(&self, id: Cipher<FileInfo>, callback: impl Fn(&Secret<FileContent>) -> T) -> Option<T> {
+	pub async fn delete_file(&self, folder: FolderId, file: FileId) -> Result<(), ServerFnError<FilesError>> {
+		files::delete_file(file).await?;
+		
+		self.folders.update(|folders| {
+			if let Some(entry) = folders.get_mut(&folder) {
+				entry.remove_file(file);
+			}
+		});
+		
+		self.files.update(|files| {
+			files.remove(&file);
+		});
+		
+		Ok(())
+	}
+	
+	/// Adds a file that was restored from the trash back to its folder, if the folder has been loaded already.
+	pub fn restore_file(&self, folder: FolderId, file: FileData) {
+		self.folders.update(|folders| {
+			if let Some(entry) = folders.get_mut(&folder) {
+				entry.add_local_files(&[file]);
+			}
+		});
+	}
+	
+	pub fn with_file_content_tracked<T>(&self, id: FileId, callback: impl Fn(&Secret<FileContent>) -> T) -> Option<T> {
 		if let Some(result) = self.files.with(|files| -> Option<_> {
 			let entry = files.get(&id)?;
 			Some(entry.as_ref().map(|content| callback(content)))
@@ -146,20 +203,29 @@ impl FileStore {
 			return result;
 		}
 		
+		if self.reauthenticate.is_prompting() {
+			return None;
+		}
+		
 		self.files.update(|files| {
-			files.insert(id.clone(), None);
+			files.insert(id, None);
 		});
 		
+		let reauthenticate = self.reauthenticate;
 		let vault = self.vault.clone();
-		let auth = self.auth.clone();
 		let files = self.files;
 		
 		spawn_local(async move {
-			let content = load_file(auth, vault, id.clone()).await;
+			let content = load_file(reauthenticate, vault, id).await;
 			
-			files.update(|files| {
-				let entry = files.get_mut(&id).expect("None was just inserted");
-				*entry = Some(content);
+			files.update(|files| match content {
+				// the file might have been deleted in the meantime
+				Some(content) => if let Some(entry) = files.get_mut(&id) {
+					*entry = Some(content);
+				},
+				None => {
+					files.remove(&id);
+				},
 			});
 		});
 		
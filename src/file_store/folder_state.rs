@@ -1,3 +1,5 @@
+use crate::files::FileId;
+
 use super::FileData;
 
 #[derive(Clone, Debug)]
@@ -24,6 +26,12 @@ impl FolderState {
 		}
 	}
 	
+	pub fn remove_file(&mut self, id: FileId) {
+		match self {
+			Self::Loading(files) | Self::Loaded(files) => files.retain(|file| file.id != id),
+		}
+	}
+	
 	pub fn loaded_files(self) -> Option<Vec<FileData>> {
 		match self {
 			Self::Loading(_) => None,
@@ -1,69 +1,202 @@
-use std::{fs, path::PathBuf, str::FromStr};
+use std::str::FromStr;
+#[cfg(feature = "ssr")]
+use std::{fs, io, path::{Path, PathBuf}, time::{Duration, SystemTime}};
 
 use leptos::{server, ServerFnError};
+use serde::{Deserialize, Serialize};
 use thiserror::Error;
 
-use crate::{account::{Auth, AuthError}, vault::{Cipher, FileContent, FileInfo, FolderName}};
+use crate::{account::AuthError, vault::{Cipher, FileInfo, FolderName}};
 
 #[allow(unused)]
 use crate::db;
+#[cfg(feature = "ssr")]
+use crate::account::authenticate;
 
 #[cfg(feature = "ssr")]
 mod new_file_transaction;
 #[cfg(feature = "ssr")]
-use self::new_file_transaction::*;
+pub use self::new_file_transaction::*;
+
+mod id;
+pub use id::*;
+
+mod transfer;
+pub use transfer::*;
+
+/// Route that file contents are uploaded to, followed by the ids of the folder and the new file.
+pub const UPLOAD_PATH: &str = "/files/upload";
+/// Route that file contents are downloaded from, followed by the id of the file.
+pub const DOWNLOAD_PATH: &str = "/files/download";
+/// Header carrying the hex encoded `Cipher<FileInfo>` of an uploaded file.
+pub const FILE_INFO_HEADER: &str = "X-Vault-File-Info";
 
+#[derive(Clone, Serialize, Deserialize, Debug)]
+pub struct FolderEntry {
+	pub id: FolderId,
+	pub name: Cipher<FolderName>,
+}
+
+#[derive(Clone, Serialize, Deserialize, Debug)]
+pub struct FileEntry {
+	pub id: FileId,
+	pub info: Cipher<FileInfo>,
+}
+
+#[derive(Clone, Serialize, Deserialize, Debug)]
+pub struct TrashedFile {
+	pub id: FileId,
+	pub folder: FolderId,
+	pub info: Cipher<FileInfo>,
+}
+
+#[derive(Clone, Serialize, Deserialize, Debug)]
+pub struct Trash {
+	pub folders: Vec<FolderEntry>,
+	pub files: Vec<TrashedFile>,
+}
+
+/// How long a generated id can be used to create a folder or file for, long enough for large uploads.
+#[cfg(feature = "ssr")]
+const ID_RESERVATION_LIFETIME: Duration = Duration::from_days(1);
+
+/// Generates the id for a new folder, it is needed before creating the folder to encrypt its name.
+/// Only the user it was generated for can create a folder with it.
 #[server]
-pub async fn create_folder(auth: Auth, folder_name: Cipher<FolderName>) -> Result<(), ServerFnError<FilesError>> {
-	let username = auth.username()?;
+pub async fn new_folder_id() -> Result<FolderId, ServerFnError<FilesError>> {
+	let username = authenticate().await?;
 	
 	let db = db::use_db();
 	
-	db.add_folder(username, &folder_name)?;
+	let folder_id = FolderId::generate().map_err(|_| ServerFnError::ServerError("Server Error".to_owned()))?;
+	db.reserve_id(&username, folder_id.as_bytes(), SystemTime::now() - ID_RESERVATION_LIFETIME)?;
+	
+	Ok(folder_id)
+}
+
+/// Generates the id for a new file, it is needed before uploading the file to encrypt it.
+/// Only the user it was generated for can upload a file with it.
+#[server]
+pub async fn new_file_id() -> Result<FileId, ServerFnError<FilesError>> {
+	let username = authenticate().await?;
+	
+	let db = db::use_db();
+	
+	let file_id = FileId::generate().map_err(|_| ServerFnError::ServerError("Server Error".to_owned()))?;
+	db.reserve_id(&username, file_id.as_bytes(), SystemTime::now() - ID_RESERVATION_LIFETIME)?;
+	
+	Ok(file_id)
+}
+
+#[server]
+pub async fn create_folder(folder_id: FolderId, folder_name: Cipher<FolderName>) -> Result<(), ServerFnError<FilesError>> {
+	let username = authenticate().await?;
+	
+	let db = db::use_db();
+	
+	db.add_folder(&username, folder_id, &folder_name)?;
 	
 	Ok(())
 }
 
 #[server]
-pub async fn get_files(auth: Auth, folder: Cipher<FolderName>) -> Result<Vec<Cipher<FileInfo>>, ServerFnError<FilesError>> {
-	let username = auth.username()?;
+pub async fn rename_folder(folder_id: FolderId, new_name: Cipher<FolderName>) -> Result<(), ServerFnError<FilesError>> {
+	let username = authenticate().await?;
 	
 	let db = db::use_db();
 	
-	let files = db.get_files(username, &folder)?;
+	db.rename_folder(&username, folder_id, &new_name)?;
 	
-	Ok(files)
+	Ok(())
 }
 
+/// Moves the folder and the files in it to the trash.
 #[server]
-pub async fn upload_file(auth: Auth, folder: Cipher<FolderName>, info: Cipher<FileInfo>, content: Cipher<FileContent>) -> Result<(), ServerFnError<FilesError>> {
-	let username = auth.username()?;
+pub async fn delete_folder(folder: FolderId) -> Result<(), ServerFnError<FilesError>> {
+	let username = authenticate().await?;
 	
 	let db = db::use_db();
-	let files_location: PathBuf = leptos::use_context().unwrap();
 	
-	let new_file_transaction = NewFileTransaction::open_file(&files_location).map_err(|_| ServerFnError::ServerError("Server Error".to_owned()))?;
+	db.trash_folder(&username, folder)?;
 	
-	db.add_file(username, &folder, &info, new_file_transaction.id())?;
+	Ok(())
+}
+
+/// Moves the file to the trash.
+#[server]
+pub async fn delete_file(file: FileId) -> Result<(), ServerFnError<FilesError>> {
+	let username = authenticate().await?;
 	
-	new_file_transaction.write_data(content.as_bytes()).map_err(|_| ServerFnError::ServerError("Server Error".to_owned()))?;
+	let db = db::use_db();
+	
+	db.trash_file(&username, file)?;
+	
+	Ok(())
+}
+
+#[server]
+pub async fn get_trash() -> Result<Trash, ServerFnError<FilesError>> {
+	let username = authenticate().await?;
+	
+	let db = db::use_db();
+	
+	let trash = db.get_trash(&username)?;
+	
+	Ok(trash)
+}
+
+#[server]
+pub async fn restore_folder(folder: FolderId) -> Result<(), ServerFnError<FilesError>> {
+	let username = authenticate().await?;
+	
+	let db = db::use_db();
+	
+	db.restore_folder(&username, folder)?;
+	
+	Ok(())
+}
+
+#[server]
+pub async fn restore_file(file: FileId) -> Result<(), ServerFnError<FilesError>> {
+	let username = authenticate().await?;
+	
+	let db = db::use_db();
+	
+	db.restore_file(&username, file)?;
 	
 	Ok(())
 }
 
 #[server]
-pub async fn download_file(auth: Auth, file: Cipher<FileInfo>) -> Result<Cipher<FileContent>, ServerFnError<FilesError>> {
-	let username = auth.username()?;
+pub async fn empty_trash() -> Result<(), ServerFnError<FilesError>> {
+	let username = authenticate().await?;
 	
 	let db = db::use_db();
 	let files_location: PathBuf = leptos::use_context().unwrap();
 	
-	let file_id = db.get_file_id(username, &file)?;
+	db.empty_trash(&username, |blob_id| remove_blob(&files_location, blob_id))?;
+	
+	Ok(())
+}
+
+/// Removes a file's data from disk, data that is already missing counts as removed.
+#[cfg(feature = "ssr")]
+pub fn remove_blob(files_location: &Path, blob_id: &str) -> Result<(), io::Error> {
+	match fs::remove_file(files_location.join(blob_id)) {
+		Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(()),
+		result => result,
+	}
+}
+
+#[server]
+pub async fn get_files(folder: FolderId) -> Result<Vec<FileEntry>, ServerFnError<FilesError>> {
+	let username = authenticate().await?;
+	
+	let db = db::use_db();
 	
-	let path = files_location.join(file_id);
-	let content = fs::read(path).map_err(|_| ServerFnError::ServerError("Server error".to_owned()))?;
+	let files = db.get_files(&username, folder)?;
 	
-	Ok(Cipher::from_bytes(content))
+	Ok(files)
 }
 
 #[derive(Clone, Copy, Error, Debug)]
@@ -72,6 +205,11 @@ pub enum FilesError {
 	NotFound,
 	#[error("Not Authenticated")]
 	NotAuthenticated,
+	#[error("File too large")]
+	FileTooLarge,
+	/// The id wasn't generated for the user with [new_folder_id] or [new_file_id], or was used already.
+	#[error("Invalid ID")]
+	InvalidId,
 }
 
 impl FromStr for FilesError {
@@ -81,6 +219,8 @@ impl FromStr for FilesError {
 		match s {
 			"Not Found" => Ok(Self::NotFound),
 			"Not Authenticated" => Ok(Self::NotAuthenticated),
+			"File too large" => Ok(Self::FileTooLarge),
+			"Invalid ID" => Ok(Self::InvalidId),
 			_ => Err(())
 		}
 	}
@@ -97,11 +237,12 @@ impl From<db::Error> for ServerFnError<FilesError> {
 	fn from(err: db::Error) -> Self {
 		use db::Error::*;
 		match err {
-			FileError(_) | SQLiteError(_) => {
+			FileError(_) | SQLiteError(_) | UnsupportedSchemaVersion(_) | MigrationFailed(_) => {
 				eprintln!("Error: {err}");
 				ServerFnError::ServerError("Server error".to_owned())
 			},
-			NotFound => ServerFnError::WrappedServerError(FilesError::NotFound)
+			NotFound => ServerFnError::WrappedServerError(FilesError::NotFound),
+			IdNotReserved => ServerFnError::WrappedServerError(FilesError::InvalidId),
 		}
 	}
 }
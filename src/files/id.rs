@@ -0,0 +1,79 @@
+use std::{fmt::{self, Debug, Display}, str::FromStr};
+
+use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
+use thiserror::Error;
+
+use crate::utils::from_hex;
+
+#[derive(Clone, Copy, Error, Debug)]
+#[error("Invalid ID")]
+pub struct InvalidId;
+
+const ID_LENGTH: usize = 16;
+
+macro_rules! id_type {
+	($name: ident) => {
+		#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+		pub struct $name([u8; ID_LENGTH]);
+		
+		impl $name {
+			#[cfg(feature = "ssr")]
+			pub fn generate() -> Result<Self, getrandom::Error> {
+				let mut id = [0; ID_LENGTH];
+				getrandom::getrandom(&mut id)?;
+				
+				Ok(Self(id))
+			}
+			
+			pub fn as_bytes(&self) -> &[u8; ID_LENGTH] {
+				&self.0
+			}
+			
+			#[cfg(feature = "ssr")]
+			pub fn from_bytes(bytes: [u8; ID_LENGTH]) -> Self {
+				Self(bytes)
+			}
+		}
+		
+		impl Display for $name {
+			fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+				self.0.iter()
+					.try_for_each(|byte| write!(f, "{byte:02x}"))
+			}
+		}
+		
+		impl Debug for $name {
+			fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+				write!(f, "{}({self})", stringify!($name))
+			}
+		}
+		
+		impl FromStr for $name {
+			type Err = InvalidId;
+			
+			fn from_str(s: &str) -> Result<Self, Self::Err> {
+				let id = from_hex(s)
+					.and_then(|bytes| bytes.try_into().ok())
+					.ok_or(InvalidId)?;
+				
+				Ok(Self(id))
+			}
+		}
+		
+		impl Serialize for $name {
+			fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+				serializer.collect_str(self)
+			}
+		}
+		
+		impl<'de> Deserialize<'de> for $name {
+			fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+				let string = String::deserialize(deserializer)?;
+				string.parse().map_err(de::Error::custom)
+			}
+		}
+	};
+}
+
+id_type!(FolderId);
+id_type!(FileId);
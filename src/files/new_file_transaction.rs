@@ -56,12 +56,13 @@ impl NewFileTransaction {
 		&self.id
 	}
 	
-	pub fn write_data(mut self, data: &[u8]) -> Result<(), io::Error> {
-		self.file.write_all(data)?;
-		
+	pub fn write(&mut self, data: &[u8]) -> Result<(), io::Error> {
+		self.file.write_all(data)
+	}
+	
+	/// Keeps the written file, otherwise it is deleted when the transaction is dropped.
+	pub fn complete(mut self) {
 		self.is_complete = true;
-		
-		Ok(())
 	}
 }
 
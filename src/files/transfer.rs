@@ -0,0 +1,254 @@
+use std::{rc::Rc, time::Duration};
+
+use futures::channel::oneshot;
+use js_sys::{Array, Reflect, Uint8Array};
+use leptos::{leptos_dom, set_timeout, window, ServerFnError};
+use wasm_bindgen::{closure::Closure, JsCast, JsValue};
+use wasm_bindgen_futures::JsFuture;
+use web_sys::{Blob, Headers, ReadableStream, ReadableStreamDefaultController, ReadableStreamDefaultReader, Request, RequestInit, Response, UnderlyingSource};
+
+use crate::{utils::to_hex, vault::{Cipher, DecryptionError, EncryptionError, FileContent, FileInfo, Secret, Vault}};
+
+use super::{FileId, FilesError, FolderId, DOWNLOAD_PATH, FILE_INFO_HEADER, UPLOAD_PATH};
+
+const DOWNLOAD_RETRIES: u32 = 3;
+const RETRY_DELAY: Duration = Duration::from_secs(1);
+/// Size of the pieces the plain text of an upload is handed to encryption in.
+const UPLOAD_PIECE_SIZE: usize = 64 * 1024;
+
+/// Uploads a file as a plain request body instead of a server function argument,
+/// so the server can stream it to disk. The auth cookie is sent along like with server functions.
+/// The content is encrypted chunk by chunk with [Vault::encrypt_chunks] while it is being sent,
+/// browsers that can't stream request bodies get a body made up of the encrypted chunks instead.
+pub async fn upload_file(vault: &Vault, folder: FolderId, file: FileId, info: &Cipher<FileInfo>, content: Rc<Secret<FileContent>>) -> Result<(), ServerFnError<FilesError>> {
+	let url = format!("{UPLOAD_PATH}/{folder}/{file}");
+	let encrypt = || vault.encrypt_chunks(file, plain_text_pieces(content.clone())).map_err(encryption_error);
+	
+	if supports_request_streams() {
+		// dropped only once the request is done, since the stream calls it for each chunk
+		let (body, _pull) = encrypted_stream(encrypt()?)?;
+		
+		match fetch(&url, &upload_init(info, &body, true)?).await {
+			// Chrome only streams request bodies over HTTP/2 and fails before sending anything otherwise
+			Err(ServerFnError::Request(err)) => leptos_dom::warn!("Uploading {file} without streaming: {err}"),
+			result => return result.map(|_| ()),
+		}
+	}
+	
+	let parts = Array::new();
+	
+	for chunk in encrypt()? {
+		parts.push(&Uint8Array::from(&chunk.map_err(encryption_error)?[..]));
+	}
+	
+	let body = Blob::new_with_u8_array_sequence(&parts).map_err(request_error)?;
+	
+	fetch(&url, &upload_init(info, &body, false)?).await?;
+	
+	Ok(())
+}
+
+fn upload_init(info: &Cipher<FileInfo>, body: &JsValue, streamed: bool) -> Result<RequestInit, ServerFnError<FilesError>> {
+	let headers = Headers::new().map_err(request_error)?;
+	headers.set(FILE_INFO_HEADER, &to_hex(info.as_bytes())).map_err(request_error)?;
+	
+	let mut init = RequestInit::new();
+	init.method("POST")
+		.headers(&headers)
+		.body(Some(body));
+	
+	if streamed {
+		// required for stream bodies, but web-sys has no setter for it
+		Reflect::set(&init, &"duplex".into(), &"half".into()).map_err(request_error)?;
+	}
+	
+	Ok(init)
+}
+
+/// Browsers that can't send a stream turn it into a string body, which gets a `Content-Type`, see
+/// <https://developer.chrome.com/docs/capabilities/web-apis/fetch-streaming-requests#feature_detection>.
+fn supports_request_streams() -> bool {
+	let Ok(stream) = ReadableStream::new() else {
+		return false;
+	};
+	
+	let mut init = RequestInit::new();
+	init.method("POST")
+		.body(Some(&stream));
+	
+	Reflect::set(&init, &"duplex".into(), &"half".into()).is_ok_and(|_| {
+		Request::new_with_str_and_init("", &init)
+			.is_ok_and(|request| request.headers().has("Content-Type") == Ok(false))
+	})
+}
+
+/// Hands the plain text to encryption in pieces, so it is never copied as a whole.
+fn plain_text_pieces(content: Rc<Secret<FileContent>>) -> impl Iterator<Item = Vec<u8>> {
+	let len = content.reveal_secret().data.len();
+	
+	(0..len).step_by(UPLOAD_PIECE_SIZE)
+		.map(move |start| content.reveal_secret().data[start..len.min(start + UPLOAD_PIECE_SIZE)].to_vec())
+}
+
+/// Encrypts the next chunk whenever the browser is ready to send more.
+/// The returned closure has to be kept alive for as long as the stream is read.
+fn encrypted_stream(mut chunks: impl Iterator<Item = Result<Vec<u8>, EncryptionError>> + 'static) -> Result<(ReadableStream, Closure<dyn FnMut(ReadableStreamDefaultController)>), ServerFnError<FilesError>> {
+	let pull = Closure::<dyn FnMut(ReadableStreamDefaultController)>::new(move |controller: ReadableStreamDefaultController| {
+		let result = match chunks.next() {
+			Some(Ok(chunk)) => controller.enqueue_with_chunk(&Uint8Array::from(&chunk[..])),
+			Some(Err(err)) => {
+				controller.error_with_e(&format!("Error encrypting file: {err}").into());
+				Ok(())
+			},
+			None => controller.close(),
+		};
+		
+		if let Err(err) = result {
+			leptos_dom::error!("Error streaming upload: {err:?}");
+		}
+	});
+	
+	let mut source = UnderlyingSource::new();
+	source.pull(pull.as_ref().unchecked_ref());
+	
+	let stream = ReadableStream::new_with_underlying_source(&source).map_err(request_error)?;
+	
+	Ok((stream, pull))
+}
+
+/// Downloads the content of a file and decrypts it chunk by chunk with [Vault::decrypt_chunks] while it arrives.
+/// Downloads interrupted by network errors are resumed where they stopped, unless the file changed in the meantime.
+pub async fn download_file(vault: &Vault, file: FileId) -> Result<Secret<FileContent>, ServerFnError<FilesError>> {
+	let url = format!("{DOWNLOAD_PATH}/{file}");
+	
+	let mut decryptor = vault.decrypt_chunks(file);
+	let mut plain_text = Vec::new();
+	let mut received = 0;
+	let mut etag = None;
+	let mut retries = 0;
+	
+	loop {
+		let received_before = received;
+		
+		let result = async {
+			let response = download_from(&url, received, etag.as_deref()).await?;
+			let header = |name| response.headers().get(name).ok().flatten();
+			
+			if response.status() == 206 {
+				let start = header("Content-Range")
+					.and_then(|range| range.strip_prefix("bytes ")?.split_once('-')?.0.parse().ok());
+				
+				if start != Some(received) {
+					return Err(ServerFnError::Deserialization("Received a range that doesn't continue the download".to_owned()));
+				}
+			} else if received > 0 {
+				// the file changed since the download started or ranges aren't supported
+				decryptor = vault.decrypt_chunks(file);
+				plain_text.clear();
+				received = 0;
+			}
+			
+			etag = header("ETag");
+			
+			read_body(&response, |piece| {
+				received += piece.len();
+				plain_text.extend(decryptor.push(piece).map_err(decryption_error)?);
+				Ok(())
+			}).await
+		}.await;
+		
+		if received > received_before {
+			retries = 0;
+		}
+		
+		match result {
+			Ok(()) => break,
+			Err(ServerFnError::Request(err)) if retries < DOWNLOAD_RETRIES => {
+				retries += 1;
+				leptos_dom::warn!("Resuming download of {file}: {err}");
+				sleep(RETRY_DELAY * retries).await;
+			},
+			Err(err) => return Err(err),
+		}
+	}
+	
+	plain_text.extend(decryptor.finish().map_err(decryption_error)?);
+	
+	Ok(Secret::hide(FileContent {
+		data: plain_text,
+	}))
+}
+
+/// Requests the file starting at the byte `start`, the range only applies if the file still has the `etag`.
+async fn download_from(url: &str, start: usize, etag: Option<&str>) -> Result<Response, ServerFnError<FilesError>> {
+	let headers = Headers::new().map_err(request_error)?;
+	
+	if start > 0 {
+		headers.set("Range", &format!("bytes={start}-")).map_err(request_error)?;
+		
+		if let Some(etag) = etag {
+			headers.set("If-Range", etag).map_err(request_error)?;
+		}
+	}
+	
+	let mut init = RequestInit::new();
+	init.method("GET")
+		.headers(&headers);
+	
+	fetch(url, &init).await
+}
+
+/// Calls `on_piece` for each piece of the body as it arrives.
+async fn read_body(response: &Response, mut on_piece: impl FnMut(&[u8]) -> Result<(), ServerFnError<FilesError>>) -> Result<(), ServerFnError<FilesError>> {
+	let Some(body) = response.body() else {
+		return Ok(());
+	};
+	
+	let reader: ReadableStreamDefaultReader = body.get_reader().unchecked_into();
+	
+	loop {
+		let result = JsFuture::from(reader.read()).await.map_err(request_error)?;
+		
+		if Reflect::get(&result, &"done".into()).map_err(request_error)?.is_truthy() {
+			return Ok(());
+		}
+		
+		let piece: Uint8Array = Reflect::get(&result, &"value".into()).map_err(request_error)?.unchecked_into();
+		on_piece(&piece.to_vec())?;
+	}
+}
+
+async fn sleep(duration: Duration) {
+	let (sender, receiver) = oneshot::channel();
+	set_timeout(move || {
+		let _ = sender.send(());
+	}, duration);
+	let _ = receiver.await;
+}
+
+async fn fetch(url: &str, init: &RequestInit) -> Result<Response, ServerFnError<FilesError>> {
+	let response: Response = JsFuture::from(window().fetch_with_str_and_init(url, init)).await
+		.map_err(request_error)?
+		.unchecked_into();
+	
+	match response.status() {
+		200..=299 => Ok(response),
+		401 => Err(ServerFnError::WrappedServerError(FilesError::NotAuthenticated)),
+		404 => Err(ServerFnError::WrappedServerError(FilesError::NotFound)),
+		409 => Err(ServerFnError::WrappedServerError(FilesError::InvalidId)),
+		413 => Err(ServerFnError::WrappedServerError(FilesError::FileTooLarge)),
+		status => Err(ServerFnError::ServerError(format!("Unexpected status code: {status}"))),
+	}
+}
+
+fn request_error(err: JsValue) -> ServerFnError<FilesError> {
+	ServerFnError::Request(format!("{err:?}"))
+}
+
+fn encryption_error(err: EncryptionError) -> ServerFnError<FilesError> {
+	ServerFnError::Serialization(format!("Error encrypting file: {err}"))
+}
+
+fn decryption_error(err: DecryptionError) -> ServerFnError<FilesError> {
+	ServerFnError::Deserialization(format!("Error decrypting file: {err}"))
+}
@@ -1,7 +1,15 @@
 #[cfg(feature = "ssr")]
 #[tokio::main]
-async fn main() {
-	vault::server::serve().await;
+async fn main() -> std::process::ExitCode {
+	let args: Vec<String> = std::env::args().skip(1).collect();
+	
+	match args.first().map(String::as_str) {
+		None | Some("serve") if args.len() <= 1 => {
+			vault::server::serve().await;
+			std::process::ExitCode::SUCCESS
+		},
+		_ => vault::server::run_command(&args),
+	}
 }
 
 #[cfg(not(feature = "ssr"))]
@@ -1,23 +1,37 @@
 mod serve_file;
+mod purge_trash;
+mod upload_file;
+mod download_file;
+mod cli;
 
-use std::{fs, net::Ipv4Addr, path::{Path, PathBuf}};
+use std::{fs, net::{Ipv4Addr, SocketAddr}, path::{Path, PathBuf}, process::ExitCode, time::{Duration, SystemTime}};
 
-use axum::{body::Body, extract::{FromRef, Request, State}, response::IntoResponse, routing::post, Router};
+use axum::{body::Body, extract::{FromRef, Request, State}, middleware, response::IntoResponse, routing::{get, post}, Router};
 use http::{header, HeaderValue};
 use leptos::*;
 use leptos_axum::{generate_route_list, handle_server_fns_with_context, render_app_to_stream_with_context, LeptosRoutes};
 use tokio::net::TcpListener;
 use getrandom::getrandom;
 
-use crate::{account::Authenticator, app::App, db::Database};
+use crate::{account::{renew_auth, Admins, Authenticator, RateLimiter, RegistrationMode}, app::App, db::Database, files::{DOWNLOAD_PATH, UPLOAD_PATH}, vault::{KdfParameters, Pepper}};
 use serve_file::serve_file;
+use purge_trash::purge_trash;
+use upload_file::upload_file;
+use download_file::download_file;
+use cli::{Command, USAGE};
 
 #[derive(Clone, Debug)]
 pub struct AppState {
 	leptos_options: LeptosOptions,
 	authenticator: Authenticator,
+	rate_limiter: RateLimiter,
+	registration_mode: RegistrationMode,
+	admins: Admins,
+	pepper: Pepper,
+	kdf_parameters: KdfParameters,
 	database: Database,
 	files_location: PathBuf,
+	max_upload_size: u64,
 }
 
 impl FromRef<AppState> for LeptosOptions {
@@ -26,6 +40,18 @@ impl FromRef<AppState> for LeptosOptions {
 	}
 }
 
+impl FromRef<AppState> for Authenticator {
+	fn from_ref(input: &AppState) -> Self {
+		input.authenticator.clone()
+	}
+}
+
+impl FromRef<AppState> for Database {
+	fn from_ref(input: &AppState) -> Self {
+		input.database.clone()
+	}
+}
+
 macro_rules! get_env {
 	($key: literal, $map: expr) => {
 		std::env::var($key)
@@ -40,18 +66,66 @@ macro_rules! get_env {
 	};
 }
 
-fn get_auth_key(path: &Path) -> [u8; 64] {
+macro_rules! get_env_or {
+	($key: literal, $map: expr, $default: expr) => {
+		std::env::var($key)
+			.ok()
+			.map($map)
+			.or(option_env!($key).map($map))
+			.unwrap_or($default)
+	};
+}
+
+/// Reads a key from a file, or generates a random one and writes it to the file if it doesn't exist yet.
+fn get_key<const N: usize>(path: &Path) -> [u8; N] {
 	if path.exists() {
-		fs::read(&path).expect(&format!("Could not read auth key file at: {path:?}"))
-			.try_into().expect("Auth key should be 64 bytes long")
+		fs::read(&path).expect(&format!("Could not read key file at: {path:?}"))
+			.try_into().unwrap_or_else(|_| panic!("Key at {path:?} should be {N} bytes long"))
 	} else {
-		let mut key = [0; 64];
+		let mut key = [0; N];
 		getrandom(&mut key).expect("Could not generate random key");
-		fs::write(path, &key).expect(&format!("Could not write auth key file at: {path:?}"));
+		fs::write(path, &key).expect(&format!("Could not write key file at: {path:?}"));
 		key
 	}
 }
 
+/// Runs one of the administration commands of [USAGE] on the configured database and files.
+pub fn run_command(args: &[String]) -> ExitCode {
+	let command = match Command::parse(args) {
+		Ok(Command::Help) => {
+			print!("{USAGE}");
+			return ExitCode::SUCCESS;
+		},
+		Ok(command) => command,
+		Err(err) => {
+			eprintln!("{err}");
+			return ExitCode::from(2);
+		},
+	};
+	
+	let db_file: PathBuf = get_env!("VAULT_DB_FILE");
+	let files_location: PathBuf = get_env!("VAULT_FILES_LOCATION");
+	
+	// opening would create an empty database, which is never what was meant
+	if !db_file.exists() {
+		eprintln!("No database at {db_file:?}, check VAULT_DB_FILE");
+		return ExitCode::FAILURE;
+	}
+	
+	let database = match Database::open(&db_file) {
+		Ok(database) => database,
+		Err(err) => {
+			eprintln!("Could not open database at {db_file:?}: {err}");
+			return ExitCode::FAILURE;
+		},
+	};
+	
+	command.run(&database, &files_location).unwrap_or_else(|err| {
+		eprintln!("{err}");
+		ExitCode::FAILURE
+	})
+}
+
 pub async fn serve() {
 	// <https://github.com/leptos-rs/start-axum#executing-a-server-on-a-remote-machine-without-the-toolchain>
 	let config = get_configuration(None).await.unwrap();
@@ -60,10 +134,32 @@ pub async fn serve() {
 	
 	let port: u16 = get_env!("VAULT_PORT", |str| str.parse()).expect("VAULT_PORT must be a number");
 	let auth_key_file: PathBuf = get_env!("VAULT_AUTH_KEY");
+	let pepper_file: PathBuf = get_env!("VAULT_PEPPER");
 	let db_file: PathBuf = get_env!("VAULT_DB_FILE");
 	let files_location: PathBuf = get_env!("VAULT_FILES_LOCATION");
+	let trash_retention_days: u64 = get_env_or!("VAULT_TRASH_RETENTION_DAYS", |str| str.parse().expect("VAULT_TRASH_RETENTION_DAYS must be a number"), 30);
+	let session_lifetime_hours: u64 = get_env_or!("VAULT_SESSION_LIFETIME_HOURS", |str| str.parse().expect("VAULT_SESSION_LIFETIME_HOURS must be a number"), 24);
+	let trust_proxy: bool = get_env_or!("VAULT_TRUST_PROXY", |str| str.parse().expect("VAULT_TRUST_PROXY must be true or false"), false);
+	let registration_mode: RegistrationMode = get_env_or!("VAULT_REGISTRATION", |str| str.parse().expect("VAULT_REGISTRATION must be open, invite or disabled"), RegistrationMode::Open);
+	let admins: Admins = get_env_or!("VAULT_ADMINS", |str| Admins::new(str.split(',').map(str::trim).filter(|name| !name.is_empty()).map(str::to_owned)), Admins::default());
+	let max_upload_size: u64 = get_env_or!("VAULT_MAX_UPLOAD_SIZE", |str| str.parse().expect("VAULT_MAX_UPLOAD_SIZE must be a number of bytes"), 1 << 30);
+	let default_kdf = KdfParameters::default();
+	let kdf_parameters = KdfParameters {
+		memory_cost: get_env_or!("VAULT_KDF_MEMORY_COST", |str| str.parse().expect("VAULT_KDF_MEMORY_COST must be a number of KiB"), default_kdf.memory_cost),
+		time_cost: get_env_or!("VAULT_KDF_TIME_COST", |str| str.parse().expect("VAULT_KDF_TIME_COST must be a number"), default_kdf.time_cost),
+		parallelism: get_env_or!("VAULT_KDF_PARALLELISM", |str| str.parse().expect("VAULT_KDF_PARALLELISM must be a number"), default_kdf.parallelism),
+		..default_kdf
+	};
+	
+	kdf_parameters.argon2().expect("VAULT_KDF_* variables must be valid Argon2 parameters");
 	
-	let auth_key = get_auth_key(&auth_key_file);
+	let trash_retention = trash_retention_days.checked_mul(24 * 60 * 60)
+		.map(Duration::from_secs)
+		.filter(|retention| SystemTime::now().checked_sub(*retention).is_some())
+		.expect("VAULT_TRASH_RETENTION_DAYS is too large");
+	
+	let auth_key = get_key(&auth_key_file);
+	let pepper = get_key(&pepper_file);
 	
 	if !files_location.exists() {
 		fs::create_dir_all(&files_location).expect("Could not create folder for files at: {files_location}");
@@ -71,20 +167,36 @@ pub async fn serve() {
 	
 	let context = AppState {
 		leptos_options,
-		authenticator: Authenticator::new(auth_key),
-		database: Database::open(db_file).unwrap(),
+		authenticator: Authenticator::new(auth_key, Duration::from_hours(session_lifetime_hours)),
+		rate_limiter: RateLimiter::new(trust_proxy),
+		registration_mode,
+		admins,
+		pepper: Pepper::new(pepper),
+		kdf_parameters,
+		database: Database::open(&db_file).unwrap_or_else(|err| panic!("Could not open database at {db_file:?}: {err}")),
 		files_location,
+		max_upload_size,
 	};
 	
+	tokio::spawn(purge_trash(
+		context.database.clone(),
+		context.files_location.clone(),
+		trash_retention,
+	));
+	
 	let app = Router::<AppState>::new()
 		.leptos_routes_with_handler(routes, handle_leptos_routes)
 		.route("/api/*fn_name", post(handle_server_fns))
+		.route(&format!("{UPLOAD_PATH}/:folder/:file"), post(upload_file))
+		.route(&format!("{DOWNLOAD_PATH}/:file"), get(download_file))
 		.fallback(serve_file)
+		.layer(middleware::from_fn_with_state(context.clone(), renew_auth))
 		.with_state(context);
 	
 	let listener = TcpListener::bind((Ipv4Addr::new(0, 0, 0, 0), port)).await.unwrap();
 	logging::log!("Server running on port {port}");
-	axum::serve(listener, app.into_make_service()).await
+	// the client address is needed for rate limiting logins
+	axum::serve(listener, app.into_make_service_with_connect_info::<SocketAddr>()).await
 		.unwrap();
 }
 
@@ -109,7 +221,13 @@ async fn handle_server_fns(State(app_state): State<AppState>, request: Request<B
 	handle_server_fns_with_context(
 		move || {
 			// TODO isn't there a better way?
+			provide_context(app_state.clone());
 			provide_context(app_state.authenticator.clone());
+			provide_context(app_state.rate_limiter.clone());
+			provide_context(app_state.registration_mode);
+			provide_context(app_state.admins.clone());
+			provide_context(app_state.pepper.clone());
+			provide_context(app_state.kdf_parameters);
 			provide_context(app_state.database.clone());
 			provide_context(app_state.files_location.clone());
 		},
@@ -0,0 +1,354 @@
+use std::{collections::{HashMap, HashSet}, fs, io::{self, BufRead, Write}, path::Path, process::ExitCode, time::{Duration, SystemTime, UNIX_EPOCH}};
+
+use thiserror::Error;
+
+use crate::{account::{generate_invite_code, invite_code_hash, INVITE_LIFETIME}, db::{self, Database}, files::remove_blob};
+
+pub const USAGE: &str = "\
+Usage: vault [COMMAND]
+
+Without a command, or with `serve`, the server is started.
+The other commands work on the database and files configured with VAULT_DB_FILE and VAULT_FILES_LOCATION,
+they can be run while the server is running.
+
+Commands:
+  serve                          Start the server
+  user list                      List all users with what they store
+  user disable <USERNAME>        Keep a user from logging in and end their sessions, their files are kept
+  user enable <USERNAME>         Let a disabled user log in again
+  user delete <USERNAME> [--yes] Delete a user with all their files, asks for confirmation without --yes
+  invite create [--days <DAYS>]  Create an invite code, valid for 7 days by default
+  invite list                    List the invite codes that haven't been used or expired
+  invite revoke <ID>             Delete an invite code so it can't be used anymore
+  storage stats                  Show how many files are stored and how much space they take
+  db check                       Check the database and that every file it references exists
+  help                           Show this message
+";
+
+#[derive(Error, Debug)]
+pub enum CommandError {
+	#[error("{0}\n\nRun `vault help` to see the available commands")]
+	Usage(String),
+	#[error("User {0} doesn't exist")]
+	UnknownUser(String),
+	#[error("Invite {0} doesn't exist")]
+	UnknownInvite(i64),
+	#[error("{0}")]
+	Database(#[from] db::Error),
+	#[error("File error: {0}")]
+	FileError(#[from] io::Error),
+	#[error("Could not generate random invite code: {0}")]
+	Random(#[from] getrandom::Error),
+}
+
+/// Administration command of the server binary, see [USAGE].
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum Command {
+	Help,
+	ListUsers,
+	DisableUser(String),
+	EnableUser(String),
+	DeleteUser {
+		username: String,
+		/// Whether `--yes` was passed, otherwise the operator is asked to confirm.
+		confirmed: bool,
+	},
+	CreateInvite {
+		lifetime: Duration,
+	},
+	ListInvites,
+	RevokeInvite(i64),
+	StorageStats,
+	CheckDatabase,
+}
+
+impl Command {
+	pub fn parse(args: &[String]) -> Result<Self, CommandError> {
+		let args: Vec<&str> = args.iter().map(String::as_str).collect();
+		
+		let command = match args.as_slice() {
+			["help" | "--help" | "-h"] => Self::Help,
+			["user", "list"] => Self::ListUsers,
+			["user", "disable", username] => Self::DisableUser(username.to_string()),
+			["user", "enable", username] => Self::EnableUser(username.to_string()),
+			["user", "delete", username] => Self::DeleteUser { username: username.to_string(), confirmed: false },
+			["user", "delete", username, "--yes"] | ["user", "delete", "--yes", username] => Self::DeleteUser { username: username.to_string(), confirmed: true },
+			["invite", "create"] => Self::CreateInvite { lifetime: INVITE_LIFETIME },
+			["invite", "create", "--days", days] => {
+				let days: u64 = days.parse()
+					.ok()
+					.filter(|days| (1..=365).contains(days))
+					.ok_or_else(|| CommandError::Usage(format!("Invalid number of days: {days}, must be between 1 and 365")))?;
+				
+				Self::CreateInvite { lifetime: Duration::from_days(days) }
+			},
+			["invite", "list"] => Self::ListInvites,
+			["invite", "revoke", id] => Self::RevokeInvite(id.parse().map_err(|_| CommandError::Usage(format!("Invalid invite id: {id}")))?),
+			["storage", "stats"] => Self::StorageStats,
+			["db", "check"] => Self::CheckDatabase,
+			_ => return Err(CommandError::Usage(format!("Unknown command: {}", args.join(" ")))),
+		};
+		
+		Ok(command)
+	}
+	
+	pub fn run(self, database: &Database, files_location: &Path) -> Result<ExitCode, CommandError> {
+		match self {
+			Self::Help => print!("{USAGE}"),
+			Self::ListUsers => list_users(database, files_location)?,
+			Self::DisableUser(username) => {
+				set_user_disabled(database, &username, true)?;
+				let sessions = database.delete_sessions(&username, None)?;
+				println!("Disabled user {username} and ended {sessions} sessions");
+			},
+			Self::EnableUser(username) => {
+				set_user_disabled(database, &username, false)?;
+				println!("Enabled user {username}");
+			},
+			Self::DeleteUser { username, confirmed } => {
+				if !database.is_user(&username)? {
+					return Err(CommandError::UnknownUser(username));
+				}
+				
+				if !confirmed && !confirm(&format!("Delete user {username} and all their files? This can't be undone."))? {
+					println!("Cancelled");
+					return Ok(ExitCode::FAILURE);
+				}
+				
+				database.delete_user(&username, |blob_id| remove_blob(files_location, blob_id))?;
+				println!("Deleted user {username}");
+			},
+			Self::CreateInvite { lifetime } => {
+				let code = generate_invite_code()?;
+				let code_hash = invite_code_hash(&code).expect("generated invite codes should be valid");
+				database.insert_invite(&code_hash, None, SystemTime::now() + lifetime)?;
+				
+				println!("{code}");
+			},
+			Self::ListInvites => {
+				let invites = database.get_invites()?;
+				
+				if invites.is_empty() {
+					println!("No invites");
+				}
+				
+				for invite in invites {
+					println!(
+						"{:>6}  expires in {:<10} created by {}",
+						invite.id,
+						format_duration(invite.expires_at - now()),
+						invite.created_by.as_deref().unwrap_or("the command line"),
+					);
+				}
+			},
+			Self::RevokeInvite(id) => {
+				if !database.delete_invite(id)? {
+					return Err(CommandError::UnknownInvite(id));
+				}
+				
+				println!("Revoked invite {id}");
+			},
+			Self::StorageStats => storage_stats(database, files_location)?,
+			Self::CheckDatabase => return check_database(database, files_location),
+		}
+		
+		Ok(ExitCode::SUCCESS)
+	}
+}
+
+fn set_user_disabled(database: &Database, username: &str, disabled: bool) -> Result<(), CommandError> {
+	match database.set_user_disabled(username, disabled) {
+		Err(db::Error::NotFound) => Err(CommandError::UnknownUser(username.to_owned())),
+		result => Ok(result?),
+	}
+}
+
+fn list_users(database: &Database, files_location: &Path) -> Result<(), CommandError> {
+	let users = database.get_users()?;
+	let mut sizes: HashMap<String, u64> = HashMap::new();
+	
+	for (username, blob_id) in database.get_blobs()? {
+		*sizes.entry(username).or_default() += blob_size(files_location, &blob_id)?.unwrap_or(0);
+	}
+	
+	if users.is_empty() {
+		println!("No users");
+		return Ok(());
+	}
+	
+	println!("{:<24} {:>8} {:>8} {:>10} {:>8}  STATUS", "USERNAME", "FOLDERS", "FILES", "SIZE", "SESSIONS");
+	
+	for user in users {
+		let status = match (user.disabled, user.totp_enabled) {
+			(true, _) => "disabled",
+			(false, true) => "two-factor",
+			(false, false) => "",
+		};
+		
+		println!(
+			"{:<24} {:>8} {:>8} {:>10} {:>8}  {status}",
+			user.name,
+			user.folders,
+			user.files,
+			format_size(sizes.get(&user.name).copied().unwrap_or(0)),
+			user.sessions,
+		);
+	}
+	
+	Ok(())
+}
+
+fn storage_stats(database: &Database, files_location: &Path) -> Result<(), CommandError> {
+	let users = database.get_users()?;
+	let blobs = database.get_blobs()?;
+	let mut total_size = 0;
+	let mut missing = 0;
+	
+	for (_, blob_id) in &blobs {
+		match blob_size(files_location, blob_id)? {
+			Some(size) => total_size += size,
+			None => missing += 1,
+		}
+	}
+	
+	let known: HashSet<&str> = blobs.iter().map(|(_, blob_id)| blob_id.as_str()).collect();
+	let (unreferenced, unreferenced_size) = unreferenced_blobs(files_location, &known)?;
+	
+	println!("Users:              {}", users.len());
+	println!("Disabled users:     {}", users.iter().filter(|user| user.disabled).count());
+	println!("Folders:            {}", users.iter().map(|user| user.folders).sum::<u32>());
+	println!("Files:              {}", blobs.len());
+	println!("Size of files:      {}", format_size(total_size));
+	println!("Missing files:      {missing}");
+	// uploads that are still in progress aren't in the database yet
+	println!("Unreferenced files: {} ({})", unreferenced.len(), format_size(unreferenced_size));
+	
+	Ok(())
+}
+
+fn check_database(database: &Database, files_location: &Path) -> Result<ExitCode, CommandError> {
+	let mut problems = database.check()?;
+	
+	for (username, blob_id) in database.get_blobs()? {
+		if blob_size(files_location, &blob_id)?.is_none() {
+			problems.push(format!("File {blob_id} of user {username} is missing from {}", files_location.display()));
+		}
+	}
+	
+	if problems.is_empty() {
+		println!("No problems found");
+		return Ok(ExitCode::SUCCESS);
+	}
+	
+	for problem in &problems {
+		println!("{problem}");
+	}
+	
+	match problems.len() {
+		1 => println!("\nFound 1 problem"),
+		count => println!("\nFound {count} problems"),
+	}
+	
+	Ok(ExitCode::FAILURE)
+}
+
+/// Size of the stored file, `None` if it doesn't exist.
+fn blob_size(files_location: &Path, blob_id: &str) -> Result<Option<u64>, io::Error> {
+	match fs::metadata(files_location.join(blob_id)) {
+		Ok(metadata) => Ok(Some(metadata.len())),
+		Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(None),
+		Err(err) => Err(err),
+	}
+}
+
+/// Files in the files location that no file in the database refers to, and their total size.
+fn unreferenced_blobs(files_location: &Path, known: &HashSet<&str>) -> Result<(Vec<String>, u64), io::Error> {
+	let mut unreferenced = Vec::new();
+	let mut size = 0;
+	
+	for entry in fs::read_dir(files_location)? {
+		let entry = entry?;
+		let name = entry.file_name().to_string_lossy().into_owned();
+		
+		if entry.file_type()?.is_file() && !known.contains(name.as_str()) {
+			size += entry.metadata()?.len();
+			unreferenced.push(name);
+		}
+	}
+	
+	Ok((unreferenced, size))
+}
+
+fn confirm(question: &str) -> Result<bool, io::Error> {
+	print!("{question} [y/N] ");
+	io::stdout().flush()?;
+	
+	let mut answer = String::new();
+	io::stdin().lock().read_line(&mut answer)?;
+	
+	Ok(matches!(answer.trim(), "y" | "Y" | "yes"))
+}
+
+fn now() -> i64 {
+	SystemTime::now().duration_since(UNIX_EPOCH)
+		.map(|duration| duration.as_secs() as i64)
+		.unwrap_or(0)
+}
+
+fn format_duration(seconds: i64) -> String {
+	match seconds.max(0) {
+		seconds if seconds < 60 * 60 => format!("{} min", seconds / 60),
+		seconds if seconds < 24 * 60 * 60 => format!("{} hours", seconds / (60 * 60)),
+		seconds => format!("{} days", seconds / (24 * 60 * 60)),
+	}
+}
+
+fn format_size(bytes: u64) -> String {
+	const UNITS: [&str; 5] = ["B", "KiB", "MiB", "GiB", "TiB"];
+	
+	let mut size = bytes as f64;
+	let mut unit = 0;
+	
+	while size >= 1024.0 && unit < UNITS.len() - 1 {
+		size /= 1024.0;
+		unit += 1;
+	}
+	
+	if unit == 0 {
+		format!("{bytes} B")
+	} else {
+		format!("{size:.1} {}", UNITS[unit])
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	
+	fn parse(args: &str) -> Result<Command, CommandError> {
+		Command::parse(&args.split_whitespace().map(str::to_owned).collect::<Vec<_>>())
+	}
+	
+	#[test]
+	fn parses_commands() {
+		assert_eq!(parse("user disable alice").unwrap(), Command::DisableUser("alice".to_owned()));
+		assert_eq!(parse("user delete alice").unwrap(), Command::DeleteUser { username: "alice".to_owned(), confirmed: false });
+		assert_eq!(parse("user delete --yes alice").unwrap(), Command::DeleteUser { username: "alice".to_owned(), confirmed: true });
+		assert_eq!(parse("invite create").unwrap(), Command::CreateInvite { lifetime: INVITE_LIFETIME });
+		assert_eq!(parse("invite create --days 2").unwrap(), Command::CreateInvite { lifetime: Duration::from_days(2) });
+		assert_eq!(parse("invite revoke 3").unwrap(), Command::RevokeInvite(3));
+		
+		assert!(matches!(parse("invite create --days 0"), Err(CommandError::Usage(_))));
+		assert!(matches!(parse("invite revoke x"), Err(CommandError::Usage(_))));
+		assert!(matches!(parse("user disable"), Err(CommandError::Usage(_))));
+		assert!(matches!(parse("users list"), Err(CommandError::Usage(_))));
+	}
+	
+	#[test]
+	fn formats_sizes() {
+		assert_eq!(format_size(0), "0 B");
+		assert_eq!(format_size(1023), "1023 B");
+		assert_eq!(format_size(1536), "1.5 KiB");
+		assert_eq!(format_size(3 << 30), "3.0 GiB");
+	}
+}
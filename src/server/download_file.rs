@@ -0,0 +1,70 @@
+use axum::{body::Body, extract::{Path, Request, State}, response::{IntoResponse, Response}};
+use http::{header, HeaderValue, StatusCode};
+use tower::ServiceExt;
+use tower_http::services::ServeFile;
+
+use crate::{account::Username, db, files::FileId};
+
+use super::AppState;
+
+const CACHE_CONTROL_DOWNLOAD: HeaderValue = HeaderValue::from_static("private, no-cache");
+
+/// Streams the encrypted content of a file, supporting range requests so downloads can be resumed.
+pub async fn download_file(
+	State(app_state): State<AppState>,
+	Username(username): Username,
+	Path(file): Path<FileId>,
+	request: Request,
+) -> Result<Response, StatusCode> {
+	let headers = request.headers();
+	
+	let blob_id = match app_state.database.get_blob_id(&username, file) {
+		Ok(blob_id) => blob_id,
+		Err(db::Error::NotFound) => return Err(StatusCode::NOT_FOUND),
+		Err(err) => {
+			eprintln!("Error downloading file: {err}");
+			return Err(StatusCode::INTERNAL_SERVER_ERROR);
+		},
+	};
+	
+	// blobs are never modified after being uploaded, so their id identifies the content
+	let etag = HeaderValue::from_str(&format!("\"{blob_id}\"")).expect("Blob ids should be valid header values");
+	
+	let matches_etag = |value: &HeaderValue| value.to_str()
+		.is_ok_and(|value| value.split(',')
+			.map(str::trim)
+			.any(|tag| tag == "*" || tag == etag)
+		);
+	
+	if headers.get(header::IF_NONE_MATCH).is_some_and(matches_etag) {
+		return Ok((StatusCode::NOT_MODIFIED, [(header::ETAG, etag)]).into_response());
+	}
+	
+	let mut file_request = Request::builder();
+	
+	// a range only applies if the file is still the one the client started downloading
+	if let Some(range) = headers.get(header::RANGE) {
+		if headers.get(header::IF_RANGE).map_or(true, |if_range| *if_range == etag) {
+			file_request = file_request.header(header::RANGE, range);
+		}
+	}
+	
+	let file_request = file_request
+		.body(Body::empty())
+		.unwrap();
+	
+	let mut response = ServeFile::new(app_state.files_location.join(blob_id))
+		.oneshot(file_request).await
+		.expect("Error serving file")
+		.into_response();
+	
+	if response.status() == StatusCode::NOT_FOUND {
+		eprintln!("Error downloading file: missing data for {file:?}");
+		return Err(StatusCode::INTERNAL_SERVER_ERROR);
+	}
+	
+	response.headers_mut().insert(header::ETAG, etag);
+	response.headers_mut().insert(header::CACHE_CONTROL, CACHE_CONTROL_DOWNLOAD);
+	
+	Ok(response)
+}
@@ -0,0 +1,37 @@
+use std::{path::PathBuf, time::{Duration, SystemTime}};
+
+use leptos::logging;
+
+use tokio::{task::spawn_blocking, time::interval};
+
+use crate::{db::Database, files::remove_blob};
+
+const PURGE_INTERVAL: Duration = Duration::from_hours(1);
+
+/// Periodically deletes everything that has been in the trash for longer than `retention`.
+pub async fn purge_trash(database: Database, files_location: PathBuf, retention: Duration) {
+	let mut interval = interval(PURGE_INTERVAL);
+	
+	loop {
+		interval.tick().await;
+		
+		// the retention is checked on startup, but the clock could still be set to before it
+		let Some(deleted_before) = SystemTime::now().checked_sub(retention) else {
+			continue;
+		};
+		
+		// SQLite and removing the blobs block, so they mustn't hold up the executor
+		let database = database.clone();
+		let files_location = files_location.clone();
+		let result = spawn_blocking(move || {
+			database.purge_trash(deleted_before, |blob_id| remove_blob(&files_location, blob_id))
+		}).await;
+		
+		match result {
+			Ok(Ok(0)) => (),
+			Ok(Ok(count)) => logging::log!("Purged {count} files from the trash"),
+			Ok(Err(err)) => eprintln!("Error purging trash: {err}"),
+			Err(err) => eprintln!("Purging trash panicked: {err}"),
+		}
+	}
+}
@@ -0,0 +1,73 @@
+use std::fmt::Display;
+
+use axum::{body::Body, extract::{Path, State}};
+use futures::StreamExt;
+use http::{header, HeaderMap, StatusCode};
+
+use crate::{account::Username, db, files::{FileId, FolderId, NewFileTransaction, FILE_INFO_HEADER}, utils::from_hex, vault::Cipher};
+
+use super::AppState;
+
+fn server_error(err: impl Display) -> StatusCode {
+	eprintln!("Error uploading file: {err}");
+	StatusCode::INTERNAL_SERVER_ERROR
+}
+
+/// Streams the request body to disk and adds it as a new file to the folder.
+pub async fn upload_file(
+	State(app_state): State<AppState>,
+	Username(username): Username,
+	Path((folder, file_id)): Path<(FolderId, FileId)>,
+	headers: HeaderMap,
+	body: Body,
+) -> Result<(), StatusCode> {
+	let header = |name| headers.get(name).and_then(|value| value.to_str().ok());
+	
+	let info = header(FILE_INFO_HEADER)
+		.and_then(from_hex)
+		.map(Cipher::from_bytes)
+		.ok_or(StatusCode::BAD_REQUEST)?;
+	
+	let content_length: Option<u64> = header(header::CONTENT_LENGTH.as_str())
+		.and_then(|length| length.parse().ok());
+	
+	if content_length.is_some_and(|length| length > app_state.max_upload_size) {
+		return Err(StatusCode::PAYLOAD_TOO_LARGE);
+	}
+	
+	// checked again when the file is added, but this saves receiving the whole body for a file that can't be added
+	if !app_state.database.has_folder(&username, folder).map_err(server_error)? {
+		return Err(StatusCode::NOT_FOUND);
+	}
+	
+	if !app_state.database.is_id_reserved(&username, file_id.as_bytes()).map_err(server_error)? {
+		return Err(StatusCode::CONFLICT);
+	}
+	
+	let mut new_file_transaction = NewFileTransaction::open_file(&app_state.files_location).map_err(server_error)?;
+	
+	let mut size = 0;
+	let mut stream = body.into_data_stream();
+	
+	while let Some(chunk) = stream.next().await {
+		let chunk = chunk.map_err(|_| StatusCode::BAD_REQUEST)?;
+		
+		size += chunk.len() as u64;
+		if size > app_state.max_upload_size {
+			return Err(StatusCode::PAYLOAD_TOO_LARGE);
+		}
+		
+		new_file_transaction.write(&chunk).map_err(server_error)?;
+	}
+	
+	match app_state.database.add_file(&username, folder, file_id, &info, new_file_transaction.id()) {
+		Ok(()) => (),
+		Err(db::Error::NotFound) => return Err(StatusCode::NOT_FOUND),
+		Err(db::Error::IdNotReserved) => return Err(StatusCode::CONFLICT),
+		Err(err) => return Err(server_error(err)),
+	}
+	
+	new_file_transaction.complete();
+	
+	Ok(())
+}
@@ -1,4 +1,4 @@
-use std::borrow::Cow;
+use std::{borrow::Cow, fmt::Write};
 
 use leptos::ServerFnError;
 
@@ -18,3 +18,21 @@ impl<T: ToString> ToPrettyError for ServerFnError<T> {
 		}.into()
 	}
 }
+
+pub fn to_hex(bytes: &[u8]) -> String {
+	bytes.iter()
+		.fold(String::with_capacity(bytes.len() * 2), |mut hex, byte| {
+			write!(hex, "{byte:02x}").expect("Writing to a String can't fail");
+			hex
+		})
+}
+
+pub fn from_hex(hex: &str) -> Option<Vec<u8>> {
+	if hex.len() % 2 != 0 || !hex.is_ascii() {
+		return None;
+	}
+	
+	hex.as_bytes().chunks_exact(2)
+		.map(|hex| u8::from_str_radix(std::str::from_utf8(hex).ok()?, 16).ok())
+		.collect()
+}
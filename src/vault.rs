@@ -1,8 +1,11 @@
 #[cfg(feature = "hydrate")]
 mod secure;
-use generic_array::GenericArray;
 #[cfg(feature = "hydrate")]
 pub use secure::*;
+#[cfg(feature = "hydrate")]
+mod stream;
+#[cfg(feature = "hydrate")]
+mod envelope;
 
 #[cfg(not(feature = "hydrate"))]
 mod mock_secure;
@@ -11,6 +14,10 @@ pub use mock_secure::*;
 
 #[cfg(feature = "ssr")]
 mod db_access;
+#[cfg(feature = "ssr")]
+mod verifier;
+#[cfg(feature = "ssr")]
+pub use verifier::*;
 
 mod cipher_secret;
 pub use cipher_secret::*;
@@ -39,18 +46,88 @@ pub enum DecryptionError {
 	#[error("Plain text ended unexpectedly")]
 	UnexpectedEndOfBytes,
 	#[cfg(feature = "hydrate")]
+	#[error("Unsupported encryption format version {0}")]
+	UnsupportedVersion(u8),
+	#[cfg(feature = "hydrate")]
+	#[error("Unsupported encryption algorithm {0}")]
+	UnsupportedAlgorithm(u8),
+	#[cfg(feature = "hydrate")]
+	#[error("Encrypted with unknown key {0}")]
+	UnknownKey(u16),
+	#[cfg(feature = "hydrate")]
 	#[error("Error decrypting ciphertext: {0}")]
 	ChaChaError(#[from] chacha20poly1305::Error),
 }
 
-type Nonce = GenericArray<u8, generic_array::typenum::U24>;
+#[derive(Clone, Error, Debug)]
+pub enum KdfError {
+	#[error("Invalid key derivation parameters: {0}")]
+	InvalidParameters(argon2::Error),
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Serialize, Deserialize, Debug)]
+pub enum KdfAlgorithm {
+	Argon2id,
+}
+
+/// How keys are derived from a password, stored for each user next to the [Salt]
+/// so the cost can be raised without breaking existing accounts.
+#[derive(Clone, Copy, PartialEq, Eq, Serialize, Deserialize, Debug)]
+pub struct KdfParameters {
+	pub algorithm: KdfAlgorithm,
+	/// In KiB.
+	pub memory_cost: u32,
+	pub time_cost: u32,
+	pub parallelism: u32,
+}
+
+impl KdfParameters {
+	pub fn argon2(&self) -> Result<argon2::Argon2<'static>, KdfError> {
+		let algorithm = match self.algorithm {
+			KdfAlgorithm::Argon2id => argon2::Algorithm::Argon2id,
+		};
+		
+		let params = argon2::Params::new(self.memory_cost, self.time_cost, self.parallelism, None)
+			.map_err(KdfError::InvalidParameters)?;
+		
+		Ok(argon2::Argon2::new(algorithm, argon2::Version::V0x13, params))
+	}
+	
+	/// Whether keys derived with these parameters take at least as much memory and time as with `other`, and they differ.
+	pub fn is_stronger_than(&self, other: &Self) -> bool {
+		self != other
+			&& self.algorithm == other.algorithm
+			&& self.memory_cost >= other.memory_cost
+			&& self.time_cost >= other.time_cost
+	}
+}
+
+/// The parameters of `Argon2::default()`, which every account used before they were stored.
+impl Default for KdfParameters {
+	fn default() -> Self {
+		Self {
+			algorithm: KdfAlgorithm::Argon2id,
+			memory_cost: argon2::Params::DEFAULT_M_COST,
+			time_cost: argon2::Params::DEFAULT_T_COST,
+			parallelism: argon2::Params::DEFAULT_P_COST,
+		}
+	}
+}
 
 #[derive(Clone, Serialize, Deserialize)]
 pub struct Salt {
 	data: [u8; 32],
 }
 
-#[derive(Clone, PartialEq, Eq, Serialize, Deserialize)]
+/// Sent to the server to log in, derived from the password independently of the encryption key.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct AuthKey {
+	data: [u8; 32],
+}
+
+/// Login credential of accounts created before [AuthKey], the SHA-512 of the password and salt.
+/// Only sent to upgrade such an account on login.
+#[derive(Clone, Serialize, Deserialize)]
 pub struct PasswordHash {
 	#[serde(with = "BigArray")]
 	data: [u8; 64],
@@ -68,7 +145,13 @@ macro_rules! hidden_debug {
 
 hidden_debug!(Password);
 hidden_debug!(Salt);
+hidden_debug!(RecoveryCode);
+hidden_debug!(AuthKey);
 hidden_debug!(PasswordHash);
+#[cfg(feature = "ssr")]
+hidden_debug!(AuthKeyHash);
+#[cfg(feature = "ssr")]
+hidden_debug!(Pepper);
 hidden_debug!(Vault);
 
 impl<T: CipherSecret> Debug for Secret<T> {
@@ -76,3 +159,35 @@ impl<T: CipherSecret> Debug for Secret<T> {
 		write!(f, "Secret<{}>(...)", std::any::type_name::<T>())
 	}
 }
+
+/// Keys, vaults and ids shared by the tests of the encryption modules.
+#[cfg(all(test, feature = "hydrate"))]
+mod test_fixtures {
+	use crate::files::{FileId, FolderId};
+	
+	use super::*;
+	
+	/// Derived with a fixed salt, so the same password always gives the same keys.
+	pub fn keys(password: &str) -> DerivedKeys {
+		Password::new(password.to_owned()).derive_keys(&Salt { data: [0; 32] }, &KdfParameters::default()).unwrap()
+	}
+	
+	pub fn vault(username: &str) -> Vault {
+		let keys = keys("password");
+		Vault::open(username, &keys, &keys.new_key_ring(username, true).unwrap()).unwrap()
+	}
+	
+	pub fn folder(byte: u8) -> FolderId {
+		format!("{byte:02x}").repeat(16).parse().unwrap()
+	}
+	
+	pub fn file(byte: u8) -> FileId {
+		format!("{byte:02x}").repeat(16).parse().unwrap()
+	}
+	
+	pub fn folder_name(name: &str) -> Secret<FolderName> {
+		Secret::hide(FolderName {
+			name: name.to_owned(),
+		})
+	}
+}
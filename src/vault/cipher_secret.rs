@@ -2,14 +2,66 @@ use super::*;
 
 use std::{hash::{Hash, Hasher}, marker::PhantomData};
 
+use crate::files::{FileId, FolderId};
+
 pub(super) trait Sealed {}
 
 #[allow(private_bounds)]
 pub trait CipherSecret : Sized + Sealed {
+	/// Identifies the type of secret in the associated data.
+	#[cfg_attr(not(feature = "hydrate"), allow(dead_code))]
+	const TAG: u8;
+	/// Whether the secret is encrypted as a STREAM of chunks instead of a single message.
+	#[cfg_attr(not(feature = "hydrate"), allow(dead_code))]
+	const CHUNKED: bool = false;
+	
+	/// What the secret belongs to, a cipher only decrypts in the context it was encrypted in.
+	type Context: CipherContext;
+	
 	fn as_bytes(&self) -> impl AsRef<[u8]>;
 	fn from_bytes(bytes: Vec<u8>) -> Result<Self, DecryptionError>;
 }
 
+#[allow(private_bounds)]
+pub trait CipherContext : Clone + Sealed {
+	// only used for encryption, which happens in the browser
+	#[cfg_attr(not(feature = "hydrate"), allow(dead_code))]
+	fn to_bytes(&self) -> Vec<u8>;
+}
+
+impl Sealed for () {}
+
+/// For secrets that only belong to the user.
+impl CipherContext for () {
+	fn to_bytes(&self) -> Vec<u8> {
+		Vec::new()
+	}
+}
+
+impl Sealed for FolderId {}
+
+impl CipherContext for FolderId {
+	fn to_bytes(&self) -> Vec<u8> {
+		self.as_bytes().to_vec()
+	}
+}
+
+impl Sealed for FileId {}
+
+impl CipherContext for FileId {
+	fn to_bytes(&self) -> Vec<u8> {
+		self.as_bytes().to_vec()
+	}
+}
+
+impl Sealed for (FolderId, FileId) {}
+
+impl CipherContext for (FolderId, FileId) {
+	fn to_bytes(&self) -> Vec<u8> {
+		[&self.0.as_bytes()[..], &self.1.as_bytes()[..]].concat()
+	}
+}
+
 #[derive(Serialize, Deserialize)]
 pub struct Cipher<T: CipherSecret> {
 	data: Vec<u8>,
@@ -18,32 +70,10 @@ pub struct Cipher<T: CipherSecret> {
 }
 
 impl<T: CipherSecret> Cipher<T> {
-	#[cfg(feature = "hydrate")]
-	pub(super) fn new(nonce: &Nonce, ciphertext: &[u8]) -> Self {
-		let nonce: &[u8] = &nonce;
-		let mut data = nonce.to_owned();
-		data.extend_from_slice(&ciphertext);
-		
-		Self {
-			data,
-			_phantom_data: PhantomData,
-		}
-	}
-	
-	pub(super) fn nonce(&self) -> Nonce {
-		Nonce::clone_from_slice(&self.data[..std::mem::size_of::<Nonce>()])
-	}
-	
-	pub(super) fn ciphertext(&self) -> &[u8] {
-		&self.data[std::mem::size_of::<Nonce>()..]
-	}
-	
-	#[cfg(feature = "ssr")]
 	pub fn as_bytes(&self) -> &[u8] {
 		&self.data
 	}
 	
-	#[cfg(feature = "ssr")]
 	pub fn from_bytes(data: Vec<u8>) -> Self {
 		Self {
 			data,
@@ -77,6 +107,6 @@ impl<T: CipherSecret> Hash for Cipher<T> {
 
 impl<T: CipherSecret> Debug for Cipher<T> {
 	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-		write!(f, "Cipher {{ nonce: {:?}, ciphertext: {:?} }}", self.nonce(), self.ciphertext())
+		write!(f, "Cipher<{}>({} bytes)", std::any::type_name::<T>(), self.data.len())
 	}
 }
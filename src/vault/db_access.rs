@@ -1,3 +1,5 @@
+use rusqlite::types::{FromSql, FromSqlError, FromSqlResult, ToSql, ToSqlOutput, ValueRef};
+
 use super::*;
 use crate::db;
 
@@ -24,3 +26,32 @@ impl PasswordHash {
 		self.data
 	}
 }
+
+impl AuthKeyHash {
+	pub fn from_db(phc: String, _: db::Token) -> Self {
+		Self {
+			phc,
+		}
+	}
+	
+	pub fn to_db(&self, _: db::Token) -> &str {
+		&self.phc
+	}
+}
+
+impl FromSql for KdfAlgorithm {
+	fn column_result(value: ValueRef<'_>) -> FromSqlResult<Self> {
+		match value.as_str()? {
+			"argon2id" => Ok(Self::Argon2id),
+			_ => Err(FromSqlError::InvalidType),
+		}
+	}
+}
+
+impl ToSql for KdfAlgorithm {
+	fn to_sql(&self) -> rusqlite::Result<ToSqlOutput<'_>> {
+		Ok(ToSqlOutput::from(match self {
+			Self::Argon2id => "argon2id",
+		}))
+	}
+}
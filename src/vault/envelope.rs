@@ -0,0 +1,195 @@
+use super::*;
+
+const MAGIC: [u8; 4] = *b"VLTC";
+/// Data without a header is version 0, from before there were headers.
+/// Version 1 only authenticated the header, it was never released and isn't accepted,
+/// so ciphers can't be moved to other objects by relabelling them.
+const VERSION: u8 = 2;
+const MIN_VERSION: u8 = 2;
+pub(super) const HEADER_SIZE: usize = 8;
+
+pub(super) type KeyId = u16;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+#[repr(u8)]
+pub(super) enum Algorithm {
+	/// A single XChaCha20-Poly1305 message, preceded by its nonce.
+	XChaCha20Poly1305 = 1,
+	/// XChaCha20-Poly1305 STREAM of chunks, preceded by the nonce prefix.
+	XChaCha20Poly1305Stream = 2,
+}
+
+impl TryFrom<u8> for Algorithm {
+	type Error = DecryptionError;
+	
+	fn try_from(value: u8) -> Result<Self, Self::Error> {
+		match value {
+			1 => Ok(Self::XChaCha20Poly1305),
+			2 => Ok(Self::XChaCha20Poly1305Stream),
+			_ => Err(DecryptionError::UnsupportedAlgorithm(value)),
+		}
+	}
+}
+
+/// Describes how a `Cipher` was encrypted, it is authenticated as associated data.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub(super) struct Header {
+	pub version: u8,
+	pub algorithm: Algorithm,
+	pub key_id: KeyId,
+}
+
+impl Header {
+	pub fn new(algorithm: Algorithm, key_id: KeyId) -> Self {
+		Self {
+			version: VERSION,
+			algorithm,
+			key_id,
+		}
+	}
+	
+	pub fn to_bytes(self) -> [u8; HEADER_SIZE] {
+		let [key_id_high, key_id_low] = self.key_id.to_be_bytes();
+		let [m0, m1, m2, m3] = MAGIC;
+		
+		[m0, m1, m2, m3, self.version, self.algorithm as u8, key_id_high, key_id_low]
+	}
+	
+	/// Associated data binding a cipher to its header, the type of secret, the user and what it belongs to.
+	pub fn associated_data<T: CipherSecret>(self, username: &str, context: &T::Context) -> Vec<u8> {
+		let username_len = u32::try_from(username.len()).expect("Username should not be longer than 4GB");
+		
+		let mut aad = self.to_bytes().to_vec();
+		aad.push(T::TAG);
+		aad.extend_from_slice(&username_len.to_be_bytes());
+		aad.extend_from_slice(username.as_bytes());
+		aad.extend_from_slice(&context.to_bytes());
+		
+		aad
+	}
+	
+	/// Parses the header at the start of `data`, returns `None` for legacy data without a header.
+	pub fn parse(data: &[u8]) -> Result<Option<Self>, DecryptionError> {
+		let Some(header) = data.get(..HEADER_SIZE) else {
+			return Ok(None);
+		};
+		
+		if header[..MAGIC.len()] != MAGIC {
+			return Ok(None);
+		}
+		
+		let version = header[4];
+		if !(MIN_VERSION..=VERSION).contains(&version) {
+			return Err(DecryptionError::UnsupportedVersion(version));
+		}
+		
+		Ok(Some(Self {
+			version,
+			algorithm: header[5].try_into()?,
+			key_id: KeyId::from_be_bytes([header[6], header[7]]),
+		}))
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use chacha20poly1305::aead::{Aead, Payload};
+	
+	use crate::vault::test_fixtures::*;
+	
+	use super::*;
+	
+	#[test]
+	fn header_round_trips() {
+		let header = Header::new(Algorithm::XChaCha20Poly1305Stream, 258);
+		
+		assert_eq!(Header::parse(&header.to_bytes()).unwrap(), Some(header));
+		assert_eq!(Header::parse(b"VLT").unwrap(), None);
+		assert_eq!(Header::parse(&[0; HEADER_SIZE]).unwrap(), None);
+	}
+	
+	#[test]
+	fn rejects_unknown_versions() {
+		let vault = vault("user");
+		let mut data = vault.encrypt(&folder_name("folder"), &folder(1)).unwrap().as_bytes().to_owned();
+		data[4] = VERSION + 1;
+		
+		let result = vault.decrypt(&Cipher::<FolderName>::from_bytes(data), &folder(1));
+		assert!(matches!(result, Err(DecryptionError::UnsupportedVersion(version)) if version == VERSION + 1));
+	}
+	
+	#[test]
+	fn rejects_modified_headers() {
+		let vault = vault("user");
+		let mut data = vault.encrypt(&folder_name("folder"), &folder(1)).unwrap().as_bytes().to_owned();
+		data[6] = 1;
+		
+		let result = vault.decrypt(&Cipher::<FolderName>::from_bytes(data.clone()), &folder(1));
+		assert!(matches!(result, Err(DecryptionError::UnknownKey(258))));
+		
+		data[6] = 0;
+		data[7] = 0;
+		assert!(vault.decrypt(&Cipher::<FolderName>::from_bytes(data.clone()), &folder(1)).is_err());
+		
+		data[7] = 2;
+		data[5] = Algorithm::XChaCha20Poly1305Stream as u8;
+		assert!(vault.decrypt(&Cipher::<FolderName>::from_bytes(data), &folder(1)).is_err());
+	}
+	
+	#[test]
+	fn rejects_moved_ciphers() {
+		let vault = vault("user");
+		
+		let name = vault.encrypt(&folder_name("folder"), &folder(1)).unwrap();
+		assert!(vault.decrypt(&name, &folder(1)).is_ok());
+		assert!(vault.decrypt(&name, &folder(2)).is_err());
+		assert!(self::vault("other user").decrypt(&name, &folder(1)).is_err());
+		
+		let info = FileInfo {
+			name: "file".to_owned(),
+			mime_type: "text/plain".to_owned(),
+		};
+		let info = vault.encrypt(&Secret::hide(info), &(folder(1), file(1))).unwrap();
+		assert!(vault.decrypt(&info, &(folder(1), file(1))).is_ok());
+		assert!(vault.decrypt(&info, &(folder(2), file(1))).is_err());
+		assert!(vault.decrypt(&info, &(folder(1), file(2))).is_err());
+		
+		let content = FileContent {
+			data: b"content".to_vec(),
+		};
+		let content = vault.encrypt(&Secret::hide(content), &file(1)).unwrap();
+		assert!(vault.decrypt(&content, &file(1)).is_ok());
+		assert!(vault.decrypt(&content, &file(2)).is_err());
+	}
+	
+	#[test]
+	fn rejects_version_1_data() {
+		let vault = vault("user");
+		let header = Header {
+			version: 1,
+			algorithm: Algorithm::XChaCha20Poly1305,
+			key_id: 0,
+		}.to_bytes();
+		let nonce = [7; 24];
+		let ciphertext = vault.cipher_for(LEGACY_KEY_ID).unwrap().encrypt(&nonce.into(), Payload {
+			msg: b"folder",
+			aad: &header,
+		}).unwrap();
+		
+		let cipher = Cipher::<FolderName>::from_bytes([&header[..], &nonce, &ciphertext].concat());
+		assert!(matches!(vault.decrypt(&cipher, &folder(1)), Err(DecryptionError::UnsupportedVersion(1))));
+	}
+	
+	#[test]
+	fn decrypts_legacy_data() {
+		let vault = vault("user");
+		let nonce = [7; 24];
+		let ciphertext = vault.cipher_for(LEGACY_KEY_ID).unwrap().encrypt(&nonce.into(), &b"folder"[..]).unwrap();
+		
+		let cipher = Cipher::<FolderName>::from_bytes([&nonce[..], &ciphertext].concat());
+		assert_eq!(vault.decrypt(&cipher, &folder(1)).unwrap().reveal_secret().name, "folder");
+		
+		let cipher = Cipher::<FileContent>::from_bytes([&nonce[..], &ciphertext].concat());
+		assert_eq!(vault.decrypt(&cipher, &file(1)).unwrap().reveal_secret().data, b"folder");
+	}
+}
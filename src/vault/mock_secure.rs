@@ -2,6 +2,8 @@ use std::{marker::PhantomData, convert::Infallible};
 
 use super::*;
 
+use crate::files::FileId;
+
 impl Salt {
 	pub fn generate() -> Result<Self, Infallible> {
 		panic!("Not implemented for ssr");
@@ -15,7 +17,45 @@ impl Password {
 		panic!("Not implemented for ssr");
 	}
 	
-	pub fn hash(&self, _salt: &Salt) -> PasswordHash {
+	pub fn derive_keys(&self, _salt: &Salt, _kdf: &KdfParameters) -> Result<DerivedKeys, KdfError> {
+		panic!("Not implemented for ssr");
+	}
+	
+	pub fn legacy_hash(&self, _salt: &Salt) -> PasswordHash {
+		panic!("Not implemented for ssr");
+	}
+}
+
+pub struct RecoveryCode(());
+
+impl RecoveryCode {
+	pub fn generate() -> Result<Self, Infallible> {
+		panic!("Not implemented for ssr");
+	}
+	
+	pub fn parse(_code: &str) -> Option<Self> {
+		panic!("Not implemented for ssr");
+	}
+	
+	pub fn derive_keys(&self) -> DerivedKeys {
+		panic!("Not implemented for ssr");
+	}
+}
+
+impl fmt::Display for RecoveryCode {
+	fn fmt(&self, _f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		panic!("Not implemented for ssr");
+	}
+}
+
+pub struct DerivedKeys(());
+
+impl DerivedKeys {
+	pub fn auth_key(&self) -> AuthKey {
+		panic!("Not implemented for ssr");
+	}
+	
+	pub fn new_key_ring(&self, _username: &str, _keep_password_keys: bool) -> Result<Cipher<KeyRing>, EncryptionError> {
 		panic!("Not implemented for ssr");
 	}
 }
@@ -43,15 +83,49 @@ impl<T: CipherSecret> Secret<T> {
 pub struct Vault(());
 
 impl Vault {
-	pub fn new(_password: Password, _salt: Salt) -> Self {
+	pub fn open(_username: &str, _keys: &DerivedKeys, _key_ring: &Cipher<KeyRing>) -> Result<Self, DecryptionError> {
+		panic!("Not implemented for ssr");
+	}
+	
+	pub fn rewrap_key_ring(&self, _new_keys: &DerivedKeys) -> Result<Cipher<KeyRing>, EncryptionError> {
+		panic!("Not implemented for ssr");
+	}
+	
+	pub fn username(&self) -> &str {
+		panic!("Not implemented for ssr");
+	}
+	
+	pub fn encrypt<T: CipherSecret>(&self, _secret: &Secret<T>, _context: &T::Context) -> Result<Cipher<T>, EncryptionError> {
+		panic!("Not implemented for ssr");
+	}
+	
+	pub fn decrypt<T: CipherSecret>(&self, _cipher: &Cipher<T>, _context: &T::Context) -> Result<Secret<T>, DecryptionError> {
+		panic!("Not implemented for ssr");
+	}
+	
+	pub fn encrypt_chunks<I, T>(&self, _file: FileId, _pieces: I) -> Result<impl Iterator<Item = Result<Vec<u8>, EncryptionError>>, EncryptionError>
+	where
+		I: IntoIterator<Item = T>,
+		T: AsRef<[u8]>,
+	{
 		panic!("Not implemented for ssr");
+		#[allow(unreachable_code)]
+		Ok(std::iter::empty())
 	}
 	
-	pub fn encrypt<T: CipherSecret>(&self, _secret: &Secret<T>) -> Result<Cipher<T>, EncryptionError> {
+	pub fn decrypt_chunks(&self, _file: FileId) -> StreamDecryptor<FileContent> {
+		panic!("Not implemented for ssr");
+	}
+}
+
+pub struct StreamDecryptor<S: CipherSecret>(PhantomData<S>);
+
+impl<S: CipherSecret> StreamDecryptor<S> {
+	pub fn push(&mut self, _piece: &[u8]) -> Result<Vec<u8>, DecryptionError> {
 		panic!("Not implemented for ssr");
 	}
 	
-	pub fn decrypt<T: CipherSecret>(&self, _cipher: &Cipher<T>) -> Result<Secret<T>, DecryptionError> {
+	pub fn finish(self) -> Result<Vec<u8>, DecryptionError> {
 		panic!("Not implemented for ssr");
 	}
 }
@@ -1,11 +1,32 @@
 use std::rc::Rc;
 
-use argon2::Argon2;
-use chacha20poly1305::{aead::Aead, Key, KeyInit, XChaCha20Poly1305};
+use chacha20poly1305::{aead::{Aead, Payload}, Key, KeyInit, XChaCha20Poly1305};
+use generic_array::GenericArray;
 use getrandom::getrandom;
-use sha2::{Sha512, Digest};
+use hkdf::Hkdf;
+use sha2::{Sha256, Sha512, Digest};
+
+use crate::utils::{from_hex, to_hex};
 
 use super::*;
+use super::envelope::{Algorithm, Header, KeyId, HEADER_SIZE};
+
+type Nonce = GenericArray<u8, generic_array::typenum::U24>;
+
+/// Id of the key that was the Argon2 output itself, before it was split with HKDF.
+pub(super) const LEGACY_KEY_ID: KeyId = 0;
+/// Id of the encryption key derived from the Argon2 output with HKDF, it now only wraps the key ring.
+const DERIVED_KEY_ID: KeyId = 1;
+/// Id of the random master key in the key ring.
+const MASTER_KEY_ID: KeyId = 2;
+
+const RECOVERY_CODE_SIZE: usize = 20;
+/// Number of hex digits between dashes when a recovery code is shown.
+const RECOVERY_CODE_GROUP_SIZE: usize = 5;
+const RECOVERY_CODE_SALT: &[u8] = b"vault recovery code";
+
+const AUTH_KEY_INFO: &[u8] = b"vault auth key";
+const ENCRYPTION_KEY_INFO: &[u8] = b"vault encryption key";
 
 impl Salt {
 	pub fn generate() -> Result<Self, getrandom::Error> {
@@ -30,7 +51,17 @@ impl Password {
 		}
 	}
 	
-	pub fn hash(&self, salt: &Salt) -> PasswordHash {
+	/// Runs Argon2 once and splits the output into independent keys for logging in and encryption.
+	pub fn derive_keys(&self, salt: &Salt, kdf: &KdfParameters) -> Result<DerivedKeys, KdfError> {
+		let mut legacy_key = Key::default();
+		kdf.argon2()?.hash_password_into(self.plain_text.as_bytes(), &salt.data, &mut legacy_key)
+			.map_err(KdfError::InvalidParameters)?;
+		
+		Ok(DerivedKeys::from_prk(legacy_key))
+	}
+	
+	/// The login credential of accounts created before [AuthKey], only needed to upgrade them.
+	pub fn legacy_hash(&self, salt: &Salt) -> PasswordHash {
 		let mut hasher = Sha512::new();
 		hasher.update(&self.plain_text);
 		hasher.update(salt.data);
@@ -41,6 +72,113 @@ impl Password {
 	}
 }
 
+/// High entropy code shown once when an account is created, it wraps a second copy of the key ring
+/// so the account can be recovered when the password is forgotten.
+pub struct RecoveryCode {
+	data: [u8; RECOVERY_CODE_SIZE],
+}
+
+impl RecoveryCode {
+	pub fn generate() -> Result<Self, getrandom::Error> {
+		let mut code = Self {
+			data: [0; RECOVERY_CODE_SIZE],
+		};
+		
+		getrandom(&mut code.data)?;
+		
+		Ok(code)
+	}
+	
+	/// Ignores case, whitespace and dashes, so the code can be typed in however it was written down.
+	pub fn parse(code: &str) -> Option<Self> {
+		let hex = code.chars()
+			.filter(|c| !c.is_whitespace() && *c != '-')
+			.collect::<String>()
+			.to_ascii_lowercase();
+		
+		Some(Self {
+			data: from_hex(&hex)?.try_into().ok()?,
+		})
+	}
+	
+	/// The code is random, so unlike a password it doesn't need to be stretched with Argon2.
+	pub fn derive_keys(&self) -> DerivedKeys {
+		let (prk, _) = Hkdf::<Sha256>::extract(Some(RECOVERY_CODE_SALT), &self.data);
+		
+		DerivedKeys::from_prk(prk)
+	}
+}
+
+impl fmt::Display for RecoveryCode {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		let hex = to_hex(&self.data);
+		let groups = hex.as_bytes()
+			.chunks(RECOVERY_CODE_GROUP_SIZE)
+			.map(|group| std::str::from_utf8(group).expect("Hex should be ASCII"))
+			.collect::<Vec<_>>();
+		
+		write!(f, "{}", groups.join("-").to_ascii_uppercase())
+	}
+}
+
+pub struct DerivedKeys {
+	auth_key: AuthKey,
+	encryption_key: Key,
+	/// The input of HKDF, for a password this is the Argon2 output that was used to encrypt before keys were derived with HKDF.
+	legacy_key: Key,
+}
+
+impl DerivedKeys {
+	fn from_prk(prk: Key) -> Self {
+		let hkdf = Hkdf::<Sha256>::from_prk(&prk).expect("Key should be long enough for HKDF");
+		
+		let mut auth_key = AuthKey {
+			data: [0; 32],
+		};
+		hkdf.expand(AUTH_KEY_INFO, &mut auth_key.data).expect("Key length should be valid for HKDF");
+		
+		let mut encryption_key = Key::default();
+		hkdf.expand(ENCRYPTION_KEY_INFO, &mut encryption_key).expect("Key length should be valid for HKDF");
+		
+		Self {
+			auth_key,
+			encryption_key,
+			legacy_key: prk,
+		}
+	}
+	
+	pub fn auth_key(&self) -> AuthKey {
+		self.auth_key.clone()
+	}
+	
+	/// Generates a random master key and wraps it in a key ring for the server to store.
+	/// Accounts that already encrypted data with the keys derived from their password keep those in the ring.
+	pub fn new_key_ring(&self, username: &str, keep_password_keys: bool) -> Result<Cipher<KeyRing>, EncryptionError> {
+		let mut master_key = [0; 32];
+		getrandom(&mut master_key)?;
+		
+		let mut keys = vec![(MASTER_KEY_ID, master_key)];
+		
+		if keep_password_keys {
+			keys.push((DERIVED_KEY_ID, self.encryption_key.into()));
+			keys.push((LEGACY_KEY_ID, self.legacy_key.into()));
+		}
+		
+		self.wrap_key_ring(username, KeyRing { keys })
+	}
+	
+	fn wrap_key_ring(&self, username: &str, key_ring: KeyRing) -> Result<Cipher<KeyRing>, EncryptionError> {
+		self.key_ring_vault(username).encrypt(&Secret::hide(key_ring), &())
+	}
+	
+	/// Wraps and unwraps the key ring, this is the only key that depends on the password.
+	fn key_ring_vault(&self, username: &str) -> Vault {
+		Vault::from_key_ring(username, KeyRing {
+			keys: vec![(DERIVED_KEY_ID, self.encryption_key.into())],
+		})
+	}
+}
+
 #[derive(Clone)]
 pub struct Secret<T: CipherSecret> {
 	inner: T,
@@ -64,38 +202,242 @@ impl<T: CipherSecret> Secret<T> {
 
 #[derive(Clone)]
 pub struct Vault {
-	cipher: Rc<XChaCha20Poly1305>,
+	/// Encrypts new ciphers.
+	pub(super) cipher: Rc<XChaCha20Poly1305>,
+	pub(super) key_id: KeyId,
+	/// Only decrypt ciphers from before the current key.
+	old_ciphers: Rc<[(KeyId, XChaCha20Poly1305)]>,
+	/// Kept to wrap it again when the password changes.
+	key_ring: Rc<KeyRing>,
+	pub(super) username: Rc<str>,
 }
 
 impl Vault {
-	pub fn new(password: Password, salt: Salt) -> Self {
-		let argon2 = Argon2::default();
-		let mut key: Key = Key::default();
+	/// Unwraps the key ring stored on the server with the keys derived from the password.
+	pub fn open(username: &str, keys: &DerivedKeys, key_ring: &Cipher<KeyRing>) -> Result<Self, DecryptionError> {
+		let key_ring = keys.key_ring_vault(username).decrypt(key_ring, &())?;
+		
+		Ok(Self::from_key_ring(username, key_ring.into_revealed_secret()))
+	}
+	
+	/// Wraps the key ring with the keys derived from a new password, the secrets encrypted with it stay the same.
+	pub fn rewrap_key_ring(&self, new_keys: &DerivedKeys) -> Result<Cipher<KeyRing>, EncryptionError> {
+		new_keys.wrap_key_ring(&self.username, (*self.key_ring).clone())
+	}
+	
+	pub fn username(&self) -> &str {
+		&self.username
+	}
+	
+	fn from_key_ring(username: &str, key_ring: KeyRing) -> Self {
+		let mut ciphers = key_ring.keys.iter()
+			.map(|(key_id, key)| (*key_id, XChaCha20Poly1305::new(key.into())));
 		
-		argon2.hash_password_into(password.plain_text.as_bytes(), &salt.data, &mut key).unwrap();
+		let (key_id, cipher) = ciphers.next().expect("Key ring should not be empty");
 		
-		let cipher = XChaCha20Poly1305::new(&key);
+		let old_ciphers = ciphers.collect();
 		
 		Self {
 			cipher: Rc::new(cipher),
+			key_id,
+			old_ciphers,
+			key_ring: Rc::new(key_ring),
+			username: username.into(),
+		}
+	}
+	
+	pub(super) fn cipher_for(&self, key_id: KeyId) -> Result<&XChaCha20Poly1305, DecryptionError> {
+		if key_id == self.key_id {
+			return Ok(&self.cipher);
 		}
+		
+		self.old_ciphers.iter()
+			.find(|(id, _)| *id == key_id)
+			.map(|(_, cipher)| cipher)
+			.ok_or(DecryptionError::UnknownKey(key_id))
+	}
+	
+	/// Decrypts data from before ciphers had a header, which was encrypted with the Argon2 output directly.
+	pub(super) fn decrypt_legacy_message(&self, data: &[u8]) -> Result<Vec<u8>, DecryptionError> {
+		decrypt_message(self.cipher_for(LEGACY_KEY_ID)?, data, &[])
 	}
 	
-	pub fn encrypt<T: CipherSecret>(&self, secret: &Secret<T>) -> Result<Cipher<T>, EncryptionError> {
+	pub fn encrypt<T: CipherSecret>(&self, secret: &Secret<T>, context: &T::Context) -> Result<Cipher<T>, EncryptionError> {
 		let secret = secret.inner.as_bytes();
+		
+		if T::CHUNKED {
+			let data = self.encrypt_stream::<T, _, _>([secret], context)?
+				.collect::<Result<Vec<_>, _>>()?
+				.concat();
+			
+			return Ok(Cipher::from_bytes(data));
+		}
+		
+		let header = Header::new(Algorithm::XChaCha20Poly1305, self.key_id);
+		
 		let mut nonce = Nonce::default();
 		getrandom(&mut nonce)?;
 		
-		let ciphertext = self.cipher.encrypt(&nonce, secret.as_ref())?;
+		let ciphertext = self.cipher.encrypt(&nonce, Payload {
+			msg: secret.as_ref(),
+			aad: &header.associated_data::<T>(&self.username, context),
+		})?;
 		
-		Ok(Cipher::new(&nonce, &ciphertext))
+		Ok(Cipher::from_bytes([&header.to_bytes()[..], &nonce, &ciphertext].concat()))
 	}
 	
-	pub fn decrypt<T: CipherSecret>(&self, cipher: &Cipher<T>) -> Result<Secret<T>, DecryptionError> {
-		let secret = self.cipher.decrypt(&cipher.nonce(), cipher.ciphertext())?;
+	pub fn decrypt<T: CipherSecret>(&self, cipher: &Cipher<T>, context: &T::Context) -> Result<Secret<T>, DecryptionError> {
+		let secret = self.decrypt_data::<T>(cipher.as_bytes(), context)?;
 		
 		Ok(Secret {
 			inner: T::from_bytes(secret)?,
 		})
 	}
+	
+	fn decrypt_data<T: CipherSecret>(&self, data: &[u8], context: &T::Context) -> Result<Vec<u8>, DecryptionError> {
+		// only data without a header is legacy, files were a single message before chunked encryption as well
+		let Some(header) = Header::parse(data)? else {
+			return self.decrypt_legacy_message(data);
+		};
+		
+		let cipher = self.cipher_for(header.key_id)?;
+		
+		match header.algorithm {
+			Algorithm::XChaCha20Poly1305 => {
+				let aad = header.associated_data::<T>(&self.username, context);
+				decrypt_message(cipher, &data[HEADER_SIZE..], &aad)
+			},
+			Algorithm::XChaCha20Poly1305Stream => {
+				let mut decryptor = self.decrypt_stream::<T>(context);
+				let mut plain_text = decryptor.push(data)?;
+				plain_text.extend(decryptor.finish()?);
+				Ok(plain_text)
+			},
+		}
+	}
+}
+
+/// Decrypts a single message preceded by its nonce.
+fn decrypt_message(cipher: &XChaCha20Poly1305, data: &[u8], aad: &[u8]) -> Result<Vec<u8>, DecryptionError> {
+	if data.len() < std::mem::size_of::<Nonce>() {
+		return Err(DecryptionError::UnexpectedEndOfBytes);
+	}
+	
+	let (nonce, msg) = data.split_at(std::mem::size_of::<Nonce>());
+	
+	Ok(cipher.decrypt(Nonce::from_slice(nonce), Payload {
+		msg,
+		aad,
+	})?)
+}
+
+#[cfg(test)]
+mod tests {
+	use crate::vault::test_fixtures::*;
+	
+	use super::*;
+	
+	#[test]
+	fn encrypts_with_master_key() {
+		let keys = keys("password");
+		let key_ring = keys.new_key_ring("user", false).unwrap();
+		let vault = Vault::open("user", &keys, &key_ring).unwrap();
+		
+		let cipher = vault.encrypt(&folder_name("folder"), &folder(1)).unwrap();
+		assert_eq!(Header::parse(cipher.as_bytes()).unwrap().unwrap().key_id, MASTER_KEY_ID);
+		assert_eq!(vault.decrypt(&cipher, &folder(1)).unwrap().reveal_secret().name, "folder");
+		
+		let other_key_ring = keys.new_key_ring("user", false).unwrap();
+		assert!(Vault::open("user", &keys, &other_key_ring).unwrap().decrypt(&cipher, &folder(1)).is_err());
+	}
+	
+	#[test]
+	fn key_ring_needs_password_and_username() {
+		let keys = keys("password");
+		let key_ring = keys.new_key_ring("user", false).unwrap();
+		
+		assert!(Vault::open("user", &keys, &key_ring).is_ok());
+		assert!(Vault::open("user", &self::keys("other password"), &key_ring).is_err());
+		assert!(Vault::open("other user", &keys, &key_ring).is_err());
+	}
+	
+	#[test]
+	fn rewraps_key_ring_for_new_password() {
+		let keys = keys("password");
+		let vault = Vault::open("user", &keys, &keys.new_key_ring("user", false).unwrap()).unwrap();
+		let cipher = vault.encrypt(&folder_name("folder"), &folder(1)).unwrap();
+		
+		let new_keys = self::keys("new password");
+		let key_ring = vault.rewrap_key_ring(&new_keys).unwrap();
+		
+		assert!(Vault::open("user", &keys, &key_ring).is_err());
+		let vault = Vault::open("user", &new_keys, &key_ring).unwrap();
+		assert_eq!(vault.decrypt(&cipher, &folder(1)).unwrap().reveal_secret().name, "folder");
+	}
+	
+	#[test]
+	fn derives_keys_with_stored_parameters() {
+		let password = Password::new("password".to_owned());
+		let salt = Salt { data: [0; 32] };
+		let kdf = KdfParameters {
+			memory_cost: 8 * 1024,
+			..KdfParameters::default()
+		};
+		
+		let keys = password.derive_keys(&salt, &kdf).unwrap();
+		assert_eq!(keys.auth_key.data, password.derive_keys(&salt, &kdf).unwrap().auth_key.data);
+		assert_ne!(keys.auth_key.data, self::keys("password").auth_key.data);
+		
+		assert!(password.derive_keys(&salt, &KdfParameters { parallelism: 0, ..kdf }).is_err());
+	}
+	
+	#[test]
+	fn compares_kdf_strength() {
+		let kdf = KdfParameters::default();
+		
+		assert!(!kdf.is_stronger_than(&kdf));
+		assert!(KdfParameters { memory_cost: kdf.memory_cost * 2, ..kdf }.is_stronger_than(&kdf));
+		assert!(KdfParameters { time_cost: kdf.time_cost + 1, parallelism: kdf.parallelism + 1, ..kdf }.is_stronger_than(&kdf));
+		assert!(!KdfParameters { memory_cost: kdf.memory_cost * 2, time_cost: kdf.time_cost - 1, ..kdf }.is_stronger_than(&kdf));
+	}
+	
+	#[test]
+	fn recovery_code_opens_key_ring() {
+		let keys = keys("password");
+		let vault = Vault::open("user", &keys, &keys.new_key_ring("user", false).unwrap()).unwrap();
+		let cipher = vault.encrypt(&folder_name("folder"), &folder(1)).unwrap();
+		
+		let code = RecoveryCode::generate().unwrap();
+		let recovery_key_ring = vault.rewrap_key_ring(&code.derive_keys()).unwrap();
+		
+		let code = RecoveryCode::parse(&code.to_string().to_lowercase().replace('-', " ")).unwrap();
+		let vault = Vault::open("user", &code.derive_keys(), &recovery_key_ring).unwrap();
+		assert_eq!(vault.decrypt(&cipher, &folder(1)).unwrap().reveal_secret().name, "folder");
+		
+		assert!(Vault::open("user", &RecoveryCode::generate().unwrap().derive_keys(), &recovery_key_ring).is_err());
+	}
+	
+	#[test]
+	fn parses_recovery_codes() {
+		let code = RecoveryCode {
+			data: [0xab; RECOVERY_CODE_SIZE],
+		};
+		
+		assert_eq!(code.to_string(), "ABABA-BABAB-ABABA-BABAB-ABABA-BABAB-ABABA-BABAB");
+		assert_eq!(RecoveryCode::parse(&code.to_string()).unwrap().data, code.data);
+		assert!(RecoveryCode::parse("ABABA-BABAB").is_none());
+		assert!(RecoveryCode::parse("not a code").is_none());
+	}
+	
+	#[test]
+	fn keeps_password_keys_for_older_data() {
+		let keys = keys("password");
+		let old_cipher = keys.key_ring_vault("user").encrypt(&folder_name("folder"), &folder(1)).unwrap();
+		
+		let vault = Vault::open("user", &keys, &keys.new_key_ring("user", true).unwrap()).unwrap();
+		assert_eq!(vault.decrypt(&old_cipher, &folder(1)).unwrap().reveal_secret().name, "folder");
+		
+		let vault = Vault::open("user", &keys, &keys.new_key_ring("user", false).unwrap()).unwrap();
+		assert!(matches!(vault.decrypt(&old_cipher, &folder(1)), Err(DecryptionError::UnknownKey(DERIVED_KEY_ID))));
+	}
 }
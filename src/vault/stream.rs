@@ -0,0 +1,348 @@
+use chacha20poly1305::{aead::{stream::{DecryptorBE32, EncryptorBE32}, Payload}, XChaCha20Poly1305};
+use generic_array::GenericArray;
+use getrandom::getrandom;
+
+use std::marker::PhantomData;
+
+use crate::files::FileId;
+
+use super::*;
+use super::envelope::{Algorithm, Header, HEADER_SIZE};
+
+/// Size of the plain text chunks that large secrets are encrypted in.
+const CHUNK_SIZE: usize = 64 * 1024;
+const TAG_SIZE: usize = 16;
+const ENCRYPTED_CHUNK_SIZE: usize = CHUNK_SIZE + TAG_SIZE;
+/// The nonce of each chunk is this prefix followed by a 4 byte counter and a 1 byte flag marking the last chunk.
+const NONCE_PREFIX_SIZE: usize = 19;
+
+type NoncePrefix = GenericArray<u8, generic_array::typenum::U19>;
+
+/// Splits bytes coming in arbitrarily sized pieces into chunks of the requested sizes.
+struct Rechunk<I> {
+	pieces: I,
+	buffer: Vec<u8>,
+	start: usize,
+}
+
+impl<I, T> Rechunk<I>
+where
+	I: Iterator<Item = T>,
+	T: AsRef<[u8]>,
+{
+	fn new(pieces: I) -> Self {
+		Self {
+			pieces,
+			buffer: Vec::new(),
+			start: 0,
+		}
+	}
+	
+	/// Returns the next chunk and whether it is the last one,
+	/// only the last chunk can be shorter than `size`.
+	fn next_chunk(&mut self, size: usize) -> (&[u8], bool) {
+		while self.buffer.len() - self.start <= size {
+			let Some(piece) = self.pieces.next() else {
+				let start = std::mem::replace(&mut self.start, self.buffer.len());
+				return (&self.buffer[start..], true);
+			};
+			
+			// at most `size` bytes are left, so this never moves much data
+			self.buffer.drain(..self.start);
+			self.start = 0;
+			self.buffer.extend_from_slice(piece.as_ref());
+		}
+		
+		self.start += size;
+		(&self.buffer[self.start - size..self.start], false)
+	}
+}
+
+struct EncryptChunks<I> {
+	chunks: Rechunk<I>,
+	header: [u8; HEADER_SIZE],
+	aad: Vec<u8>,
+	nonce_prefix: Option<NoncePrefix>,
+	encryptor: Option<EncryptorBE32<XChaCha20Poly1305>>,
+}
+
+impl<I, T> Iterator for EncryptChunks<I>
+where
+	I: Iterator<Item = T>,
+	T: AsRef<[u8]>,
+{
+	type Item = Result<Vec<u8>, EncryptionError>;
+	
+	fn next(&mut self) -> Option<Self::Item> {
+		if let Some(nonce_prefix) = self.nonce_prefix.take() {
+			return Some(Ok([&self.header[..], &nonce_prefix].concat()));
+		}
+		
+		let mut encryptor = self.encryptor.take()?;
+		let aad = &self.aad[..];
+		
+		let result = match self.chunks.next_chunk(CHUNK_SIZE) {
+			(msg, true) => encryptor.encrypt_last(Payload { msg, aad }),
+			(msg, false) => {
+				let result = encryptor.encrypt_next(Payload { msg, aad });
+				self.encryptor = Some(encryptor);
+				result
+			},
+		};
+		
+		if result.is_err() {
+			self.encryptor = None;
+		}
+		
+		Some(result.map_err(Into::into))
+	}
+}
+
+/// Decrypts a STREAM while its cipher text arrives in pieces of any size.
+/// Fails if chunks are missing, reordered or the cipher text ends before the last chunk.
+pub struct StreamDecryptor<S: CipherSecret> {
+	vault: Vault,
+	context: S::Context,
+	/// Cipher text that hasn't been decrypted yet.
+	buffer: Vec<u8>,
+	state: DecryptorState,
+	_phantom_data: PhantomData<S>,
+}
+
+enum DecryptorState {
+	/// Waiting for the header and the nonce prefix.
+	Start,
+	/// Legacy data without a header is a single message, which can only be decrypted once it is complete.
+	Legacy,
+	Chunks {
+		decryptor: DecryptorBE32<XChaCha20Poly1305>,
+		aad: Vec<u8>,
+	},
+	/// Decrypting failed, nothing after that can be trusted.
+	Failed,
+}
+
+impl<S: CipherSecret> StreamDecryptor<S> {
+	/// Returns the plain text of the chunks that are complete and known not to be the last one.
+	pub fn push(&mut self, piece: &[u8]) -> Result<Vec<u8>, DecryptionError> {
+		self.buffer.extend_from_slice(piece);
+		
+		let result = self.decrypt_complete_chunks();
+		
+		if result.is_err() {
+			self.state = DecryptorState::Failed;
+		}
+		
+		result
+	}
+	
+	/// Returns the plain text of the rest of the cipher text, which has to end with the last chunk.
+	pub fn finish(mut self) -> Result<Vec<u8>, DecryptionError> {
+		if let DecryptorState::Start = self.state {
+			self.start()?;
+		}
+		
+		match self.state {
+			DecryptorState::Start => unreachable!("Decryption should have started"),
+			DecryptorState::Legacy => self.vault.decrypt_legacy_message(&self.buffer),
+			DecryptorState::Chunks { decryptor, aad } => Ok(decryptor.decrypt_last(Payload {
+				msg: &self.buffer,
+				aad: &aad,
+			})?),
+			DecryptorState::Failed => Err(chacha20poly1305::Error.into()),
+		}
+	}
+	
+	fn decrypt_complete_chunks(&mut self) -> Result<Vec<u8>, DecryptionError> {
+		if let DecryptorState::Start = self.state {
+			if self.buffer.len() < HEADER_SIZE + NONCE_PREFIX_SIZE {
+				return Ok(Vec::new());
+			}
+			
+			self.start()?;
+		}
+		
+		let DecryptorState::Chunks { decryptor, aad } = &mut self.state else {
+			return match self.state {
+				DecryptorState::Failed => Err(chacha20poly1305::Error.into()),
+				_ => Ok(Vec::new()),
+			};
+		};
+		
+		let mut plain_text = Vec::new();
+		let mut start = 0;
+		
+		// a chunk can only be decrypted once more data follows it, otherwise it might be the last one
+		while self.buffer.len() - start > ENCRYPTED_CHUNK_SIZE {
+			let msg = &self.buffer[start..start + ENCRYPTED_CHUNK_SIZE];
+			plain_text.extend(decryptor.decrypt_next(Payload { msg, aad })?);
+			start += ENCRYPTED_CHUNK_SIZE;
+		}
+		
+		self.buffer.drain(..start);
+		
+		Ok(plain_text)
+	}
+	
+	/// Reads the header and nonce prefix, all of which has to be buffered already unless the data is legacy.
+	fn start(&mut self) -> Result<(), DecryptionError> {
+		let Some(header) = Header::parse(&self.buffer)? else {
+			self.state = DecryptorState::Legacy;
+			return Ok(());
+		};
+		
+		if header.algorithm != Algorithm::XChaCha20Poly1305Stream {
+			return Err(DecryptionError::UnsupportedAlgorithm(header.algorithm as u8));
+		}
+		
+		let nonce_prefix = self.buffer.get(HEADER_SIZE..HEADER_SIZE + NONCE_PREFIX_SIZE)
+			.ok_or(DecryptionError::UnexpectedEndOfBytes)?;
+		
+		self.state = DecryptorState::Chunks {
+			decryptor: DecryptorBE32::from_aead(self.vault.cipher_for(header.key_id)?.clone(), NoncePrefix::from_slice(nonce_prefix)),
+			aad: header.associated_data::<S>(&self.vault.username, &self.context),
+		};
+		
+		self.buffer.drain(..HEADER_SIZE + NONCE_PREFIX_SIZE);
+		
+		Ok(())
+	}
+}
+
+impl Vault {
+	/// Encrypts the content of a file coming in pieces of any size as a STREAM of chunks.
+	/// Concatenating the returned pieces gives the complete cipher text.
+	pub fn encrypt_chunks<I, T>(&self, file: FileId, pieces: I) -> Result<impl Iterator<Item = Result<Vec<u8>, EncryptionError>>, EncryptionError>
+	where
+		I: IntoIterator<Item = T>,
+		T: AsRef<[u8]>,
+	{
+		self.encrypt_stream::<FileContent, _, _>(pieces, &file)
+	}
+	
+	/// Decrypts the content of a file while it is being downloaded.
+	/// Also accepts legacy cipher text without a header, which is only decrypted once it is complete.
+	pub fn decrypt_chunks(&self, file: FileId) -> StreamDecryptor<FileContent> {
+		self.decrypt_stream::<FileContent>(&file)
+	}
+	
+	pub(super) fn encrypt_stream<S, I, T>(&self, pieces: I, context: &S::Context) -> Result<impl Iterator<Item = Result<Vec<u8>, EncryptionError>>, EncryptionError>
+	where
+		S: CipherSecret,
+		I: IntoIterator<Item = T>,
+		T: AsRef<[u8]>,
+	{
+		let mut nonce_prefix = NoncePrefix::default();
+		getrandom(&mut nonce_prefix)?;
+		
+		let header = Header::new(Algorithm::XChaCha20Poly1305Stream, self.key_id);
+		
+		Ok(EncryptChunks {
+			chunks: Rechunk::new(pieces.into_iter()),
+			header: header.to_bytes(),
+			aad: header.associated_data::<S>(&self.username, context),
+			nonce_prefix: Some(nonce_prefix),
+			encryptor: Some(EncryptorBE32::from_aead((*self.cipher).clone(), &nonce_prefix)),
+		})
+	}
+	
+	pub(super) fn decrypt_stream<S: CipherSecret>(&self, context: &S::Context) -> StreamDecryptor<S> {
+		StreamDecryptor {
+			vault: self.clone(),
+			context: context.clone(),
+			buffer: Vec::new(),
+			state: DecryptorState::Start,
+			_phantom_data: PhantomData,
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use crate::vault::test_fixtures::*;
+	
+	use super::*;
+	
+	fn encrypt(vault: &Vault, data: &[u8], piece_size: usize) -> Vec<u8> {
+		vault.encrypt_chunks(file(1), data.chunks(piece_size)).unwrap()
+			.collect::<Result<Vec<_>, _>>().unwrap()
+			.concat()
+	}
+	
+	fn decrypt(vault: &Vault, cipher_text: &[u8], piece_size: usize) -> Result<Vec<u8>, DecryptionError> {
+		let mut decryptor = vault.decrypt_chunks(file(1));
+		let mut plain_text = Vec::new();
+		
+		for piece in cipher_text.chunks(piece_size) {
+			plain_text.extend(decryptor.push(piece)?);
+		}
+		
+		plain_text.extend(decryptor.finish()?);
+		
+		Ok(plain_text)
+	}
+	
+	fn test_data(len: usize) -> Vec<u8> {
+		(0..len).map(|i| i as u8).collect()
+	}
+	
+	#[test]
+	fn round_trips_any_size() {
+		let vault = vault("user");
+		
+		for len in [0, 1, CHUNK_SIZE - 1, CHUNK_SIZE, CHUNK_SIZE + 1, 3 * CHUNK_SIZE] {
+			let data = test_data(len);
+			let cipher_text = encrypt(&vault, &data, 1000);
+			
+			assert_eq!(cipher_text.len(), HEADER_SIZE + NONCE_PREFIX_SIZE + len + len.div_ceil(CHUNK_SIZE).max(1) * TAG_SIZE);
+			assert_eq!(decrypt(&vault, &cipher_text, 777).unwrap(), data, "length {len}");
+			assert_eq!(decrypt(&vault, &cipher_text, usize::MAX).unwrap(), data, "length {len}");
+		}
+	}
+	
+	#[test]
+	fn decrypts_chunks_as_they_arrive() {
+		let vault = vault("user");
+		let data = test_data(3 * CHUNK_SIZE);
+		let cipher_text = encrypt(&vault, &data, CHUNK_SIZE);
+		let (received, rest) = cipher_text.split_at(HEADER_SIZE + NONCE_PREFIX_SIZE + 2 * ENCRYPTED_CHUNK_SIZE);
+		
+		let mut decryptor = vault.decrypt_chunks(file(1));
+		assert_eq!(decryptor.push(received).unwrap(), data[..CHUNK_SIZE]);
+		assert_eq!(decryptor.push(&rest[..1]).unwrap(), data[CHUNK_SIZE..2 * CHUNK_SIZE]);
+		assert!(decryptor.push(&rest[1..]).unwrap().is_empty());
+		assert_eq!(decryptor.finish().unwrap(), data[2 * CHUNK_SIZE..]);
+	}
+	
+	#[test]
+	fn rejects_truncation() {
+		let vault = vault("user");
+		let cipher_text = encrypt(&vault, &test_data(3 * CHUNK_SIZE + 10), CHUNK_SIZE);
+		
+		for len in [0, HEADER_SIZE, HEADER_SIZE + NONCE_PREFIX_SIZE, HEADER_SIZE + NONCE_PREFIX_SIZE + ENCRYPTED_CHUNK_SIZE, cipher_text.len() - 1] {
+			assert!(decrypt(&vault, &cipher_text[..len], CHUNK_SIZE).is_err(), "length {len}");
+		}
+	}
+	
+	#[test]
+	fn rejects_reordering() {
+		let vault = vault("user");
+		let cipher_text = encrypt(&vault, &test_data(3 * CHUNK_SIZE), CHUNK_SIZE);
+		
+		let (nonce_prefix, chunks) = cipher_text.split_at(HEADER_SIZE + NONCE_PREFIX_SIZE);
+		let mut chunks: Vec<_> = chunks.chunks(ENCRYPTED_CHUNK_SIZE).collect();
+		chunks.swap(0, 1);
+		let reordered = [&[nonce_prefix], &chunks[..]].concat().concat();
+		
+		assert!(decrypt(&vault, &reordered, CHUNK_SIZE).is_err());
+	}
+	
+	#[test]
+	fn rejects_extension() {
+		let vault = vault("user");
+		let mut cipher_text = encrypt(&vault, &test_data(CHUNK_SIZE), CHUNK_SIZE);
+		cipher_text.extend_from_slice(&[0; TAG_SIZE]);
+		
+		assert!(decrypt(&vault, &cipher_text, CHUNK_SIZE).is_err());
+	}
+}
@@ -1,5 +1,7 @@
 use super::*;
 
+use crate::files::{FileId, FolderId};
+
 #[derive(Clone)]
 pub struct FolderName {
 	pub name: String,
@@ -8,6 +10,10 @@ pub struct FolderName {
 impl Sealed for FolderName {}
 
 impl CipherSecret for FolderName {
+	const TAG: u8 = 1;
+	
+	type Context = FolderId;
+	
 	fn as_bytes(&self) -> impl AsRef<[u8]> {
 		self.name.as_bytes()
 	}
@@ -28,6 +34,10 @@ pub struct FileInfo {
 impl Sealed for FileInfo {}
 
 impl CipherSecret for FileInfo {
+	const TAG: u8 = 2;
+	
+	type Context = (FolderId, FileId);
+	
 	fn as_bytes(&self) -> impl AsRef<[u8]> {
 		let mut bytes = Vec::with_capacity(
 			std::mem::size_of::<usize>()
@@ -73,6 +83,11 @@ pub struct FileContent {
 impl Sealed for FileContent {}
 
 impl CipherSecret for FileContent {
+	const TAG: u8 = 3;
+	const CHUNKED: bool = true;
+	
+	type Context = FileId;
+	
 	fn as_bytes(&self) -> impl AsRef<[u8]> {
 		&self.data
 	}
@@ -83,3 +98,54 @@ impl CipherSecret for FileContent {
 		})
 	}
 }
+
+/// The keys that encrypt all other secrets of a user, stored on the server wrapped with the key derived from their password.
+/// The first key encrypts new secrets, the others are only kept to decrypt older ones.
+#[derive(Clone)]
+pub struct KeyRing {
+	// only read for encryption, which happens in the browser
+	#[cfg_attr(not(feature = "hydrate"), allow(dead_code))]
+	pub(super) keys: Vec<(u16, [u8; KEY_SIZE])>,
+}
+
+const KEY_SIZE: usize = 32;
+const KEY_ENTRY_SIZE: usize = std::mem::size_of::<u16>() + KEY_SIZE;
+
+impl Sealed for KeyRing {}
+
+impl CipherSecret for KeyRing {
+	const TAG: u8 = 4;
+	
+	type Context = ();
+	
+	fn as_bytes(&self) -> impl AsRef<[u8]> {
+		let mut bytes = Vec::with_capacity(self.keys.len() * KEY_ENTRY_SIZE);
+		
+		for (key_id, key) in &self.keys {
+			bytes.extend_from_slice(&key_id.to_be_bytes());
+			bytes.extend_from_slice(key);
+		}
+		
+		bytes
+	}
+	
+	fn from_bytes(bytes: Vec<u8>) -> Result<Self, DecryptionError> {
+		if bytes.is_empty() || bytes.len() % KEY_ENTRY_SIZE != 0 {
+			return Err(DecryptionError::UnexpectedEndOfBytes);
+		}
+		
+		let keys = bytes.chunks_exact(KEY_ENTRY_SIZE)
+			.map(|entry| {
+				let (key_id, key) = entry.split_at(std::mem::size_of::<u16>());
+				(
+					u16::from_be_bytes(key_id.try_into().expect("Key id should be 2 bytes")),
+					key.try_into().expect("Key should be KEY_SIZE bytes"),
+				)
+			})
+			.collect();
+		
+		Ok(Self {
+			keys,
+		})
+	}
+}
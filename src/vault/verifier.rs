@@ -0,0 +1,198 @@
+use argon2::{password_hash::{self, PasswordHasher, PasswordVerifier, SaltString}, Algorithm, Argon2, KeyId, Params, ParamsBuilder, Version};
+use getrandom::getrandom;
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+use subtle::ConstantTimeEq;
+
+use super::*;
+
+/// Stored in the `keyid` parameter of hashes made with the pepper,
+/// hashes without it were made before there was a pepper.
+const PEPPER_KEY_ID: &[u8] = b"pepper1";
+
+/// Separates the HMAC of fake salts from other uses of the pepper.
+const FAKE_SALT_CONTEXT: &[u8] = b"vault fake salt\0";
+/// Separates the HMAC picking the user unknown users pose as from other uses of the pepper.
+const DECOY_CONTEXT: &[u8] = b"vault decoy\0";
+/// Separates the HMAC of backup codes from other uses of the pepper.
+const BACKUP_CODE_CONTEXT: &[u8] = b"vault backup code\0";
+
+/// Argon2id hash of an [AuthKey] in PHC string format, this is all the server stores to check logins.
+#[derive(Clone)]
+pub struct AuthKeyHash {
+	pub(super) phc: String,
+}
+
+/// The login credential stored for a user.
+#[derive(Clone, Debug)]
+pub enum Verifier {
+	/// Accounts that have not logged in since [AuthKey] was introduced.
+	Legacy(PasswordHash),
+	AuthKey(AuthKeyHash),
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Verification {
+	Incorrect,
+	Correct,
+	/// The credentials are correct, but the stored verifier should be replaced with a new hash.
+	Outdated,
+}
+
+/// Server secret that is mixed into every [AuthKeyHash], so a leaked database alone is not enough to check guesses.
+#[derive(Clone)]
+pub struct Pepper {
+	key: [u8; 32],
+}
+
+impl Pepper {
+	pub fn new(key: [u8; 32]) -> Self {
+		Self {
+			key,
+		}
+	}
+	
+	fn argon2(&self) -> Argon2<'_> {
+		let params = ParamsBuilder::new()
+			.keyid(KeyId::new(PEPPER_KEY_ID).expect("Key id should not be too long"))
+			.build()
+			.expect("Default parameters should be valid");
+		
+		Argon2::new_with_secret(&self.key, Algorithm::Argon2id, Version::V0x13, params).expect("Pepper should not be too long")
+	}
+	
+	/// Slow to compute, should not be called on an async executor thread.
+	pub fn hash(&self, auth_key: &AuthKey) -> Result<AuthKeyHash, getrandom::Error> {
+		let mut salt = [0; password_hash::Salt::RECOMMENDED_LENGTH];
+		getrandom(&mut salt)?;
+		
+		let salt = SaltString::encode_b64(&salt).expect("Salt should have a valid length");
+		let hash = self.argon2().hash_password(&auth_key.data, &salt).expect("Hashing with valid parameters should not fail");
+		
+		Ok(AuthKeyHash {
+			phc: hash.to_string(),
+		})
+	}
+	
+	/// Salt to send for a user that doesn't exist, it is the same every time and looks as random as a real one,
+	/// so it doesn't tell whether the user exists.
+	pub fn fake_salt(&self, username: &str) -> Salt {
+		let mut hmac = Hmac::<Sha256>::new_from_slice(&self.key).expect("HMAC takes keys of any size");
+		hmac.update(FAKE_SALT_CONTEXT);
+		hmac.update(username.as_bytes());
+		
+		Salt {
+			data: hmac.finalize().into_bytes().into(),
+		}
+	}
+	
+	/// Picks which existing user an unknown user poses as, stays the same for a username like [Pepper::fake_salt].
+	pub fn decoy_index(&self, username: &str) -> u32 {
+		let mut hmac = Hmac::<Sha256>::new_from_slice(&self.key).expect("HMAC takes keys of any size");
+		hmac.update(DECOY_CONTEXT);
+		hmac.update(username.as_bytes());
+		
+		let digest = hmac.finalize().into_bytes();
+		u32::from_le_bytes([digest[0], digest[1], digest[2], digest[3]])
+	}
+	
+	/// Hash a backup code of two-factor authentication is stored as. Backup codes are random,
+	/// so a fast keyed hash is enough and lets them be looked up directly.
+	pub fn backup_code_hash(&self, code: &str) -> [u8; 32] {
+		let mut hmac = Hmac::<Sha256>::new_from_slice(&self.key).expect("HMAC takes keys of any size");
+		hmac.update(BACKUP_CODE_CONTEXT);
+		hmac.update(code.as_bytes());
+		
+		hmac.finalize().into_bytes().into()
+	}
+	
+	/// Checks the credentials sent by a client against the stored verifier, `legacy_hash` is only needed for [Verifier::Legacy].
+	/// Slow to compute, should not be called on an async executor thread.
+	pub fn verify(&self, verifier: &Verifier, auth_key: &AuthKey, legacy_hash: Option<&PasswordHash>) -> Verification {
+		let correct = match verifier {
+			Verifier::Legacy(correct_hash) => {
+				let correct = legacy_hash.is_some_and(|hash| bool::from(hash.data.ct_eq(&correct_hash.data)));
+				
+				return if correct { Verification::Outdated } else { Verification::Incorrect };
+			},
+			Verifier::AuthKey(hash) => hash,
+		};
+		
+		let Ok(hash) = password_hash::PasswordHash::new(&correct.phc) else {
+			eprintln!("Invalid auth key hash in database");
+			return Verification::Incorrect;
+		};
+		
+		let peppered = Params::try_from(&hash).is_ok_and(|params| !params.keyid().is_empty());
+		
+		let verified = if peppered {
+			self.argon2().verify_password(&auth_key.data, &hash)
+		} else {
+			Argon2::default().verify_password(&auth_key.data, &hash)
+		};
+		
+		match (verified, peppered) {
+			(Err(_), _) => Verification::Incorrect,
+			(Ok(()), true) => Verification::Correct,
+			(Ok(()), false) => Verification::Outdated,
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	
+	fn auth_key(byte: u8) -> AuthKey {
+		AuthKey {
+			data: [byte; 32],
+		}
+	}
+	
+	fn legacy_hash(byte: u8) -> PasswordHash {
+		PasswordHash {
+			data: [byte; 64],
+		}
+	}
+	
+	#[test]
+	fn verifies_auth_keys() {
+		let pepper = Pepper::new([1; 32]);
+		let verifier = Verifier::AuthKey(pepper.hash(&auth_key(1)).unwrap());
+		
+		assert_eq!(pepper.verify(&verifier, &auth_key(1), None), Verification::Correct);
+		assert_eq!(pepper.verify(&verifier, &auth_key(2), None), Verification::Incorrect);
+		assert_eq!(Pepper::new([2; 32]).verify(&verifier, &auth_key(1), None), Verification::Incorrect);
+	}
+	
+	#[test]
+	fn upgrades_hashes_without_pepper() {
+		let pepper = Pepper::new([1; 32]);
+		let salt = SaltString::encode_b64(&[0; 16]).unwrap();
+		let verifier = Verifier::AuthKey(AuthKeyHash {
+			phc: Argon2::default().hash_password(&auth_key(1).data, &salt).unwrap().to_string(),
+		});
+		
+		assert_eq!(pepper.verify(&verifier, &auth_key(1), None), Verification::Outdated);
+		assert_eq!(pepper.verify(&verifier, &auth_key(2), None), Verification::Incorrect);
+	}
+	
+	#[test]
+	fn upgrades_legacy_hashes() {
+		let pepper = Pepper::new([1; 32]);
+		let verifier = Verifier::Legacy(legacy_hash(1));
+		
+		assert_eq!(pepper.verify(&verifier, &auth_key(1), Some(&legacy_hash(1))), Verification::Outdated);
+		assert_eq!(pepper.verify(&verifier, &auth_key(1), Some(&legacy_hash(2))), Verification::Incorrect);
+		assert_eq!(pepper.verify(&verifier, &auth_key(1), None), Verification::Incorrect);
+	}
+	
+	#[test]
+	fn fake_salts_are_deterministic() {
+		let pepper = Pepper::new([1; 32]);
+		
+		assert_eq!(pepper.fake_salt("alice").data, pepper.fake_salt("alice").data);
+		assert_ne!(pepper.fake_salt("alice").data, pepper.fake_salt("bob").data);
+		assert_ne!(pepper.fake_salt("alice").data, Pepper::new([2; 32]).fake_salt("alice").data);
+	}
+}
@@ -12,8 +12,18 @@
 , lightningcss
 , port ? null
 , authKey ? null
+, pepper ? null
 , dbFile ? null
 , filesLocation ? null
+, trashRetentionDays ? null
+, sessionLifetimeHours ? null
+, trustProxy ? null
+, registration ? null
+, admins ? null
+, maxUploadSize ? null
+, kdfMemoryCost ? null
+, kdfTimeCost ? null
+, kdfParallelism ? null
 }:
 
 let
@@ -52,8 +62,18 @@ let
 		cargoExtraArgs = "--locked --bins --features=ssr";
 		VAULT_PORT = port;
 		VAULT_AUTH_KEY = authKey;
+		VAULT_PEPPER = pepper;
 		VAULT_DB_FILE = dbFile;
 		VAULT_FILES_LOCATION = filesLocation;
+		VAULT_TRASH_RETENTION_DAYS = trashRetentionDays;
+		VAULT_SESSION_LIFETIME_HOURS = sessionLifetimeHours;
+		VAULT_TRUST_PROXY = trustProxy;
+		VAULT_REGISTRATION = registration;
+		VAULT_ADMINS = admins;
+		VAULT_MAX_UPLOAD_SIZE = maxUploadSize;
+		VAULT_KDF_MEMORY_COST = kdfMemoryCost;
+		VAULT_KDF_TIME_COST = kdfTimeCost;
+		VAULT_KDF_PARALLELISM = kdfParallelism;
 	});
 	
 	wasm = craneLib.buildPackage (commonArgs // {